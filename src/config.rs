@@ -0,0 +1,94 @@
+use anyhow::{anyhow, bail};
+use rosc::OscType;
+use std::sync::Arc;
+
+use crate::{
+    json::{self, Json},
+    osc::command_handling,
+    Sequencer,
+};
+
+/// Initial state of the sequencer, loaded from a JSON file on startup, as
+/// `{ "bpm": 120.0, "connect_to": ["synth:midi_in"], "commands": [{ "addr": ..., "args": [...] }] }`.
+/// The commands are given as for the http endpoint and go through the OSC dispatch, so that
+/// the base seqs are set up as they would be by a controller. All the fields are optional.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub bpm: Option<f32>,
+    /// Jack ports to which the midi output is connected, in addition to the --connect-to one
+    pub connect_to: Vec<String>,
+    pub commands: Vec<(String, Vec<OscType>)>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> anyhow::Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read the config file {path}: {e}"))?;
+        Config::parse(&text).map_err(|e| anyhow!("Invalid config file {path}: {e}"))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Config> {
+        let Json::Object(fields) = json::parse(text)? else {
+            bail!("The config should be a JSON object.");
+        };
+        let mut config = Config::default();
+        for (key, value) in fields {
+            match (key.as_str(), value) {
+                ("bpm", Json::Float(bpm)) => config.bpm = Some(bpm),
+                ("bpm", Json::Int(bpm)) => config.bpm = Some(bpm as f32),
+                ("connect_to", Json::Array(ports)) => {
+                    config.connect_to = ports
+                        .into_iter()
+                        .map(|port| match port {
+                            Json::String(port) => Ok(port),
+                            _ => bail!("The ports to connect to should be strings."),
+                        })
+                        .collect::<anyhow::Result<_>>()?;
+                }
+                ("commands", Json::Array(commands)) => {
+                    config.commands = commands
+                        .into_iter()
+                        .map(Json::into_command)
+                        .collect::<anyhow::Result<_>>()?;
+                }
+                (key, _) => bail!("Unexpected config field {key}."),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Set up the sequencer, stopping at the first command failing
+    pub fn apply(&self, seq: &Arc<Sequencer>) -> anyhow::Result<()> {
+        if let Some(bpm) = self.bpm {
+            seq.set_bpm(bpm)?;
+        }
+        for (idx, (addr, args)) in self.commands.iter().enumerate() {
+            command_handling(addr, args, seq)
+                .map_err(|e| anyhow!("Config command nb {idx} {addr} failed: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_config() {
+    let config = Config::parse(
+        r#"{
+            "bpm": 90,
+            "connect_to": ["synth:midi_in"],
+            "commands": [
+                { "addr": "/gisele/add_euclid_base", "args": [4.0, 60, 4, 4, 0.5, 0.0, 100, 0.0, 1] }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.connect_to, vec!["synth:midi_in".to_string()]);
+    let seq = Arc::new(Sequencer::new(120.));
+    config.apply(&seq).unwrap();
+    assert_eq!(seq.params.read().bpm, 90.);
+    assert_eq!(seq.base_seqs.read().len(), 1);
+
+    assert!(Config::parse(r#"{ "bmp": 90 }"#).is_err());
+    let config = Config::parse(r#"{ "commands": [{ "addr": "/gisele/unknown" }] }"#).unwrap();
+    assert!(config.apply(&seq).is_err());
+}
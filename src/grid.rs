@@ -0,0 +1,141 @@
+use rosc::{OscMessage, OscPacket, OscType};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use crate::osc::send_reply;
+use crate::seq::{SeqStatus, Sequencer};
+
+/// Period at which the grid leds are refreshed
+const GRID_PERIOD: Duration = Duration::from_millis(25);
+/// Nb of grid columns over which the loop of the focused BaseSeq is spread
+const GRID_COLS: u32 = 16;
+
+/// Led rows of the grid, one bit per column: the steps holding a note on of the focused BaseSeq
+/// on the first row, and its playhead on the second
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GridLeds {
+    pub steps: u16,
+    pub playhead: u16,
+}
+
+impl GridLeds {
+    /// Leds of the focused BaseSeq, all off without focus
+    pub fn of_focused(seq: &Sequencer) -> GridLeds {
+        let Some(base_seq_id) = seq.params.read().focused_base_seq else {
+            return GridLeds::default();
+        };
+        let Ok(base_seq) = seq.get_base_seq(base_seq_id) else {
+            return GridLeds::default();
+        };
+        let loop_len = base_seq.params.read().loop_length;
+        let column = |bar_pos: f64| {
+            let column = (bar_pos / loop_len as f64 * GRID_COLS as f64) as u32;
+            1 << column.min(GRID_COLS - 1)
+        };
+        let steps = base_seq
+            .event_buffer
+            .read()
+            .iter()
+            .filter(|e| e.is_note_on() && e.bar_pos < loop_len)
+            .fold(0, |steps, e| steps | column(e.bar_pos as f64));
+        let playhead = column(seq.internal.read().j_window_time_end % loop_len as f64);
+        GridLeds { steps, playhead }
+    }
+
+    /// Row messages of serialosc, each row being given as two bytes of 8 leds
+    fn messages(&self) -> Vec<OscPacket> {
+        [self.steps, self.playhead]
+            .iter()
+            .enumerate()
+            .map(|(y, row)| {
+                OscPacket::Message(OscMessage {
+                    addr: "/monome/grid/led/row".to_string(),
+                    args: vec![
+                        OscType::Int(0),
+                        OscType::Int(y as i32),
+                        OscType::Int((row & 0xFF) as i32),
+                        OscType::Int((row >> 8) as i32),
+                    ],
+                })
+            })
+            .collect()
+    }
+}
+
+/// Returns the loop sending the leds of the focused BaseSeq to the grid, whenever they change
+pub fn grid_process_closure(seq: Arc<Sequencer>) -> impl FnOnce() -> anyhow::Result<()> {
+    move || {
+        let udp_socket = UdpSocket::bind("0.0.0.0:0")?;
+        let mut last_sent: Option<(SocketAddr, GridLeds)> = None;
+        while seq.params.read().status != SeqStatus::Shutdown {
+            thread::sleep(GRID_PERIOD);
+            let target = seq.params.read().grid_feedback;
+            match target {
+                Some(target) => {
+                    let leds = GridLeds::of_focused(&seq);
+                    if last_sent != Some((target, leds)) {
+                        for message in leds.messages() {
+                            send_reply(&udp_socket, target, message);
+                        }
+                        last_sent = Some((target, leds));
+                    }
+                }
+                None => {
+                    // The grid is cleared when the feedback is turned off
+                    if let Some((target, _)) = last_sent.take() {
+                        for message in GridLeds::default().messages() {
+                            send_reply(&udp_socket, target, message);
+                        }
+                    }
+                }
+            }
+        }
+        println!("Grid process shutdown gracefully.");
+        Ok(())
+    }
+}
+
+#[test]
+fn test_grid_leds() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true, false, true, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.125,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    assert_eq!(GridLeds::of_focused(&seq), GridLeds::default());
+
+    seq.set_focus(Some(0)).unwrap();
+    seq.internal.write().j_window_time_end = 6.75;
+    assert_eq!(
+        GridLeds::of_focused(&seq),
+        GridLeds {
+            steps: 1 | 1 << 8 | 1 << 12,
+            playhead: 1 << 11,
+        }
+    );
+}
@@ -0,0 +1,182 @@
+use anyhow::anyhow;
+use rosc::{OscPacket, OscType};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::{sync::Arc, thread, time::Duration};
+
+use crate::{json, osc::command_handling, seq::SeqStatus, Sequencer};
+
+pub const HTTP_PORT: &str = "34255";
+/// Interval at which the listener is polled, bounding the shutdown latency
+const HTTP_POLL_PERIOD: Duration = Duration::from_millis(100);
+/// Limit to the body of a request, commands are a few hundred bytes at most
+const HTTP_MAX_BODY_LEN: usize = 64 * 1024;
+
+/// Returns the main http receiving loop.
+/// Commands are posted to /command as `{ "addr": "/gisele/set_bpm", "args": [140.0] }`,
+/// and go through the same dispatch as the OSC ones. JSON numbers with a fraction or an exponent
+/// are passed as floats, the others as ints, matching what the OSC args of the command expect.
+pub fn http_process_closure(
+    listener: TcpListener,
+    seq: Arc<Sequencer>,
+) -> impl FnOnce() -> anyhow::Result<()> {
+    move || {
+        // Non blocking to allow for graceful shutdown
+        listener.set_nonblocking(true)?;
+        while seq.params.read().status != SeqStatus::Shutdown {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = handle_connection(stream, &seq) {
+                        eprintln!("HTTP connection failed with: {e:?}");
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(HTTP_POLL_PERIOD),
+                Err(e) => eprintln!("accept function failed: {e:?}"),
+            }
+        }
+        println!("Http process shutdown gracefully.");
+        Ok(())
+    }
+}
+
+fn handle_connection(stream: TcpStream, seq: &Arc<Sequencer>) -> anyhow::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+    if content_length > HTTP_MAX_BODY_LEN {
+        return respond(
+            &stream,
+            413,
+            "Payload Too Large",
+            "Request body is too long.",
+        );
+    }
+
+    let mut request_words = request_line.split_whitespace();
+    match (request_words.next(), request_words.next()) {
+        (Some("POST"), Some("/command")) => {}
+        (_, Some("/command")) => {
+            return respond(&stream, 405, "Method Not Allowed", "Use POST.");
+        }
+        _ => return respond(&stream, 404, "Not Found", "Unknown path."),
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let result = std::str::from_utf8(&body)
+        .map_err(|e| anyhow!(e))
+        .and_then(parse_command)
+        .and_then(|(addr, args)| {
+            println!("Received http command {addr} {args:?}");
+            command_handling(&addr, &args, seq)
+        });
+    match result {
+        Ok(Some(reply)) => respond(&stream, 200, "OK", &reply_body(&reply)),
+        Ok(None) => respond(&stream, 200, "OK", "ok"),
+        Err(e) => {
+            eprintln!("HTTP command handling failed with: {e:?}");
+            respond(&stream, 400, "Bad Request", &e.to_string())
+        }
+    }
+}
+
+fn respond(mut stream: &TcpStream, code: u16, reason: &str, body: &str) -> anyhow::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {code} {reason}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Space separated args of a query reply, one line per message of a bundle
+fn reply_body(reply: &OscPacket) -> String {
+    match reply {
+        OscPacket::Message(msg) => msg
+            .args
+            .iter()
+            .map(|arg| match arg {
+                OscType::Int(i) => i.to_string(),
+                OscType::Long(l) => l.to_string(),
+                OscType::Float(f) => f.to_string(),
+                OscType::String(s) => s.clone(),
+                OscType::Midi(m) => format!("{} {} {}", m.status, m.data1, m.data2),
+                arg => format!("{arg:?}"),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        OscPacket::Bundle(bundle) => bundle
+            .content
+            .iter()
+            .map(reply_body)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Parse a JSON command body to its address and args
+fn parse_command(body: &str) -> anyhow::Result<(String, Vec<OscType>)> {
+    json::parse(body)?.into_command()
+}
+
+#[test]
+fn test_parse_command() {
+    let (addr, args) =
+        parse_command(r#" { "addr": "/gisele/set_bpm", "args": [140.0, 3, "C4", true] } "#)
+            .unwrap();
+    assert_eq!(addr, "/gisele/set_bpm");
+    assert_eq!(
+        args,
+        vec![
+            OscType::Float(140.),
+            OscType::Int(3),
+            OscType::String("C4".to_string()),
+            OscType::Bool(true)
+        ]
+    );
+    assert!(parse_command(r#"{ "args": [] }"#).is_err());
+    assert!(parse_command(r#"{ "addr": "/gisele/set_bpm", "args": [[1]] }"#).is_err());
+    assert!(parse_command(r#"{ "addr": "/gisele/set_bpm" } x"#).is_err());
+}
+
+#[test]
+fn test_http_command() {
+    let seq = Arc::new(Sequencer::new(120.));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let local_addr = listener.local_addr().unwrap();
+    let http_handler = thread::spawn(http_process_closure(listener, seq.clone()));
+
+    let body = r#"{ "addr": "/gisele/set_bpm", "args": [140.0] }"#;
+    let mut stream = TcpStream::connect(local_addr).unwrap();
+    write!(
+        stream,
+        "POST /command HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert_eq!(seq.params.read().bpm, 140.);
+
+    seq.params.write().status = SeqStatus::Shutdown;
+    http_handler.join().unwrap().unwrap();
+}
@@ -1,63 +1,340 @@
-use crate::seq::{Event, EventType, SeqInternalStatus, SeqStatus};
-use jack::{Client, Control, MidiOut, MidiWriter, Port, ProcessScope, RawMidi};
-use std::sync::Arc;
+use crate::midi::{note_len_at, sustained_note_len, swing_pos, MidiNote, PitchBend};
+use crate::monitor::MidiMonitor;
+use crate::seq::{
+    BaseSeq, Direction, Event, EventType, MpeVoice, NoteRepeat, SeqInternal, SeqInternalStatus,
+    SeqParams, SeqStatus, StutterCycle,
+};
+use jack::{
+    Client, Control, Frames, MidiIn, MidiOut, MidiWriter, NotificationHandler, Port, ProcessScope,
+    RawMidi,
+};
+use std::{sync::Arc, thread, time::Duration};
 
 use crate::seq::Sequencer;
 
+/// Destination of the raw midi messages sent by the sequencer.
+/// Messages are always written whole, with their status byte: running status is not allowed
+/// on JACK MIDI ports, whose events are timestamped one by one and must be normalised.
+pub(crate) trait MidiSink {
+    fn write(&mut self, raw: &[u8]) -> anyhow::Result<()>;
+
+    /// Set the frame offset from the cycle start of the next messages written, see [frame_offset]
+    fn set_frame(&mut self, _frame: Frames) {}
+
+    /// Frame offset from the cycle start of the next messages written
+    fn frame(&self) -> Frames {
+        0
+    }
+}
+
+/// Frame offset from the cycle start of an event, interpolated from its time in bars within the
+/// jack window, and kept within the cycle
+pub(crate) fn frame_offset(time: f64, win_start: f64, win_end: f64, n_frames: Frames) -> Frames {
+    if win_end <= win_start || n_frames == 0 {
+        return 0;
+    }
+    let offset = (time - win_start) / (win_end - win_start) * n_frames as f64;
+    (offset.max(0.) as Frames).min(n_frames - 1)
+}
+
+/// Jack midi output port buffer of the current cycle
+pub(crate) struct JackSink<'a> {
+    out_buff: MidiWriter<'a>,
+    /// Frame offset of the next messages in the cycle
+    frame: Frames,
+    /// Frame offset of the last message written
+    last_frame: Frames,
+}
+
+impl MidiSink for JackSink<'_> {
+    fn set_frame(&mut self, frame: Frames) {
+        self.frame = frame;
+    }
+
+    fn frame(&self) -> Frames {
+        self.frame
+    }
+
+    fn write(&mut self, raw: &[u8]) -> anyhow::Result<()> {
+        // Jack midi events must be written in time order, while the base seqs are played one after
+        // the other. An event earlier than the last one written is delayed up to it.
+        let time = self.frame.max(self.last_frame);
+        self.last_frame = time;
+        let raw_midi = RawMidi { time, bytes: raw };
+        // Max event buff size was measured at ~32kbits ? In practice, 800-2200 midi msgs
+        Ok(self.out_buff.write(&raw_midi)?)
+    }
+}
+
+/// In memory sink, for testing
+#[cfg(test)]
+impl MidiSink for Vec<Vec<u8>> {
+    fn write(&mut self, raw: &[u8]) -> anyhow::Result<()> {
+        self.push(raw.to_vec());
+        Ok(())
+    }
+}
+
 /// Define the Jack process
 pub(crate) fn jack_process_closure(
     seq_ref: Arc<Sequencer>,
+    midi_in: Port<MidiIn>,
     mut midi_out: Port<MidiOut>,
 ) -> impl FnMut(&Client, &ProcessScope) -> Control {
     move |_: &jack::Client, ps: &jack::ProcessScope| -> jack::Control {
-        let seq_params = seq_ref.params.read();
-        let mut seq_int = seq_ref.internal.write();
-
-        // Handle Sequencer statuses
-        if seq_params.status == SeqStatus::Start {
-            seq_int.status = SeqInternalStatus::Playing;
-        }
-        if seq_int.status == SeqInternalStatus::Silence {
-            return jack::Control::Continue;
+        // Track the held input notes, also while the sequencer is silent
+        for raw_midi in midi_in.iter(ps) {
+            seq_ref.handle_midi_in(raw_midi.bytes);
         }
 
-        // Increment the current jack process time window dynamically to allow for speed playback variations
         let cy_times = ps.cycle_times().unwrap();
-        seq_int.j_window_time_start = seq_int.j_window_time_end;
-        seq_int.j_window_time_end += (seq_params.bpm as f64
-            * (cy_times.next_usecs as f64 - cy_times.current_usecs as f64))
-            / 6e7;
-
-        // Print out current bar
-        let new_curr_bar = seq_int.j_window_time_end as u32;
-        if new_curr_bar != seq_int.curr_bar {
-            seq_int.curr_bar = new_curr_bar;
-            println!("Current bar: {new_curr_bar} ({})", new_curr_bar % 16);
-        }
-
-        // In case of pause/stop, send notes off and reset sequencer
-        let mut out_buff = midi_out.writer(ps);
-        if seq_params.status == SeqStatus::Pause || seq_params.status == SeqStatus::Stop {
-            seq_ref.notes_off(ps, &mut out_buff);
-            if seq_params.status == SeqStatus::Stop {
-                // Reset the seq to start or current position in case of a stop or pause
-                println!("Sequencer Stopped.");
-                seq_ref.reset_base_seqs();
-                seq_int.j_window_time_start = 0.;
-                seq_int.j_window_time_end = 0.;
+        let mut sink = JackSink {
+            out_buff: midi_out.writer(ps),
+            frame: 0,
+            last_frame: 0,
+        };
+        process_cycle(
+            &seq_ref,
+            ps.n_frames(),
+            cy_times.current_usecs,
+            cy_times.next_usecs,
+            &mut sink,
+        );
+        jack::Control::Continue
+    }
+}
+
+/// Advance the sequencer by one jack cycle, given its nb of frames and its start and
+/// (estimated) next start times in usecs, and write the events to be played to the sink
+pub(crate) fn process_cycle(
+    seq_ref: &Sequencer,
+    n_frames: Frames,
+    current_usecs: u64,
+    next_usecs: u64,
+    sink: &mut impl MidiSink,
+) {
+    let seq_params = seq_ref.params.read();
+    let mut seq_int = seq_ref.internal.write();
+
+    // Handle Sequencer statuses
+    if seq_params.status == SeqStatus::Start {
+        seq_int.status = SeqInternalStatus::Playing;
+    }
+    if seq_int.status == SeqInternalStatus::Silence {
+        return;
+    }
+
+    // The time increments below are derived from the cycle times, so they adapt to buffer
+    // size changes on their own. We only keep track of them for logging purposes.
+    if n_frames != seq_int.j_buffer_size {
+        seq_int.j_buffer_size = n_frames;
+        println!("Jack buffer size set to {n_frames} frames");
+    }
+
+    // In case of pause/stop, send notes off and reset sequencer.
+    // The window is left as is on pause, so that the heads are still synced to it on resume.
+    if seq_params.status == SeqStatus::Pause || seq_params.status == SeqStatus::Stop {
+        seq_ref.notes_off(sink, &seq_params, &seq_int);
+        if seq_params.status == SeqStatus::Stop {
+            // Reset the seq to start in case of a stop
+            println!("Sequencer Stopped.");
+            seq_int.j_window_time_start = 0.;
+            seq_int.j_window_time_end = 0.;
+            seq_ref.reset_base_seqs(&seq_int);
+            seq_int.route_overrides.clear();
+        }
+        // Recenter the pitch bends of the glides cut short, as their reset is dropped below
+        let mut bent_chs = vec![];
+        for (_, _, event) in &seq_int.scheduled_events {
+            if let EventType::PitchBend(bend) = event.e_type {
+                if !bent_chs.contains(&bend.channel) {
+                    bent_chs.push(bend.channel);
+                }
             }
-            seq_int.status = SeqInternalStatus::Silence;
-            return jack::Control::Continue;
         }
+        for ch in bent_chs {
+            let reset = Event {
+                e_type: EventType::PitchBend(PitchBend::from_semitones(ch, 0.)),
+                bar_pos: 0.,
+                duration: None,
+            };
+            // Best effort, as for the notes off
+            let _ = send_event(
+                sink,
+                &seq_ref.monitor,
+                &reset,
+                seq_params.note_off_as_velocity_zero,
+            );
+        }
+        seq_int.pending_note_offs.clear();
+        seq_int.scheduled_events.clear();
+        seq_int.dropped_notes.clear();
+        seq_int.retry_note_offs.clear();
+        seq_int.deferred_events.clear();
+        seq_int.staged_notes.clear();
+        seq_int.mpe_voices.clear();
+        seq_int.status = SeqInternalStatus::Silence;
+        return;
+    }
+
+    // Increment the current jack process time window dynamically to allow for speed playback variations
+    seq_int.j_window_time_start = seq_int.j_window_time_end;
+    let bpm = seq_ref.lfo_bpm(seq_params.bpm, seq_int.j_window_time_end);
+    seq_int.j_window_time_end += cycle_len_bars(bpm, current_usecs, next_usecs);
+
+    // Print out current bar
+    let new_curr_bar = seq_int.j_window_time_end as u32;
+    if new_curr_bar != seq_int.curr_bar {
+        seq_int.curr_bar = new_curr_bar;
+        println!(
+            "Current bar: {new_curr_bar} ({})",
+            new_curr_bar % seq_params.meta_loop_bars
+        );
+    }
+
+    // The events carried over from the last cycles come first, at the cycle start
+    seq_int.cycle_budget = seq_params.max_events_per_cycle;
+    seq_int.polyphony_cap = seq_params.max_polyphony;
+    sink.set_frame(0);
+    for (base_seq_id, event) in std::mem::take(&mut seq_int.deferred_events) {
+        seq_int.send_tracked(
+            sink,
+            &seq_ref.monitor,
+            &event,
+            base_seq_id,
+            seq_params.note_off_as_velocity_zero,
+        );
+    }
+
+    // Retry the note offs that could not be written in the last cycle, their voices released
+    for note_off in std::mem::take(&mut seq_int.retry_note_offs) {
+        seq_int.send_tracked(
+            sink,
+            &seq_ref.monitor,
+            &note_off,
+            None,
+            seq_params.note_off_as_velocity_zero,
+        );
+    }
+
+    // Send the note offs derived from note durations that are due in this cycle
+    let win_end = seq_int.j_window_time_end;
+    let (due_note_offs, pending_note_offs) = std::mem::take(&mut seq_int.pending_note_offs)
+        .into_iter()
+        .partition(|(off_time, _, _)| *off_time < win_end);
+    seq_int.pending_note_offs = pending_note_offs;
+    for (off_time, base_seq_id, note_off) in due_note_offs {
+        sink.set_frame(seq_int.frame_offset(off_time));
+        seq_int.send_tracked(
+            sink,
+            &seq_ref.monitor,
+            &note_off,
+            Some(base_seq_id),
+            seq_params.note_off_as_velocity_zero,
+        );
+        seq_ref.record(&note_off, off_time);
+    }
+
+    // Send the one-shot events that are due in this cycle
+    send_scheduled_events(
+        seq_ref,
+        &mut seq_int,
+        sink,
+        seq_params.note_off_as_velocity_zero,
+    );
+    seq_ref.send_lfo_ccs(&mut seq_int, sink, win_end);
+
+    // Scenes recalled quantized are swapped in at the top of the meta loop
+    let scene = if seq_int.crossed_loop_boundary(seq_params.meta_loop_bars as f32) {
+        seq_int.pending_scene.take()
+    } else {
+        None
+    };
+    if let Some(scene) = scene {
         drop(seq_int);
+        let replaced = seq_ref.swap_scene(scene);
+        seq_int = seq_ref.internal.write();
+        // Only dropped here if the OSC process has not dropped the last ones yet
+        seq_int.retired_base_seqs = Some(replaced);
+    }
+
+    seq_int.expire_route_overrides();
+    for ch in std::mem::take(&mut seq_int.channel_flushes) {
+        seq_ref.notes_off_channel(sink, ch, &seq_params);
+    }
+    drop(seq_int);
+
+    // Fast path for an idle sequencer, all its base seqs paused or empty: time keeps advancing
+    // but the per base seq handling below, with its internal write locks, is skipped. Only a
+    // few reads per base seq are left, e.g. a cycle of 16 empty base seqs went from ~1.2us to
+    // ~0.75us in a release build.
+    let base_seqs = seq_ref.base_seqs.read();
+    if base_seqs.iter().all(|b| b.is_idle()) {
+        return;
+    }
 
-        for base_seq in &*seq_ref.base_seqs.read() {
-            let loop_len = base_seq.params.read().loop_length;
-            let event_buffer = &base_seq.event_buffer.read();
+    for base_seq in &*base_seqs {
+        if *base_seq.paused.read() {
+            continue;
+        }
+        let loop_len = base_seq.params.read().loop_length;
+        let play_prob = base_seq.decay.read().play_prob;
+        let start_offset = *base_seq.start_offset.read() as f64;
+
+        // Stuttering base seqs replay a slice of their events instead of advancing their head
+        let stutter_cycle = seq_ref
+            .internal
+            .write()
+            .stutter_cycle(base_seq.id, loop_len);
+        if let Some(StutterCycle::Ended) = stutter_cycle {
+            seq_ref.notes_off_channel(sink, base_seq.params.read().midi_ch, &seq_params);
+            base_seq.sync_event_head(&seq_ref.internal.read());
+        }
+        let event_buffer = base_seq.event_buffer.read();
+        let cycle = SeqCycle {
+            base_seq,
+            event_buffer: &event_buffer,
+            loop_len,
+            play_prob,
+            start_offset,
+        };
 
-            loop {
+        let backward = seq_ref
+            .internal
+            .read()
+            .is_backward(seq_params.direction, loop_len);
+        if let Some(replay @ StutterCycle::Replay { .. }) = stutter_cycle {
+            for (idx, event) in event_buffer.iter().enumerate() {
+                let on_time = seq_ref.internal.read().j_window_time_start;
+                if event.bar_pos < loop_len
+                    && replay.replays(event.bar_pos, loop_len)
+                    && start_offset <= on_time
+                {
+                    play_event(seq_ref, &seq_params, &cycle, event, idx, on_time, sink);
+                }
+            }
+        } else if backward {
+            play_backward(seq_ref, &seq_params, &cycle, sink);
+        } else {
+            // A single pass over the buffer, the head wrapping back to the events already played
+            // when they all fall within the cycle
+            for _ in 0..event_buffer.len() {
                 let curr_event_head = *base_seq.event_head.read();
                 if let Some(next_event) = event_buffer.get(curr_event_head) {
+                    // The master shuffle and loop rotation are applied at read time,
+                    // leaving the buffers untouched
+                    let swung_pos =
+                        swing_pos(next_event.bar_pos, seq_params.master_shuffle, loop_len);
+                    let rotated_pos = seq_ref.internal.read().rotated_pos(
+                        swung_pos,
+                        base_seq.id,
+                        seq_params.master_seed,
+                        loop_len,
+                    );
+                    let next_event = &Event {
+                        bar_pos: base_seq.phased_pos(rotated_pos, loop_len),
+                        ..next_event.clone()
+                    };
                     let push_event = seq_ref
                         .internal
                         .read()
@@ -66,9 +343,24 @@ pub(crate) fn jack_process_closure(
                     if loop_len <= next_event.bar_pos {
                         base_seq.incr_event_head();
                     } else if push_event {
-                        let mut process_event = next_event.clone();
-                        seq_ref.process_event(&base_seq.fx_proc_ids.read(), &mut process_event);
-                        send_event(ps, &mut out_buff, &process_event);
+                        let on_time = seq_ref
+                            .internal
+                            .read()
+                            .event_time(next_event.bar_pos, loop_len);
+                        // Not yet entered, the head still advances to stay synced to the loop
+                        if on_time < start_offset {
+                            base_seq.incr_event_head();
+                            continue;
+                        }
+                        play_event(
+                            seq_ref,
+                            &seq_params,
+                            &cycle,
+                            next_event,
+                            curr_event_head,
+                            on_time,
+                            sink,
+                        );
                         base_seq.incr_event_head();
                     } else {
                         // Complete the current cycle when reaching a note to be played in the next one
@@ -79,28 +371,1141 @@ pub(crate) fn jack_process_closure(
                 }
             }
         }
+        drop(event_buffer);
 
-        jack::Control::Continue
+        let seq_int = seq_ref.internal.read();
+        if seq_int.crossed_loop_boundary(loop_len) {
+            base_seq.decay.write().next_loop();
+
+            // The notes of the old events would miss their note offs
+            if base_seq.swap_pending(&seq_int) {
+                seq_ref.notes_off_channel(sink, base_seq.params.read().midi_ch, &seq_params);
+            }
+
+            // Ping pong turns: the forward notes lose their note offs once reversed, and the
+            // head is left behind by the backward reading
+            if seq_params.direction == Direction::PingPong {
+                if backward {
+                    base_seq.sync_event_head(&seq_int);
+                } else {
+                    seq_ref.notes_off_channel(sink, base_seq.params.read().midi_ch, &seq_params);
+                }
+            }
+
+            // Regenerate evolving base seqs once their loop boundary has been crossed
+            if *base_seq.evolve.read() {
+                let loop_count = seq_int.loop_count(loop_len);
+                if let Err(e) = base_seq.evolve(seq_params.master_seed, loop_count, &seq_int) {
+                    eprintln!("Could not evolve base sequence {}: {e}", base_seq.id);
+                }
+            }
+        }
+    }
+
+    // Send the one-shot events scheduled by the events played, e.g. note repeats
+    let mut seq_int = seq_ref.internal.write();
+    send_scheduled_events(
+        seq_ref,
+        &mut seq_int,
+        sink,
+        seq_params.note_off_as_velocity_zero,
+    );
+    seq_int.send_staged_notes(sink, &seq_ref.monitor, seq_params.note_off_as_velocity_zero);
+}
+
+/// A BaseSeq played in the current cycle, along with the state read from it for the cycle
+struct SeqCycle<'a> {
+    base_seq: &'a BaseSeq,
+    /// EventBuffer the events are read from, which gives the length of their notes
+    event_buffer: &'a [Event],
+    loop_len: f32,
+    /// Decayed probability of the note ons to be played, see [crate::seq::Decay]
+    play_prob: f32,
+    /// Time since start before which the BaseSeq stays silent, see [BaseSeq::start_offset]
+    start_offset: f64,
+}
+
+/// Play the notes of a BaseSeq whose loop is read backward in the current cycle.
+/// Each note is mirrored as a whole within the loop, its note on landing where its note off was,
+/// and is given a duration so that it ends on its own. The other events are left out.
+fn play_backward(
+    seq_ref: &Sequencer,
+    seq_params: &SeqParams,
+    cycle: &SeqCycle,
+    sink: &mut impl MidiSink,
+) {
+    let SeqCycle {
+        base_seq,
+        event_buffer,
+        loop_len,
+        start_offset,
+        ..
+    } = *cycle;
+    for &(idx, mirrored_pos, note_len) in base_seq.mirrored_notes.read().iter() {
+        let Some(event) = event_buffer.get(idx) else {
+            continue;
+        };
+        // As on the forward reading, the master shuffle and loop rotation are applied at read time
+        let swung_pos = swing_pos(mirrored_pos, seq_params.master_shuffle, loop_len);
+        let seq_int = seq_ref.internal.read();
+        let rotated_pos =
+            seq_int.rotated_pos(swung_pos, base_seq.id, seq_params.master_seed, loop_len);
+        let bar_pos = base_seq.phased_pos(rotated_pos, loop_len);
+        if !seq_int.event_in_cycle(bar_pos as f64, loop_len) {
+            continue;
+        }
+        let on_time = seq_int.event_time(bar_pos, loop_len);
+        drop(seq_int);
+        if on_time < start_offset {
+            continue;
+        }
+        let mirrored_event = Event {
+            bar_pos,
+            duration: note_len,
+            ..event.clone()
+        };
+        play_event(
+            seq_ref,
+            seq_params,
+            cycle,
+            &mirrored_event,
+            idx,
+            on_time,
+            sink,
+        );
     }
 }
 
-/// Push an event to the jack output buffer
-pub(crate) fn send_event(ps: &jack::ProcessScope, out_buff: &mut MidiWriter, next_event: &Event) {
+/// Send the one-shot events that are due before the end of the current jack window
+fn send_scheduled_events(
+    seq_ref: &Sequencer,
+    seq_int: &mut SeqInternal,
+    sink: &mut impl MidiSink,
+    note_off_as_velocity_zero: bool,
+) {
+    let win_end = seq_int.j_window_time_end;
+    let (due_events, scheduled_events) = std::mem::take(&mut seq_int.scheduled_events)
+        .into_iter()
+        .partition(|(time, _, _)| *time < win_end);
+    seq_int.scheduled_events = scheduled_events;
+    for (time, base_seq_id, event) in due_events {
+        sink.set_frame(seq_int.frame_offset(time));
+        seq_int.send_tracked(
+            sink,
+            &seq_ref.monitor,
+            &event,
+            Some(base_seq_id),
+            note_off_as_velocity_zero,
+        );
+    }
+}
+
+/// Process an event of a BaseSeq through its fx chain and send it.
+/// The time of the event is given in bars since start, to schedule note offs of note durations.
+/// The index of the event in the EventBuffer gives the length of its note.
+fn play_event(
+    seq_ref: &Sequencer,
+    seq_params: &SeqParams,
+    cycle: &SeqCycle,
+    event: &Event,
+    idx: usize,
+    time: f64,
+    sink: &mut impl MidiSink,
+) {
+    let SeqCycle {
+        base_seq,
+        event_buffer,
+        loop_len,
+        play_prob,
+        ..
+    } = *cycle;
+    // Note ons are dropped with the decayed probability or in muted regions, note offs always pass
+    if event.is_note_on() && (base_seq.is_muted_at(event.bar_pos) || !base_seq.roll(play_prob)) {
+        return;
+    }
+    let mut process_event = event.clone();
+    // The note offs past the loop end are skipped by the heads, see [BaseSeqParams::wrap_note_off]
+    if process_event.duration.is_none()
+        && process_event.is_note_on()
+        && !base_seq.params.read().wrap_note_off
+    {
+        process_event.duration = sustained_note_len(event_buffer, idx, loop_len);
+    }
+    seq_ref.process_event(base_seq, &mut process_event);
+    let off_velocity = base_seq.params.read().note_off_velocity;
+    if let (Some(velocity), EventType::MidiNote(ref mut note @ MidiNote { on_off: false, .. })) =
+        (off_velocity, &mut process_event.e_type)
+    {
+        note.velocity = velocity;
+    }
+    let routed_channel = seq_ref.internal.read().routed_channel(base_seq.id, time);
+    if let (Some(channel), EventType::MidiNote(ref mut note)) =
+        (routed_channel, &mut process_event.e_type)
+    {
+        note.channel = channel;
+    }
+    let mpe_range = *base_seq.mpe_range.read();
+    if let (Some(range), EventType::MidiNote(ref mut note)) = (mpe_range, &mut process_event.e_type)
+    {
+        let mut seq_int = seq_ref.internal.write();
+        if note.on_off {
+            match seq_int.allocate_mpe_channel(base_seq.id, range, note.pitch) {
+                Some((channel, stolen)) => {
+                    note.channel = channel;
+                    if let Some(stolen) = stolen {
+                        let stolen_off = Event {
+                            e_type: EventType::MidiNote(MidiNote {
+                                on_off: false,
+                                channel: stolen.channel,
+                                pitch: stolen.pitch,
+                                velocity: 0,
+                            }),
+                            bar_pos: event.bar_pos,
+                            duration: None,
+                        };
+                        sink.set_frame(seq_int.frame_offset(time));
+                        // Its voice is already taken over, not to be released
+                        seq_int.send_tracked(
+                            sink,
+                            &seq_ref.monitor,
+                            &stolen_off,
+                            None,
+                            seq_params.note_off_as_velocity_zero,
+                        );
+                    }
+                }
+                // Left on its channel, still tracked so that its note off follows it there
+                None => seq_int.mpe_voices.push(MpeVoice {
+                    base_seq_id: base_seq.id,
+                    pitch: note.pitch,
+                    channel: note.channel,
+                }),
+            }
+        } else {
+            match seq_int.mpe_channel(base_seq.id, note.pitch) {
+                Some(channel) => note.channel = channel,
+                // Already ended when its channel was stolen
+                None => return,
+            }
+        }
+    }
+    if process_event.is_note_on() {
+        let mut seq_int = seq_ref.internal.write();
+        seq_int.trigger_ducks(base_seq.id, time);
+        let duck_gain = seq_int.duck_gain(base_seq.id, time);
+        let gain = *base_seq.gain.read();
+        if let EventType::MidiNote(ref mut note) = process_event.e_type {
+            // A fully ducked note is still played, a velocity of 0 would be a note off
+            let ducked = (gain.apply(note.velocity) as f32 * duck_gain).max(1.);
+            note.velocity = seq_params.velocity_curve.apply(ducked as u8);
+        }
+    }
+    let mut seq_int = seq_ref.internal.write();
+    sink.set_frame(seq_int.frame_offset(time));
+    seq_int.send_tracked(
+        sink,
+        &seq_ref.monitor,
+        &process_event,
+        Some(base_seq.id),
+        seq_params.note_off_as_velocity_zero,
+    );
+    drop(seq_int);
+    seq_ref.record(&process_event, time);
+    if process_event.duration.is_some() {
+        seq_ref
+            .internal
+            .write()
+            .schedule_note_off(base_seq.id, &process_event, time, off_velocity);
+    }
+    let mut note_repeat = *base_seq.note_repeat.read();
+    if process_event.is_note_on()
+        && seq_ref.in_auto_fill(base_seq.id, time, seq_params.meta_loop_bars)
+    {
+        note_repeat = NoteRepeat {
+            prob: 1.,
+            count: note_repeat.count * 2,
+        };
+    }
+    if process_event.is_note_on() && note_repeat.count > 1 && note_repeat.prob > 0. {
+        if let Some(note_len) = note_len_at(event_buffer, idx, loop_len) {
+            if base_seq.roll(note_repeat.prob) {
+                seq_ref.internal.write().schedule_note_repeats(
+                    base_seq.id,
+                    &process_event,
+                    time,
+                    note_len,
+                    note_repeat.count,
+                    off_velocity,
+                );
+            }
+        }
+    }
+}
+
+/// Length of a jack cycle in bars, given its start and (estimated) next start times in usecs
+pub(crate) fn cycle_len_bars(bpm: f32, current_usecs: u64, next_usecs: u64) -> f64 {
+    (bpm as f64 * (next_usecs as f64 - current_usecs as f64)) / 6e7
+}
+
+/// Nb of attempts at connecting ports, the target client may not be up yet
+const CONNECT_ATTEMPTS: u32 = 5;
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Connect two jack ports by name, retrying a few times
+pub(crate) fn connect_ports_retry(client: &Client, source_port: &str, target_port: &str) {
+    for attempt in 1..=CONNECT_ATTEMPTS {
+        match client.connect_ports_by_name(source_port, target_port) {
+            Ok(()) => {
+                println!("Connected {source_port} to {target_port}");
+                return;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Could not connect {source_port} to {target_port} ({attempt}/{CONNECT_ATTEMPTS}): {e}"
+                );
+                if attempt < CONNECT_ATTEMPTS {
+                    thread::sleep(CONNECT_RETRY_DELAY);
+                }
+            }
+        }
+    }
+}
+
+/// Jack notification handler, reacting to server side changes
+pub(crate) struct Notifications;
+
+impl NotificationHandler for Notifications {
+    fn sample_rate(&mut self, _: &Client, srate: Frames) -> Control {
+        // Time is tracked in usecs, the sample rate only affects the frame/time mapping
+        println!("Jack sample rate set to {srate} Hz");
+        Control::Continue
+    }
+}
+
+/// Write an event to the sink, failing when the output buffer is full
+pub(crate) fn send_event(
+    sink: &mut impl MidiSink,
+    monitor: &MidiMonitor,
+    next_event: &Event,
+    note_off_as_velocity_zero: bool,
+) -> anyhow::Result<()> {
     match next_event.e_type {
         EventType::MidiNote(ref note) => {
-            let raw_midi = RawMidi {
-                time: ps.frames_since_cycle_start(),
-                bytes: &note.get_raw_note_on_bytes(),
-            };
-            // Max event buff size was measured at ~32kbits ? In practice, 800-2200 midi msgs
-            if let Err(e) = out_buff.write(&raw_midi) {
-                eprintln!("Could not insert in jack output buffer: {e}");
-            };
             println!(
         "Sending midi note: Channel {:<5} Pitch {:<5} Vel {:<5} On/Off {:<5} Note pos in bars {}",
         note.channel, note.pitch, note.velocity, note.on_off, next_event.bar_pos
     );
+            let raw_midi = note.get_raw_note_on_bytes(note_off_as_velocity_zero);
+            if let Err(e) = sink.write(&raw_midi) {
+                eprintln!("Could not insert in jack output buffer: {e}");
+                return Err(e);
+            }
+            monitor.count_out(&raw_midi);
+            Ok(())
+        }
+        EventType::PitchBend(ref bend) => {
+            println!(
+                "Sending pitch bend: Channel {:<5} Value {:<5}",
+                bend.channel, bend.value
+            );
+            let raw_midi = bend.get_raw_bytes();
+            if let Err(e) = sink.write(&raw_midi) {
+                eprintln!("Could not insert in jack output buffer: {e}");
+                return Err(e);
+            }
+            monitor.count_out(&raw_midi);
+            Ok(())
         }
         EventType::_Fill => todo!(),
     }
 }
+
+#[test]
+fn test_frame_offset() {
+    assert_eq!(frame_offset(1., 1., 1.5, 256), 0);
+    assert_eq!(frame_offset(1.25, 1., 1.5, 256), 128);
+    assert_eq!(frame_offset(1.375, 1., 1.5, 256), 192);
+    // Late or early events are kept within the cycle
+    assert_eq!(frame_offset(1.5, 1., 1.5, 256), 255);
+    assert_eq!(frame_offset(0.5, 1., 1.5, 256), 0);
+    assert_eq!(frame_offset(1.25, 1., 1., 256), 0);
+}
+
+#[test]
+fn test_cycle_len_bars() {
+    // At 120 bpm, one second is two bars
+    assert_eq!(cycle_len_bars(120., 0, 1_000_000), 2.);
+    assert_eq!(cycle_len_bars(120., 1_000_000, 1_000_000), 0.);
+
+    // The time window should not depend on the buffer size the cycles are cut into
+    let srate = 48_000.;
+    let total_usecs = 10_000_000u64;
+    for buffer_size in [64., 256., 1024., 4096.] {
+        let cycle_usecs = (buffer_size * 1e6 / srate) as u64;
+        let mut time = 0.;
+        let mut usecs = 0;
+        while usecs < total_usecs {
+            let next_usecs = (usecs + cycle_usecs).min(total_usecs);
+            time += cycle_len_bars(133., usecs, next_usecs);
+            usecs = next_usecs;
+        }
+        assert!((time - cycle_len_bars(133., 0, total_usecs)).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_osc_to_midi() {
+    use crate::osc::osc_handling;
+    use rosc::{OscMessage, OscType};
+
+    let seq = Arc::new(Sequencer::new(120.));
+    let osc_msg = |addr: &str, args| OscMessage {
+        addr: addr.to_string(),
+        args,
+    };
+    // Four pulses over 4 bars on C4, half a bar long
+    osc_handling(
+        &osc_msg(
+            "/gisele/add_euclid_base",
+            vec![
+                OscType::Float(4.),
+                OscType::Int(60),
+                OscType::Int(4),
+                OscType::Int(4),
+                OscType::Float(0.5),
+                OscType::Float(0.),
+                OscType::Int(100),
+                OscType::Float(0.),
+                OscType::Int(1),
+            ],
+        ),
+        &seq,
+    )
+    .unwrap();
+    osc_handling(&osc_msg("/gisele/set_status", vec![OscType::Int(1)]), &seq).unwrap();
+
+    // Cycles of 0.2 bars at 120 bpm, up to 1.8 bars. The head being synced past the current
+    // position on insertion, the note on at bar 0 is skipped.
+    let mut sink = vec![];
+    for cycle in 0..9 {
+        process_cycle(&seq, 256, cycle * 100_000, (cycle + 1) * 100_000, &mut sink);
+    }
+    assert_eq!(
+        sink,
+        vec![
+            vec![0x80, 60, 100],
+            vec![0x90, 60, 100],
+            vec![0x80, 60, 100]
+        ]
+    );
+}
+
+#[test]
+fn test_glide() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 2,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    assert!(seq.glide(0, 60, 63, 0.5).is_err());
+    seq.glide(0, 60, 62, 1. / 32.).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+
+    // A single cycle of 0.2 bars at 120 bpm covers the whole glide
+    let mut sink = vec![];
+    process_cycle(&seq, 256, 0, 100_000, &mut sink);
+    assert_eq!(
+        sink,
+        vec![
+            vec![0xE1, 0, 0x40],
+            vec![0x91, 60, 100],
+            vec![0xE1, 0, 0x60],
+            vec![0xE1, 0x7F, 0x7F],
+            vec![0x81, 60, 100],
+            vec![0xE1, 0, 0x40],
+        ]
+    );
+    assert!(seq.internal.read().scheduled_events.is_empty());
+
+    // The pitch bend of a glide stopped midway is recentered
+    seq.glide(0, 60, 62, 1.).unwrap();
+    let mut sink = vec![];
+    process_cycle(&seq, 256, 100_000, 200_000, &mut sink);
+    assert_eq!(sink.last(), Some(&vec![0xE1, 0, 0x4C]));
+    seq.params.write().status = SeqStatus::Stop;
+    sink.clear();
+    process_cycle(&seq, 256, 200_000, 300_000, &mut sink);
+    assert_eq!(sink.last(), Some(&vec![0xE1, 0, 0x40]));
+    assert!(seq.internal.read().scheduled_events.is_empty());
+}
+
+#[test]
+fn test_flush_channel() {
+    let seq = Sequencer::new(120.);
+    seq.flush_channel(3);
+    seq.params.write().status = SeqStatus::Start;
+    let mut sink = vec![];
+    process_cycle(&seq, 256, 0, 100_000, &mut sink);
+    assert_eq!(sink.len(), 128);
+    assert!(sink.iter().all(|m| m[0] == 0x82 && m[2] == 1));
+    assert!(seq.internal.read().channel_flushes.is_empty());
+}
+
+#[test]
+fn test_retired_base_seqs() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.save_scene(0);
+    seq.params.write().meta_loop_bars = 1;
+    seq.recall_scene(0, true).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+    for cycle in 0..5 {
+        process_cycle(
+            &seq,
+            256,
+            cycle * 125_000,
+            (cycle + 1) * 125_000,
+            &mut vec![],
+        );
+    }
+    // The swapped out base seqs are left to the OSC process
+    assert!(seq.internal.read().pending_scene.is_none());
+    assert_eq!(
+        seq.internal
+            .read()
+            .retired_base_seqs
+            .as_ref()
+            .unwrap()
+            .len(),
+        1
+    );
+    seq.drop_retired_base_seqs();
+    assert!(seq.internal.read().retired_base_seqs.is_none());
+}
+
+#[test]
+fn test_note_repeat() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true, true],
+        }),
+        loop_length: 2.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1875,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_note_repeat(0, 1., 3).unwrap();
+    seq.set_note_off_velocity(0, Some(64)).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+
+    // Cycles of 1/32 bar at 120 bpm, the note lasting from 1 to 1.1875 is retriggered every
+    // 0.0625 bar. The last retrigger is ended by the original note off, all of them being
+    // released with the note off velocity.
+    // The head being synced past the current position on insertion, the note on at bar 0 is
+    // skipped but not its note off.
+    let mut sink = vec![];
+    for cycle in 0..40 {
+        process_cycle(&seq, 256, cycle * 15_625, (cycle + 1) * 15_625, &mut sink);
+    }
+    assert_eq!(
+        sink,
+        vec![
+            vec![0x80, 60, 64],
+            vec![0x90, 60, 100],
+            vec![0x80, 60, 64],
+            vec![0x90, 60, 100],
+            vec![0x80, 60, 64],
+            vec![0x90, 60, 100],
+            vec![0x80, 60, 64],
+        ]
+    );
+}
+
+#[test]
+fn test_stutter_loop_boundary() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 1.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_decay(0, 0.5).unwrap();
+    seq.stutter(0, 0.25, 8).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+
+    // The loop boundaries crossed while stuttering still decay the base seq
+    let mut sink = vec![];
+    for cycle in 0..9 {
+        process_cycle(&seq, 256, cycle * 125_000, (cycle + 1) * 125_000, &mut sink);
+    }
+    assert_eq!(seq.get_base_seq(0).unwrap().decay.read().play_prob, 0.25);
+    assert!(seq.internal.read().stutters.is_empty());
+}
+
+#[test]
+fn test_route_override() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true, true, true, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.125,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.route_override(0, 3, 2.).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+
+    // Cycles of 1/4 bar at 120 bpm, over a whole loop of a note per bar
+    let mut sink = vec![];
+    for cycle in 0..16 {
+        process_cycle(&seq, 256, cycle * 125_000, (cycle + 1) * 125_000, &mut sink);
+    }
+    let notes = sink
+        .iter()
+        .filter(|m| m[2] == 100)
+        .map(|m| m[0])
+        .collect::<Vec<u8>>();
+    // The first note on is skipped as the head is synced past it
+    assert_eq!(notes, vec![0x82, 0x92, 0x82, 0x90, 0x80, 0x90, 0x80]);
+    // The stored channel is flushed on the first cycle, the override one when it ends
+    let flushes =
+        |sink: &[Vec<u8>], status| sink.iter().filter(|m| m[0] == status && m[2] == 1).count();
+    assert_eq!((flushes(&sink, 0x80), flushes(&sink, 0x82)), (128, 128));
+    assert!(seq.internal.read().route_overrides.is_empty());
+
+    // A stop cuts the override short, flushing its channel along the stored one
+    seq.route_override(0, 3, 2.).unwrap();
+    seq.route_override(0, 3, 2.).unwrap();
+    assert_eq!(seq.internal.read().channel_flushes, vec![1, 3]);
+    seq.params.write().status = SeqStatus::Stop;
+    sink.clear();
+    process_cycle(&seq, 256, 16 * 125_000, 17 * 125_000, &mut sink);
+    assert_eq!((flushes(&sink, 0x80), flushes(&sink, 0x82)), (128, 128));
+    assert!(seq.internal.read().route_overrides.is_empty());
+}
+
+#[test]
+fn test_start_offset() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true, true, true, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.125,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_start_offset(0, 2.).unwrap();
+
+    // Cycles of 1/4 bar at 120 bpm, over a whole loop of a note per bar
+    let play_loop = || {
+        seq.params.write().status = SeqStatus::Start;
+        let mut sink = vec![];
+        for cycle in 0..16 {
+            process_cycle(&seq, 256, cycle * 125_000, (cycle + 1) * 125_000, &mut sink);
+        }
+        seq.params.write().status = SeqStatus::Stop;
+        process_cycle(&seq, 256, 0, 125_000, &mut vec![]);
+        sink.iter()
+            .filter(|m| m[2] == 100)
+            .map(|m| m[0])
+            .collect::<Vec<u8>>()
+    };
+    // Only the notes of the last two bars are played, and again after a stop
+    assert_eq!(play_loop(), vec![0x90, 0x80, 0x90, 0x80]);
+    assert_eq!(play_loop(), vec![0x90, 0x80, 0x90, 0x80]);
+}
+
+#[test]
+fn test_direction() {
+    use crate::seq::{BaseSeqParams, BaseSeqType};
+
+    let seq = Sequencer::new(120.);
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    seq.add_custom_base_seq(
+        BaseSeqParams {
+            ty: BaseSeqType::Custom,
+            loop_length: 2.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.,
+            note_len_div: 0.,
+            velocity_avg: 0,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        },
+        vec![
+            note(true, 60, 0.25),
+            note(false, 60, 0.5),
+            note(true, 62, 1.),
+            note(false, 62, 1.5),
+        ],
+    )
+    .unwrap();
+    seq.set_direction(Direction::PingPong);
+    seq.params.write().status = SeqStatus::Start;
+
+    // Cycles of 1/8 bar at 120 bpm, over a forward then a backward loop
+    let mut sink = vec![];
+    for cycle in 0..32 {
+        process_cycle(&seq, 256, cycle * 62_500, (cycle + 1) * 62_500, &mut sink);
+    }
+    let notes = sink
+        .iter()
+        .filter(|m| m[1] != 0 && m[2] != 1)
+        .map(|m| (m[0], m[1]))
+        .collect::<Vec<(u8, u8)>>();
+    // The first note on is skipped as the head is synced past it. Backward, the notes are
+    // mirrored as a whole, their note ons landing where their note offs were.
+    assert_eq!(
+        notes,
+        vec![
+            (0x80, 60),
+            (0x90, 62),
+            (0x80, 62),
+            (0x90, 62),
+            (0x80, 62),
+            (0x90, 60),
+            (0x80, 60),
+        ]
+    );
+}
+
+#[test]
+fn test_note_off_velocity() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true, true, true, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.125,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_note_off_velocity(0, Some(64)).unwrap();
+    assert!(seq.set_note_off_velocity(0, Some(128)).is_err());
+    seq.params.write().notes_off_velocity = 20;
+    seq.params.write().status = SeqStatus::Start;
+
+    // Cycles of 1/4 bar at 120 bpm, over a whole loop of a note per bar, then a stop
+    let mut sink = vec![];
+    for cycle in 0..16 {
+        process_cycle(&seq, 256, cycle * 125_000, (cycle + 1) * 125_000, &mut sink);
+    }
+    seq.params.write().status = SeqStatus::Stop;
+    process_cycle(&seq, 256, 16 * 125_000, 17 * 125_000, &mut sink);
+    let off_velocities = |velocity| {
+        sink.iter()
+            .filter(|m| m[0] == 0x80 && m[2] == velocity)
+            .count()
+    };
+    assert_eq!(off_velocities(64), 4);
+    assert_eq!(off_velocities(20), 128);
+    assert_eq!(off_velocities(100), 0);
+}
+
+#[test]
+fn test_regen_on_loop() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true, false, false, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.125,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_regen_on_loop(0, true).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+
+    // Cycles of 1/4 bar at 120 bpm, the step at half the loop being added in the first loop
+    let mut sink = vec![];
+    let mut note_ons = vec![];
+    for cycle in 0..32 {
+        if cycle == 2 {
+            seq.toggle_step(0, 2).unwrap();
+        }
+        process_cycle(&seq, 256, cycle * 125_000, (cycle + 1) * 125_000, &mut sink);
+        let nb_ons = sink.iter().filter(|m| m[0] == 0x90).count();
+        note_ons.push(nb_ons);
+    }
+    // The first note on is skipped as the head is synced past it. The added step only
+    // plays from the second loop on.
+    assert_eq!(note_ons[15], 1);
+    assert_eq!(note_ons[31], 4);
+    assert!(seq.get_base_seq(0).unwrap().pending_buffer.read().is_none());
+}
+
+#[test]
+fn test_idle_regen_on_loop() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![false; 4],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.125,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_regen_on_loop(0, true).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+
+    // The step toggled in the silent first loop is swapped in at its boundary
+    let mut sink = vec![];
+    for cycle in 0..24 {
+        if cycle == 1 {
+            seq.toggle_step(0, 1).unwrap();
+        }
+        process_cycle(&seq, 256, cycle * 125_000, (cycle + 1) * 125_000, &mut sink);
+    }
+    assert_eq!(sink.iter().filter(|m| m[0] == 0x90).count(), 1);
+    assert!(seq.get_base_seq(0).unwrap().pending_buffer.read().is_none());
+}
+
+#[test]
+fn test_pause_resume() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true, true, true, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.125,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.params.write().status = SeqStatus::Start;
+
+    // Cycles of 1/4 bar at 120 bpm, paused for a few cycles right before the note of bar 2
+    let mut sink = vec![];
+    let mut usecs = 0;
+    let mut run = |nb_cycles, sink: &mut Vec<Vec<u8>>| {
+        for _ in 0..nb_cycles {
+            process_cycle(&seq, 256, usecs, usecs + 125_000, sink);
+            usecs += 125_000;
+        }
+    };
+    run(8, &mut sink);
+    seq.params.write().status = SeqStatus::Pause;
+    run(5, &mut vec![]);
+    assert_eq!(seq.internal.read().j_window_time_end, 2.);
+    seq.params.write().status = SeqStatus::Start;
+    run(8, &mut sink);
+
+    // The first note on is skipped as the head is synced past it. The notes of bars 2 and 3
+    // are played on resume, at the start of their cycle.
+    assert_eq!(sink.iter().filter(|m| m[0] == 0x90).count(), 3);
+    assert_eq!(seq.internal.read().j_window_time_end, 4.);
+}
+
+#[test]
+fn test_stop_phase_locked() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    for _ in 0..2 {
+        seq.add_base_seq(BaseSeqParams {
+            ty: BaseSeqType::Steps(StepsBase {
+                pattern: vec![true, false, true, false],
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        })
+        .unwrap();
+    }
+    seq.phase_lock(0, 1, 1.).unwrap();
+    seq.params.write().status = SeqStatus::Start;
+    process_cycle(&seq, 256, 0, 125_000, &mut vec![]);
+    seq.params.write().status = SeqStatus::Stop;
+    process_cycle(&seq, 256, 125_000, 250_000, &mut vec![]);
+
+    // Back to the loop start, the follower keeping its offset
+    assert_eq!(seq.internal.read().j_window_time_end, 0.);
+    assert_eq!(*seq.get_base_seq(0).unwrap().phase.read(), 0.);
+    assert_eq!(*seq.get_base_seq(1).unwrap().phase.read(), 3.);
+}
+
+#[test]
+fn test_sustained_note_off() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![false, false, false, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 1.5,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_wrap_note_off(0, false).unwrap();
+    let off_pos = |seq: &Sequencer| {
+        let base_seq = seq.get_base_seq(0).unwrap();
+        let event_buffer = base_seq.event_buffer.read();
+        event_buffer
+            .iter()
+            .find(|e| !e.is_note_on())
+            .unwrap()
+            .bar_pos
+    };
+    assert_eq!(off_pos(&seq), 4.5);
+    seq.params.write().status = SeqStatus::Start;
+
+    // Cycles of 1/4 bar at 120 bpm over two loops, the note on being at bar 3
+    let mut off_cycles = vec![];
+    for cycle in 0..32 {
+        let mut sink = vec![];
+        process_cycle(&seq, 256, cycle * 125_000, (cycle + 1) * 125_000, &mut sink);
+        if sink.iter().any(|m| m[0] == 0x80) {
+            off_cycles.push(cycle);
+        }
+    }
+    // Sustained into the next loop, without any stray note off at the loop start
+    assert_eq!(off_cycles, vec![18]);
+
+    // Kept past the loop end through the note and loop length changes
+    seq.change_note_len(0, 1.75).unwrap();
+    assert_eq!(off_pos(&seq), 4.75);
+    seq.change_loop_len(0, 4.).unwrap();
+    assert_eq!(off_pos(&seq), 4.75);
+    // The passes wrapping the note offs again are exclusive of it
+    assert!(seq.set_legato(0, true).is_err());
+    assert!(seq.set_explicit_durations(0, true).is_err());
+
+    seq.set_wrap_note_off(0, true).unwrap();
+    assert_eq!(off_pos(&seq), 0.75);
+    seq.set_legato(0, true).unwrap();
+    assert!(seq.set_wrap_note_off(0, false).is_err());
+}
@@ -0,0 +1,182 @@
+use anyhow::{anyhow, bail};
+use rosc::OscType;
+
+/// Parse a whole JSON text. JSON numbers with a fraction or an exponent are parsed as floats,
+/// the others as ints, matching what the OSC args of the commands expect.
+pub fn parse(text: &str) -> anyhow::Result<Json> {
+    let mut parser = JsonParser {
+        chars: text.chars().peekable(),
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.chars.next().is_some() {
+        bail!("Trailing characters after the JSON value.");
+    }
+    Ok(value)
+}
+
+/// Subset of JSON needed for the commands and the config
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Command object to its address and args, as `{ "addr": "/gisele/set_bpm", "args": [140.0] }`
+    pub fn into_command(self) -> anyhow::Result<(String, Vec<OscType>)> {
+        let fields = match self {
+            Json::Object(fields) => fields,
+            _ => bail!("The command should be a JSON object."),
+        };
+        let mut addr = None;
+        let mut args = vec![];
+        for (key, value) in fields {
+            match (key.as_str(), value) {
+                ("addr", Json::String(a)) => addr = Some(a),
+                ("args", Json::Array(values)) => {
+                    args = values
+                        .into_iter()
+                        .map(Json::into_osc_type)
+                        .collect::<anyhow::Result<_>>()?;
+                }
+                (key, _) => bail!("Unexpected command field {key}."),
+            }
+        }
+        Ok((
+            addr.ok_or_else(|| anyhow!("The command addr is missing."))?,
+            args,
+        ))
+    }
+
+    fn into_osc_type(self) -> anyhow::Result<OscType> {
+        Ok(match self {
+            Json::Bool(b) => OscType::Bool(b),
+            Json::Int(i) => OscType::Int(i),
+            Json::Float(f) => OscType::Float(f),
+            Json::String(s) => OscType::String(s),
+            _ => bail!("Command args should be numbers, strings or booleans."),
+        })
+    }
+}
+
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> anyhow::Result<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            c => bail!("Expected '{expected}' in JSON, found {c:?}."),
+        }
+    }
+
+    fn parse_value(&mut self) -> anyhow::Result<Json> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => Ok(Json::String(self.parse_string()?)),
+            Some('-' | '0'..='9') => self.parse_number(),
+            Some(_) => self.parse_literal(),
+            None => bail!("Unexpected end of JSON."),
+        }
+    }
+
+    fn parse_object(&mut self) -> anyhow::Result<Json> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            fields.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Json::Object(fields)),
+                c => bail!("Expected ',' or '}}' in JSON object, found {c:?}."),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> anyhow::Result<Json> {
+        self.expect('[')?;
+        let mut values = vec![];
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&']').is_some() {
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Json::Array(values)),
+                c => bail!("Expected ',' or ']' in JSON array, found {c:?}."),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> anyhow::Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some(c @ ('"' | '\\' | '/')) => s.push(c),
+                    c => bail!("Unsupported JSON escape {c:?}."),
+                },
+                Some(c) => s.push(c),
+                None => bail!("Unterminated JSON string."),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> anyhow::Result<Json> {
+        let mut number = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            number.push(c);
+        }
+        if number.contains(['.', 'e', 'E']) {
+            Ok(Json::Float(number.parse()?))
+        } else {
+            Ok(Json::Int(number.parse()?))
+        }
+    }
+
+    fn parse_literal(&mut self) -> anyhow::Result<Json> {
+        let mut literal = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+            literal.push(c);
+        }
+        match literal.as_str() {
+            "true" => Ok(Json::Bool(true)),
+            "false" => Ok(Json::Bool(false)),
+            "null" => Ok(Json::Null),
+            _ => bail!("Unexpected JSON literal {literal}."),
+        }
+    }
+}
@@ -1,51 +1,209 @@
-use anyhow::Result;
+// The FromPrimitive derive of num-derive 0.3 expands to non-local impls
+#![allow(non_local_definitions)]
+
+use anyhow::{anyhow, bail, Result};
+use grid::grid_process_closure;
 use jack::{Client, ClientOptions};
+use monitor::monitor_process_closure;
 use osc::{osc_process_closure, OSC_PORT};
+use replay::{load_log, replay_process_closure, CommandLog};
 use seq::{EventType, SeqStatus};
-use std::{io, net::UdpSocket, sync::Arc, thread, time::Duration};
+use std::{
+    env, io,
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
 
-use crate::{jackp::jack_process_closure, seq::Sequencer};
+use crate::{
+    config::Config,
+    jackp::{connect_ports_retry, jack_process_closure, Notifications},
+    seq::Sequencer,
+};
 
+mod config;
+mod grid;
+#[cfg(feature = "http")]
+mod http;
 mod jackp;
+mod json;
 mod midi;
+mod monitor;
 mod osc;
+mod replay;
+mod ring;
 mod seq;
 
 const INIT_BPM: f32 = 120.;
 
+/// Set on user input or on SIGINT/SIGTERM, polled by the main thread to shut down
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Only async-signal-safe operations are allowed here, hence the flag
+extern "C" fn request_shutdown(_signal: libc::c_int) {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Command line arguments
+#[derive(Default)]
+struct CliArgs {
+    /// Jack port to which the midi output is connected on startup
+    connect_to: Option<String>,
+    /// Prefix of the OSC addresses, to tell apart several instances
+    addr_prefix: Option<String>,
+    /// JSON file of the initial sequencer state, see [Config]
+    config: Option<String>,
+    /// File to which the received OSC commands are logged, see [replay::CommandLog]
+    log_commands: Option<String>,
+    /// Command log to be replayed on startup, at its recorded timing
+    replay: Option<String>,
+}
+
+impl CliArgs {
+    fn parse() -> Result<Self> {
+        let mut cli_args = CliArgs::default();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--connect-to" => {
+                    cli_args.connect_to = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--connect-to needs a port"))?,
+                    );
+                }
+                "--addr-prefix" => {
+                    cli_args.addr_prefix = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--addr-prefix needs a prefix"))?,
+                    );
+                }
+                "--config" => {
+                    cli_args.config = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--config needs a path"))?,
+                    );
+                }
+                "--log-commands" => {
+                    cli_args.log_commands = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--log-commands needs a path"))?,
+                    );
+                }
+                "--replay" => {
+                    cli_args.replay = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--replay needs a path"))?,
+                    );
+                }
+                _ => bail!("Unknown argument {arg}"),
+            }
+        }
+        Ok(cli_args)
+    }
+}
+
 fn main() -> Result<()> {
+    let cli_args = CliArgs::parse()?;
+    let config = match &cli_args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    // Loaded and created upfront, to fail before the jack client is set up
+    let replayed_commands = cli_args.replay.as_deref().map(load_log).transpose()?;
+    let command_log = cli_args
+        .log_commands
+        .as_deref()
+        .map(CommandLog::create)
+        .transpose()?;
+
     // Set up jack ports
     let (jclient, _) = Client::new("gisele_jack", ClientOptions::NO_START_SERVER)?;
 
-    let midi_out = jclient
-        .register_port("gisele_out", jack::MidiOut::default())
-        .unwrap();
+    let midi_in = jclient.register_port("gisele_in", jack::MidiIn).unwrap();
+    let midi_out = jclient.register_port("gisele_out", jack::MidiOut).unwrap();
+    let midi_out_name = midi_out.name()?;
 
     // Initiate sequencer and build the Jack process
     let seq_arc = Arc::new(Sequencer::new(INIT_BPM));
+    seq_arc.internal.write().sample_rate = jclient.sample_rate() as u32;
+    if let Some(addr_prefix) = cli_args.addr_prefix {
+        seq_arc.set_addr_prefix(addr_prefix)?;
+    }
+    // Set up before the threads start, for the sequencer to come up ready to play
+    config.apply(&seq_arc)?;
     let seq_ref = seq_arc.clone();
-    let jack_process = jack_process_closure(seq_ref, midi_out);
+    let jack_process = jack_process_closure(seq_ref, midi_in, midi_out);
 
     // Start the Jack thread
     let process = jack::ClosureProcessHandler::new(jack_process);
-    let active_client = jclient.activate_async((), process).unwrap();
+    let active_client = jclient.activate_async(Notifications, process).unwrap();
+
+    for target_port in cli_args.connect_to.iter().chain(&config.connect_to) {
+        connect_ports_retry(active_client.as_client(), &midi_out_name, target_port);
+    }
 
     // Start the OSC listening thread
     let udp_socket = UdpSocket::bind(format!("0.0.0.0:{OSC_PORT}"))?;
     // Setting the UDP recv timeout to 1s to allow for gracefull shutdown
     udp_socket.set_read_timeout(Some(Duration::from_secs(1)))?;
-    let osc_process = osc_process_closure(udp_socket, seq_arc.clone());
+    let osc_process = osc_process_closure(udp_socket, seq_arc.clone(), command_log);
     let osc_handler = thread::spawn(osc_process);
 
-    // Graceful shutdown on user input
+    // Start the http listening thread
+    #[cfg(feature = "http")]
+    let http_handler = {
+        let listener = std::net::TcpListener::bind(format!("0.0.0.0:{}", http::HTTP_PORT))?;
+        thread::spawn(http::http_process_closure(listener, seq_arc.clone()))
+    };
+
+    // Start the midi monitor thread
+    let monitor_handler = thread::spawn(monitor_process_closure(seq_arc.clone()));
+
+    // Start the monome grid feedback thread
+    let grid_handler = thread::spawn(grid_process_closure(seq_arc.clone()));
+
+    // Start the replay of a command log, alongside the commands received live
+    let replay_handler = replayed_commands
+        .map(|commands| thread::spawn(replay_process_closure(commands, seq_arc.clone())));
+
+    // Graceful shutdown on user input, Ctrl-C or service stop
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // Safety: the handler only stores to an atomic
+        unsafe {
+            libc::signal(
+                signal,
+                request_shutdown as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
     println!("Press enter/return to quit...");
-    let mut user_input = String::new();
-    io::stdin().read_line(&mut user_input).ok();
+    thread::spawn(|| {
+        // A closed stdin, as for a background service, is not a request to quit
+        let mut user_input = String::new();
+        if let Ok(1..) = io::stdin().read_line(&mut user_input) {
+            SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+        }
+    });
+    while !SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    // The OSC thread notices the status within its socket read timeout
     seq_arc.params.write().status = SeqStatus::Shutdown;
     active_client.deactivate().unwrap();
     println!("Jack process shutdown.");
     println!("Waiting for OSC process...");
     osc_handler.join().unwrap()?;
+    #[cfg(feature = "http")]
+    http_handler.join().unwrap()?;
+    monitor_handler.join().unwrap();
+    grid_handler.join().unwrap()?;
+    if let Some(replay_handler) = replay_handler {
+        replay_handler.join().unwrap();
+    }
 
     Ok(())
 }
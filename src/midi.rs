@@ -1,6 +1,6 @@
 use anyhow::anyhow;
-use rand::Rng;
-use rand_distr::{Distribution, Normal, Uniform};
+use rand::{distributions::WeightedIndex, Rng};
+use rand_distr::{Distribution, Exp, Normal, Uniform};
 use rust_music_theory::{
     note::{Note, Notes, PitchClass},
     scale::{Direction, Mode, Scale, ScaleType},
@@ -9,12 +9,31 @@ use rust_music_theory::{
 use crate::{
     seq::{
         BaseSeq, BaseSeqParams,
-        BaseSeqType::{Euclid, Random},
-        EuclidBase, Event, RandomBase,
+        BaseSeqType::{Euclid, Random, Steps},
+        EuclidBase, Event, GridVelocity, RandomBase, Spacing, StepsBase, VelocityDist,
     },
     EventType,
 };
 
+/// Finest grid of the ascii piano rolls, in columns per bar
+const ASCII_COLS_PER_BAR: f32 = 16.;
+/// Max width of the grid of the ascii piano rolls, in columns
+const ASCII_MAX_COLS: usize = 64;
+
+/// Shortest generated note length, in bars.
+/// This is one tick of the default fixed-point resolution used to sort the EventBuffer.
+const MIN_NOTE_LEN: f32 = 0.001;
+
+/// Markov move weights favouring small steps, by nb of scale degrees, up to a bit more than
+/// an octave of a heptatonic scale. Repeats are kept rarer than steps to keep the line moving.
+pub const DEFAULT_MARKOV_WEIGHTS: [f32; 9] = [1., 4., 3., 2., 1., 0.5, 0.5, 0.25, 0.25];
+
+/// Step of the grid on which Spacing::Grid snaps events, in bars (a sixteenth note)
+const SPACING_GRID_STEP: f32 = 0.25;
+
+/// Step of the grid whose off steps are delayed by swing, in bars (a sixteenth note)
+const SWING_GRID_STEP: f32 = 0.25;
+
 #[derive(Debug, Copy, Clone)]
 pub struct MidiNote {
     pub on_off: bool,
@@ -25,7 +44,11 @@ pub struct MidiNote {
 }
 
 impl MidiNote {
-    pub fn get_raw_note_on_bytes(&self) -> [u8; 3] {
+    /// Note offs are encoded either as such, or as note ons of velocity 0
+    pub fn get_raw_note_on_bytes(&self, note_off_as_velocity_zero: bool) -> [u8; 3] {
+        if !self.on_off && note_off_as_velocity_zero {
+            return [0x90 + (self.channel - 1), self.pitch, 0];
+        }
         [
             (8 + self.on_off as u8) * 16 + (self.channel - 1),
             self.pitch,
@@ -34,10 +57,172 @@ impl MidiNote {
     }
 }
 
+/// Semitones of the degrees of the major scale above its root
+const MAJOR_SCALE_INTERVALS: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Shift a pitch by scale degrees of the major scale of the root, keeping it in the key.
+/// Pitches out of the scale keep their alteration from the degree below.
+pub fn diatonic_shift(pitch: u8, root_pitch: u8, degrees: i32) -> u8 {
+    let rel_pitch = pitch as i32 - root_pitch as i32;
+    let octave = rel_pitch.div_euclid(12);
+    let pitch_class = rel_pitch.rem_euclid(12);
+    let degree = MAJOR_SCALE_INTERVALS
+        .iter()
+        .rposition(|interval| *interval <= pitch_class)
+        .unwrap() as i32;
+    let alteration = pitch_class - MAJOR_SCALE_INTERVALS[degree as usize];
+    let target_degree = degree + degrees;
+    let target_pitch = root_pitch as i32
+        + (octave + target_degree.div_euclid(7)) * 12
+        + MAJOR_SCALE_INTERVALS[target_degree.rem_euclid(7) as usize]
+        + alteration;
+    target_pitch.clamp(0, 127) as u8
+}
+
+/// Mirror a pitch around the pivot, clamped to the midi pitches
+pub fn invert_pitch(pitch: u8, pivot: u8) -> u8 {
+    (2 * pivot as i32 - pitch as i32).clamp(0, 127) as u8
+}
+
+/// Center of the 14 bit pitch bend values, leaving the pitch untouched
+pub const PITCH_BEND_CENTER: u16 = 8192;
+
+/// Range of the pitch bend either way, in semitones. The receiving synths should be set to it.
+pub const PITCH_BEND_RANGE: f32 = 2.;
+
+/// Step between the pitch bend messages of a glide, in bars
+const GLIDE_STEP: f32 = 1. / 64.;
+
+#[derive(Debug, Copy, Clone)]
+pub struct PitchBend {
+    /// Channel, should be 1-16
+    pub channel: u8,
+    /// 14 bits, centered on PITCH_BEND_CENTER
+    pub value: u16,
+}
+
+impl PitchBend {
+    /// Bend of the given nb of semitones, clamped to the pitch bend range
+    pub fn from_semitones(channel: u8, semitones: f32) -> Self {
+        let offset = semitones / PITCH_BEND_RANGE * PITCH_BEND_CENTER as f32;
+        PitchBend {
+            channel,
+            value: (PITCH_BEND_CENTER as f32 + offset)
+                .round()
+                .clamp(0., 16383.) as u16,
+        }
+    }
+
+    pub fn get_raw_bytes(&self) -> [u8; 3] {
+        [
+            0xE0 + (self.channel - 1),
+            (self.value & 0x7F) as u8,
+            (self.value >> 7) as u8,
+        ]
+    }
+}
+
+/// Events of a glide from a pitch to another over the given bars, positioned from 0: a note on
+/// bent step by step up to the target pitch, then released. The bend is centered around the note.
+pub fn glide_events(
+    channel: u8,
+    from_pitch: u8,
+    to_pitch: u8,
+    velocity: u8,
+    bars: f32,
+) -> Vec<Event> {
+    let semitones = to_pitch as f32 - from_pitch as f32;
+    let event = |e_type, bar_pos| Event {
+        e_type,
+        bar_pos,
+        duration: None,
+    };
+    let bend = |semitones| EventType::PitchBend(PitchBend::from_semitones(channel, semitones));
+    let note = |on_off| {
+        EventType::MidiNote(MidiNote {
+            on_off,
+            channel,
+            pitch: from_pitch,
+            velocity,
+        })
+    };
+    let nb_steps = (bars / GLIDE_STEP).ceil().max(1.) as u32;
+    let mut events = vec![event(bend(0.), 0.), event(note(true), 0.)];
+    for step in 1..=nb_steps {
+        let progress = step as f32 / nb_steps as f32;
+        events.push(event(bend(semitones * progress), bars * progress));
+    }
+    events.push(event(note(false), bars));
+    events.push(event(bend(0.), bars));
+    events
+}
+
+/// Control change, channel should be 1-16
+pub fn get_raw_cc_bytes(channel: u8, controller: u8, value: u8) -> [u8; 3] {
+    [0xB0 + (channel - 1), controller, value]
+}
+
 pub fn note_to_midi_pitch(note: &Note) -> u8 {
     (note.octave + 1) * 12 + note.pitch_class.into_u8()
 }
 
+/// Name of a note with its octave, e.g. A4
+pub fn note_name(note: &Note) -> String {
+    format!("{}{}", note.pitch_class, note.octave)
+}
+
+/// Parse a note name with its octave, e.g. A4, C#3 or Eb5
+pub fn parse_note_name(name: &str) -> anyhow::Result<Note> {
+    let octave_idx = name
+        .find(|c: char| c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("Note name {name} is missing its octave"))?;
+    let (pitch_class, octave) = name.split_at(octave_idx);
+    Ok(Note {
+        pitch_class: PitchClass::from_str(pitch_class)
+            .ok_or_else(|| anyhow!("Pitch class {pitch_class} was not recognized"))?,
+        octave: octave.parse()?,
+    })
+}
+
+/// Pitches of the scale of the given mode starting on the root note
+pub fn scale_pitches(root_note: &Note, mode: Mode) -> Vec<u8> {
+    let scale_type = match mode {
+        Mode::HarmonicMinor => ScaleType::HarmonicMinor,
+        Mode::MelodicMinor => ScaleType::MelodicMinor,
+        _ => ScaleType::Diatonic,
+    };
+    Scale::new(
+        scale_type,
+        root_note.pitch_class,
+        root_note.octave,
+        Some(mode),
+        Direction::Ascending,
+    )
+    .unwrap()
+    .notes()
+    .iter()
+    .map(note_to_midi_pitch)
+    .collect()
+}
+
+/// Mode from its code: 0 Ionian to 6 Locrian, 7 harmonic minor and 8 melodic minor
+pub fn mode_from_code(code: u32) -> Option<Mode> {
+    use Mode::*;
+    [
+        Ionian,
+        Dorian,
+        Phrygian,
+        Lydian,
+        Mixolydian,
+        Aeolian,
+        Locrian,
+        HarmonicMinor,
+        MelodicMinor,
+    ]
+    .get(code as usize)
+    .copied()
+}
+
 pub fn midi_pitch_to_note(pitch: u8) -> anyhow::Result<Note> {
     // We only allow midi pitch >= 12 because C_0=12 and rust_music_theory
     // does not allow for negative octaves.
@@ -51,12 +236,20 @@ pub fn midi_pitch_to_note(pitch: u8) -> anyhow::Result<Note> {
 }
 
 pub fn gen_rand_midi_vec(rand_seq: &BaseSeq) -> Vec<Event> {
-    let mut rng = rand::thread_rng();
+    let rng = &mut *rand_seq.rng.write();
     let mut events_buffer = vec![];
 
     let params = rand_seq.params.read();
     if let BaseSeqParams {
-        ty: Random(RandomBase { nb_events }),
+        ty:
+            Random(RandomBase {
+                nb_events,
+                spacing,
+                octaves,
+                octave_scales,
+                markov,
+                velocity_dist,
+            }),
         loop_length,
         root_note,
         note_len_avg,
@@ -64,51 +257,98 @@ pub fn gen_rand_midi_vec(rand_seq: &BaseSeq) -> Vec<Event> {
         velocity_avg,
         velocity_div,
         midi_ch,
+        pitch_pool,
+        vel_len_coupling,
+        wrap_note_off,
+        ..
     } = params.clone()
     {
-        // Harmonic quantization
-        let scale = Scale::new(
-            ScaleType::Diatonic,
-            root_note.pitch_class,
-            root_note.octave,
-            Some(Mode::Ionian),
-            Direction::Ascending,
-        )
-        .unwrap();
-        let scale_notes = scale.notes();
+        // Harmonic quantization, unless the pitches are given, spread over the octave range
+        let pitches = (0..octaves)
+            .flat_map(|o| {
+                let octave_pitches = pitch_pool.clone().unwrap_or_else(|| {
+                    let mode = octave_scales
+                        .iter()
+                        .find(|scale| (scale.octaves.0..=scale.octaves.1).contains(&o))
+                        .map_or(Mode::Ionian, |scale| scale.mode);
+                    scale_pitches(&root_note, mode)
+                });
+                octave_pitches
+                    .into_iter()
+                    .map(move |p| (p as u32 + 12 * o).min(127) as u8)
+            })
+            .collect::<Vec<u8>>();
+        // The Markov moves are counted in scale degrees, hence over the ordered pitches
+        let pitches = if markov.is_some() {
+            let mut pitches = pitches;
+            pitches.sort();
+            pitches.dedup();
+            pitches
+        } else {
+            pitches
+        };
         let velocity_distr = Normal::new(velocity_avg as f32, velocity_div).unwrap();
+        // The velocities are coupled to the note lengths around the center of their distribution
+        let velocity_center = match velocity_dist {
+            VelocityDist::Normal => velocity_avg,
+            VelocityDist::Uniform { min, max } => ((min as u16 + max as u16) / 2) as u8,
+        };
         let note_len_distr = Normal::new(note_len_avg, note_len_div).unwrap();
         let time_incr_distr = Uniform::new(0., loop_length);
+        let poisson_incr_distr = Exp::new(nb_events.max(1) as f32 / loop_length).unwrap();
 
         let mut step_offset = 0.;
+        let mut degree = None;
         for _ in 0..nb_events {
-            let pitch = rng.gen_range(0..scale_notes.len());
-            let velocity = velocity_distr.sample(&mut rng) as u8;
-            let note_len = note_len_distr.sample(&mut rng);
+            let next_degree = match (&markov, degree) {
+                (Some(weights), Some(prev)) => markov_degree(rng, prev, pitches.len(), weights),
+                _ => rng.gen_range(0..pitches.len()),
+            };
+            degree = Some(next_degree);
+            let pitch = pitches[next_degree];
+            let velocity = match velocity_dist {
+                VelocityDist::Normal => velocity_distr.sample(rng) as u8,
+                VelocityDist::Uniform { min, max } => rng.gen_range(min..=max),
+            };
+            // Negative lengths would place the note off before its note on
+            let note_len = coupled_note_len(
+                note_len_distr.sample(rng),
+                velocity,
+                velocity_center,
+                vel_len_coupling,
+            );
 
             let event_midi_on = Event {
                 e_type: EventType::MidiNote(MidiNote {
                     channel: midi_ch,
-                    pitch: note_to_midi_pitch(&scale_notes[pitch]),
+                    pitch,
                     velocity,
                     on_off: true,
                 }),
                 bar_pos: step_offset,
+                duration: None,
             };
             let event_midi_off = Event {
                 e_type: EventType::MidiNote(MidiNote {
                     channel: midi_ch,
-                    pitch: note_to_midi_pitch(&scale_notes[pitch]),
+                    pitch,
                     velocity,
                     on_off: false,
                 }),
-                bar_pos: (step_offset + note_len) % loop_length,
+                bar_pos: off_pos(step_offset + note_len, loop_length, wrap_note_off),
+                duration: None,
             };
 
             events_buffer.push(event_midi_on);
             events_buffer.push(event_midi_off);
-            let time_incr = time_incr_distr.sample(&mut rng);
-            step_offset = (step_offset + time_incr) % loop_length;
+            step_offset = match spacing {
+                Spacing::Uniform => (step_offset + time_incr_distr.sample(rng)) % loop_length,
+                Spacing::Grid => {
+                    let offset = step_offset + time_incr_distr.sample(rng);
+                    ((offset / SPACING_GRID_STEP).round() * SPACING_GRID_STEP) % loop_length
+                }
+                Spacing::Poisson => (step_offset + poisson_incr_distr.sample(rng)) % loop_length,
+            };
         }
     } else {
         eprintln!("Could not insert BaseSeq as its not Random.")
@@ -117,8 +357,554 @@ pub fn gen_rand_midi_vec(rand_seq: &BaseSeq) -> Vec<Event> {
     events_buffer
 }
 
+/// Next scale degree of a Markov chain, the move from the previous one being weighted by its
+/// nb of degrees. Moves further than the weights go are never drawn.
+fn markov_degree(rng: &mut impl Rng, prev: usize, nb_degrees: usize, weights: &[f32]) -> usize {
+    let move_weights =
+        (0..nb_degrees).map(|d| weights.get(prev.abs_diff(d)).copied().unwrap_or(0.));
+    WeightedIndex::new(move_weights).map_or(prev, |distr| distr.sample(rng))
+}
+
+/// Note length scaled by the deviation of the velocity from the average, relative to it,
+/// times the coupling amount. Kept positive.
+fn coupled_note_len(note_len: f32, velocity: u8, velocity_avg: u8, coupling: f32) -> f32 {
+    let deviation = (velocity as f32 - velocity_avg as f32) / velocity_avg.max(1) as f32;
+    (note_len * (1. + coupling * deviation)).max(MIN_NOTE_LEN)
+}
+
+/// Scale the note on velocities by their distance to the nearest step of the grid, relative
+/// to half a step: on the grid they are kept, halfway between two steps they are multiplied
+/// by 1 - strength. Applied after the velocity contour.
+pub fn apply_grid_velocity(events: &mut [Event], grid_velocity: GridVelocity) {
+    let step = 1. / grid_velocity.division as f32;
+    for event in events.iter_mut() {
+        if let EventType::MidiNote(ref mut note @ MidiNote { on_off: true, .. }) = event.e_type {
+            let offset = event.bar_pos - (event.bar_pos / step).round() * step;
+            let distance = offset.abs() / (step / 2.);
+            let velocity = note.velocity as f32 * (1. - grid_velocity.strength * distance);
+            note.velocity = velocity.round().clamp(1., 127.) as u8;
+        }
+    }
+}
+
+/// Multiply the note on velocities by the contour multiplier of their step on the sixteenth grid,
+/// the contour being repeated if shorter than the loop
+pub fn apply_velocity_contour(events: &mut [Event], contour: &[f32]) {
+    for event in events.iter_mut() {
+        if let EventType::MidiNote(ref mut note @ MidiNote { on_off: true, .. }) = event.e_type {
+            let step = (event.bar_pos / SPACING_GRID_STEP) as usize;
+            let velocity = note.velocity as f32 * contour[step % contour.len()];
+            note.velocity = velocity.round().clamp(1., 127.) as u8;
+        }
+    }
+}
+
+/// Position of an event once swung.
+/// Every second step of the swing grid is delayed by the swing amount, a fraction of a step
+/// in [0, 1). The positions in between are stretched so that the event order is preserved,
+/// and no event is moved across a pair of steps, hence across the loop end.
+pub fn swing_pos(bar_pos: f32, swing: f32, loop_length: f32) -> f32 {
+    let pair_len = 2. * SWING_GRID_STEP;
+    let pair_start = (bar_pos / pair_len).floor() * pair_len;
+    if swing == 0. || pair_start + pair_len > loop_length {
+        return bar_pos;
+    }
+    let pos_in_pair = bar_pos - pair_start;
+    let swung_mid = SWING_GRID_STEP * (1. + swing);
+    pair_start
+        + if pos_in_pair < SWING_GRID_STEP {
+            pos_in_pair * swung_mid / SWING_GRID_STEP
+        } else {
+            swung_mid + (pos_in_pair - SWING_GRID_STEP) * (pair_len - swung_mid) / SWING_GRID_STEP
+        }
+}
+
+/// Nearest step of a grid of 1/division bar, once the grid is swung as at playback by
+/// [swing_pos], for captured events to keep the feel of the sequencer.
+/// The pair of swing steps crossing the loop end is swung too, unlike at playback.
+pub fn quantize_with_swing(bar_pos: f32, division: u32, swing: f32) -> f32 {
+    let step = 1. / division as f32;
+    let nearest = (bar_pos / step).round();
+    if swing == 0. {
+        return nearest * step;
+    }
+    // A grid step is moved by less than a swing step, the snapped one is among its neighbours
+    let span = (SWING_GRID_STEP / step).ceil() + 1.;
+    let (first, last) = ((nearest - span).max(0.) as u32, (nearest + span) as u32);
+    (first..=last)
+        .map(|k| swing_pos(k as f32 * step, swing, f32::INFINITY))
+        .min_by(|a, b| (a - bar_pos).abs().total_cmp(&(b - bar_pos).abs()))
+        .unwrap_or(0.)
+}
+
+/// Fold each note off into the duration of its preceding note on of same channel and pitch.
+/// The events need to be sorted by bar_pos. Note offs without a note on are kept as is.
+pub fn events_to_durations(events: Vec<Event>, loop_length: f32) -> Vec<Event> {
+    let mut consumed = vec![false; events.len()];
+    let mut durations: Vec<Option<f32>> = events.iter().map(|e| e.duration).collect();
+    for (i, event) in events.iter().enumerate() {
+        if let EventType::MidiNote(on @ MidiNote { on_off: true, .. }) = event.e_type {
+            // Look for the next matching note off, wrapping around the loop
+            let matching_off = (1..events.len()).map(|k| (i + k) % events.len()).find(|j| {
+                match events[*j].e_type {
+                    EventType::MidiNote(off) => {
+                        !off.on_off
+                            && !consumed[*j]
+                            && off.channel == on.channel
+                            && off.pitch == on.pitch
+                    }
+                    EventType::PitchBend(_) | EventType::_Fill => false,
+                }
+            });
+            if let Some(j) = matching_off {
+                consumed[j] = true;
+                durations[i] = Some((events[j].bar_pos - event.bar_pos).rem_euclid(loop_length));
+            }
+        }
+    }
+    events
+        .into_iter()
+        .zip(durations)
+        .zip(consumed)
+        .filter(|(_, consumed)| !consumed)
+        .map(|((event, duration), _)| Event { duration, ..event })
+        .collect()
+}
+
+/// Length of the note whose note on is at the index, given by its duration or by the next note
+/// off of same channel and pitch, wrapping around the loop. None for the other events.
+pub fn note_len_at(events: &[Event], idx: usize, loop_length: f32) -> Option<f32> {
+    let event = events.get(idx)?;
+    if event.duration.is_some() {
+        return event.duration;
+    }
+    matching_note_off(events, idx)
+        .map(|off| (off.bar_pos - event.bar_pos).rem_euclid(loop_length))
+        .filter(|len| *len > 0.)
+}
+
+/// Length of the note whose note on is at the index when its note off lies past the loop end,
+/// the note sustaining into the next loop. None for the other events.
+pub fn sustained_note_len(events: &[Event], idx: usize, loop_length: f32) -> Option<f32> {
+    let event = events.get(idx)?;
+    matching_note_off(events, idx)
+        .filter(|off| loop_length <= off.bar_pos)
+        .map(|off| off.bar_pos - event.bar_pos)
+}
+
+/// Index of a note on, the position it is mirrored to when read backward, landing where its
+/// note off was, and its length as given by [note_len_at]
+pub type MirroredNote = (usize, f32, Option<f32>);
+
+/// Note ons of the loop read backward, see [MirroredNote].
+/// The note offs are matched in a single pass over the loop, read twice to wrap around it.
+pub fn mirrored_notes(events: &[Event], loop_length: f32) -> Vec<MirroredNote> {
+    let nb_events = events.len();
+    // Index of the next note off of each channel and pitch
+    let mut next_offs = [[None::<usize>; 128]; 16];
+    let mut notes = vec![];
+    for i in (0..2 * nb_events).rev() {
+        let event = &events[i % nb_events];
+        let EventType::MidiNote(note) = event.e_type else {
+            continue;
+        };
+        let next_off = &mut next_offs[(note.channel.wrapping_sub(1) & 0xF) as usize]
+            [(note.pitch & 0x7F) as usize];
+        if !note.on_off {
+            *next_off = Some(i);
+        } else if i < nb_events && event.bar_pos < loop_length {
+            let note_len = event.duration.or_else(|| {
+                next_off
+                    .filter(|j| *j < i + nb_events)
+                    .map(|j| {
+                        (events[j % nb_events].bar_pos - event.bar_pos).rem_euclid(loop_length)
+                    })
+                    .filter(|len| *len > 0.)
+            });
+            let mirrored_pos =
+                (loop_length - event.bar_pos - note_len.unwrap_or(0.)).rem_euclid(loop_length);
+            notes.push((i, mirrored_pos, note_len));
+        }
+    }
+    notes.reverse();
+    notes
+}
+
+/// Next note off of same channel and pitch as the note on at the index, wrapping around the loop
+fn matching_note_off(events: &[Event], idx: usize) -> Option<&Event> {
+    let EventType::MidiNote(on @ MidiNote { on_off: true, .. }) = events.get(idx)?.e_type else {
+        return None;
+    };
+    (1..events.len())
+        .map(|k| &events[(idx + k) % events.len()])
+        .find(|e| match e.e_type {
+            EventType::MidiNote(off) => {
+                !off.on_off && off.channel == on.channel && off.pitch == on.pitch
+            }
+            EventType::PitchBend(_) | EventType::_Fill => false,
+        })
+}
+
+/// Position of a generated note off, wrapped to the loop start or kept past the loop end,
+/// see [BaseSeqParams::wrap_note_off]
+fn off_pos(bar_pos: f32, loop_length: f32, wrap_note_off: bool) -> f32 {
+    if wrap_note_off {
+        bar_pos % loop_length
+    } else {
+        bar_pos
+    }
+}
+
+/// Expand the note on durations into separate note off events, sorted by bar_pos
+pub fn durations_to_events(events: Vec<Event>, loop_length: f32) -> Vec<Event> {
+    let mut expanded = vec![];
+    for event in events {
+        if let (EventType::MidiNote(note), Some(duration)) = (event.e_type.clone(), event.duration)
+        {
+            expanded.push(Event {
+                e_type: EventType::MidiNote(MidiNote {
+                    on_off: false,
+                    ..note
+                }),
+                bar_pos: (event.bar_pos + duration) % loop_length,
+                duration: None,
+            });
+        }
+        expanded.push(Event {
+            duration: None,
+            ..event
+        });
+    }
+    expanded.sort_by_key(Event::sort_key);
+    expanded
+}
+
+/// Position of an event once its loop is rotated by the offset, in [0, loop_length).
+/// The loop is compressed into [offset, loop_length) rather than wrapped, so that the order
+/// of the events is preserved and no event is moved across the loop end.
+pub fn rotate_pos(bar_pos: f32, offset: f32, loop_length: f32) -> f32 {
+    if offset == 0. || bar_pos >= loop_length {
+        return bar_pos;
+    }
+    offset + bar_pos * (loop_length - offset) / loop_length
+}
+
+/// Connect the notes overlapping the next note on of their channel, for legato on mono synths:
+/// their note off is moved right after the next note on, so that the pitch changes without
+/// retriggering. The note off of a repeated pitch is moved right before, as it cannot be tied.
+/// The input events need to be sorted by bar_pos.
+pub fn connect_notes(events: Vec<Event>, loop_length: f32) -> Vec<Event> {
+    let mut events = events_to_durations(events, loop_length);
+    let note_ons: Vec<(usize, MidiNote)> = events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| match e.e_type {
+            EventType::MidiNote(note) if note.on_off => Some((i, note)),
+            _ => None,
+        })
+        .collect();
+    for (k, (i, note)) in note_ons.iter().enumerate() {
+        // Next note on of the channel, wrapping around the loop
+        let Some((j, next_note)) = (1..=note_ons.len())
+            .map(|n| note_ons[(k + n) % note_ons.len()])
+            .find(|(_, next)| next.channel == note.channel)
+        else {
+            continue;
+        };
+        let gap = (events[j].bar_pos - events[*i].bar_pos).rem_euclid(loop_length);
+        let gap = if gap == 0. { loop_length } else { gap };
+        if let Some(ref mut duration) = events[*i].duration {
+            if *duration > gap {
+                *duration = if next_note.pitch == note.pitch {
+                    (gap - MIN_NOTE_LEN).max(MIN_NOTE_LEN)
+                } else {
+                    gap + MIN_NOTE_LEN
+                };
+            }
+        }
+    }
+    durations_to_events(events, loop_length)
+}
+
+/// Set the length of every note to the ratio of the gap to the next note on of its channel,
+/// wrapping around the loop, a note alone on its channel taking the whole loop as gap.
+/// The events need to be sorted by bar_pos. The note ons with a duration are expanded into
+/// separate note offs.
+pub fn apply_gate_ratio(events: Vec<Event>, ratio: f32, loop_length: f32) -> Vec<Event> {
+    let mut events = events_to_durations(events, loop_length);
+    let note_ons: Vec<(f32, u8)> = events
+        .iter()
+        .filter_map(|e| match e.e_type {
+            EventType::MidiNote(note) if note.on_off => Some((e.bar_pos, note.channel)),
+            _ => None,
+        })
+        .collect();
+    for event in &mut events {
+        let EventType::MidiNote(note @ MidiNote { on_off: true, .. }) = event.e_type else {
+            continue;
+        };
+        // Chord notes share their next note on, so the gap is taken to the closest later one
+        let gap = note_ons
+            .iter()
+            .filter(|(_, channel)| *channel == note.channel)
+            .map(|(bar_pos, _)| (bar_pos - event.bar_pos).rem_euclid(loop_length))
+            .filter(|gap| *gap > 0.)
+            .fold(loop_length, f32::min);
+        event.duration = Some((ratio * gap).max(MIN_NOTE_LEN));
+    }
+    durations_to_events(events, loop_length)
+}
+
+/// Snap the event positions to the nearest step of a grid of 1/division bar, swung by the given
+/// swing (see [quantize_with_swing]), wrapping around the loop.
+/// The rounding keeps the order of the events, so note offs stay after their note ons
+/// but notes shorter than a step can be collapsed.
+pub fn quantize_positions(
+    events: Vec<Event>,
+    division: u32,
+    swing: f32,
+    loop_length: f32,
+) -> Vec<Event> {
+    let mut events: Vec<Event> = events
+        .into_iter()
+        .map(|event| Event {
+            bar_pos: quantize_with_swing(event.bar_pos, division, swing) % loop_length,
+            ..event
+        })
+        .collect();
+    // Sorted on the positions only, the sort being stable
+    events.sort_by_key(|e| e.sort_key().0);
+    events
+}
+
+/// Snap the event positions to the ticks of the resolution, see
+/// [crate::seq::SeqParams::ticks_per_bar]. The notes collapsed by the rounding are kept a tick
+/// long, so that their note off stays after their note on. The note offs past the loop end are
+/// left past it, see [BaseSeqParams::wrap_note_off].
+pub fn snap_to_ticks(events: Vec<Event>, ticks_per_bar: u32, loop_length: f32) -> Vec<Event> {
+    let tick = 1. / ticks_per_bar as f32;
+    let snap = |bar_pos: f32| (bar_pos * ticks_per_bar as f32).round() / ticks_per_bar as f32;
+    let mut positions: Vec<f32> = events.iter().map(|e| snap(e.bar_pos)).collect();
+    // Each note on is paired with the next matching note off, as in [events_to_durations]
+    let mut consumed = vec![false; events.len()];
+    for (i, event) in events.iter().enumerate() {
+        let EventType::MidiNote(on @ MidiNote { on_off: true, .. }) = event.e_type else {
+            continue;
+        };
+        if event.duration.is_some() {
+            continue;
+        }
+        let matching_off = (i + 1..events.len()).find(|j| match events[*j].e_type {
+            EventType::MidiNote(off) => {
+                !off.on_off && !consumed[*j] && off.channel == on.channel && off.pitch == on.pitch
+            }
+            EventType::PitchBend(_) | EventType::_Fill => false,
+        });
+        if let Some(j) = matching_off {
+            consumed[j] = true;
+            positions[j] = positions[j].max(positions[i] + tick);
+        }
+    }
+    let mut events: Vec<Event> = events
+        .into_iter()
+        .zip(positions)
+        .map(|(event, bar_pos)| Event {
+            bar_pos: if event.bar_pos < loop_length {
+                bar_pos % loop_length
+            } else {
+                bar_pos
+            },
+            duration: event.duration.map(|duration| snap(duration).max(tick)),
+            ..event
+        })
+        .collect();
+    events.sort_by_key(Event::sort_key);
+    events
+}
+
+/// Remove the silence before the first note on, shifting the events so that it lands at the
+/// loop start, and/or after the end of the last note, shortening the loop to it.
+/// Returns the events, with the note ons with a duration expanded into separate note offs,
+/// along with the new loop length. Events without any note on are left as is.
+pub fn trim_silence(
+    events: Vec<Event>,
+    loop_length: f32,
+    leading: bool,
+    trailing: bool,
+) -> (Vec<Event>, f32) {
+    let events = events_to_durations(events, loop_length);
+    let Some(first_on) = events
+        .iter()
+        .filter(|e| e.is_note_on())
+        .map(|e| e.bar_pos)
+        .min_by(f32::total_cmp)
+    else {
+        return (durations_to_events(events, loop_length), loop_length);
+    };
+    let shift = if leading { first_on } else { 0. };
+    let events: Vec<Event> = events
+        .into_iter()
+        .map(|event| Event {
+            bar_pos: (event.bar_pos - shift).rem_euclid(loop_length),
+            ..event
+        })
+        .collect();
+    // Notes wrapping around the loop end keep the whole loop
+    let last_end = events
+        .iter()
+        .map(|e| e.bar_pos + e.duration.unwrap_or(0.))
+        .fold(0., f32::max);
+    let trimmed_len = if trailing && 0. < last_end {
+        last_end.min(loop_length)
+    } else {
+        loop_length
+    };
+    (durations_to_events(events, trimmed_len), trimmed_len)
+}
+
+/// Stagger the coincident note ons by the spread in bars each, from the lowest pitch up,
+/// or from the highest down with a negative spread. The notes keep their lengths.
+/// The note ons with a duration are expanded into separate note offs.
+pub fn strum_chords(events: Vec<Event>, spread: f32, loop_length: f32) -> Vec<Event> {
+    let mut events = events_to_durations(events, loop_length);
+    let mut chords: Vec<(u32, Vec<(u8, usize)>)> = vec![];
+    for (idx, event) in events.iter().enumerate() {
+        if let EventType::MidiNote(MidiNote {
+            on_off: true,
+            pitch,
+            ..
+        }) = event.e_type
+        {
+            let tick = event.sort_key().0;
+            match chords.iter_mut().find(|(t, _)| *t == tick) {
+                Some((_, notes)) => notes.push((pitch, idx)),
+                None => chords.push((tick, vec![(pitch, idx)])),
+            }
+        }
+    }
+    for (_, mut notes) in chords {
+        notes.sort_by_key(|(pitch, _)| *pitch);
+        if spread < 0. {
+            notes.reverse();
+        }
+        for (k, (_, idx)) in notes.into_iter().enumerate() {
+            let event = &mut events[idx];
+            event.bar_pos = (event.bar_pos + k as f32 * spread.abs()) % loop_length;
+        }
+    }
+    durations_to_events(events, loop_length)
+}
+
+/// Add a copy of every note, shifted by the interval in semitones and clamped to the midi pitches.
+/// The events need to be sorted by bar_pos. The note ons with a duration are expanded into
+/// separate note offs.
+pub fn double_notes(events: Vec<Event>, interval: i32, loop_length: f32) -> Vec<Event> {
+    let events = events_to_durations(events, loop_length);
+    let doubled: Vec<Event> = events
+        .iter()
+        .filter_map(|event| match event.e_type {
+            EventType::MidiNote(note @ MidiNote { on_off: true, .. }) => Some(Event {
+                e_type: EventType::MidiNote(MidiNote {
+                    pitch: (note.pitch as i32 + interval).clamp(0, 127) as u8,
+                    ..note
+                }),
+                ..event.clone()
+            }),
+            _ => None,
+        })
+        .collect();
+    durations_to_events(events.into_iter().chain(doubled).collect(), loop_length)
+}
+
+/// Remove the notes whose note on has the channel, pitch and bar_pos of a previous one, along with
+/// their note offs, as they would retrigger the same note.
+/// The note ons with a duration are expanded into separate note offs.
+pub fn dedup_notes(events: Vec<Event>, loop_length: f32) -> Vec<Event> {
+    let mut note_ons = vec![];
+    let events = events_to_durations(events, loop_length)
+        .into_iter()
+        .filter(|event| match event.e_type {
+            EventType::MidiNote(note @ MidiNote { on_off: true, .. }) => {
+                let key = (note.channel, note.pitch, event.bar_pos);
+                let is_new = !note_ons.contains(&key);
+                note_ons.push(key);
+                is_new
+            }
+            _ => true,
+        })
+        .collect();
+    durations_to_events(events, loop_length)
+}
+
+/// Keep only the notes whose note on falls in an active step of the mask, along with their note offs.
+/// The steps divide the loop evenly, whether their length is a whole nb of sixteenths or not.
+/// The note ons with a duration are expanded into separate note offs.
+pub fn apply_rhythm_mask(events: Vec<Event>, mask: &[u8], loop_length: f32) -> Vec<Event> {
+    let step_len = loop_length / mask.len() as f32;
+    let events = events_to_durations(events, loop_length)
+        .into_iter()
+        .filter(|event| {
+            !matches!(
+                event.e_type,
+                EventType::MidiNote(MidiNote { on_off: true, .. })
+            ) || mask[((event.bar_pos / step_len) as usize).min(mask.len() - 1)] == 1
+        })
+        .collect();
+    durations_to_events(events, loop_length)
+}
+
+/// Render the notes as an ascii piano roll, one row per pitch from the highest down and one
+/// column per grid step: `x` for the note ons, `-` for the held notes and `.` for the rests.
+/// The grid is the finest of 1/16, 1/8... bar keeping the roll within its max width.
+pub fn ascii_piano_roll(events: Vec<Event>, loop_length: f32) -> String {
+    let mut cols_per_bar = ASCII_COLS_PER_BAR;
+    while (ASCII_MAX_COLS as f32) < loop_length * cols_per_bar {
+        cols_per_bar /= 2.;
+    }
+    let nb_cols = ((loop_length * cols_per_bar).ceil() as usize).clamp(1, ASCII_MAX_COLS);
+    let col = |bar_pos: f32| ((bar_pos * cols_per_bar) as usize).min(nb_cols - 1);
+
+    let mut rows: Vec<(u8, Vec<u8>)> = vec![];
+    for event in events_to_durations(events, loop_length) {
+        if let EventType::MidiNote(MidiNote {
+            on_off: true,
+            pitch,
+            ..
+        }) = event.e_type
+        {
+            let row_idx = match rows.iter().position(|(p, _)| *p == pitch) {
+                Some(row_idx) => row_idx,
+                None => {
+                    rows.push((pitch, vec![b'.'; nb_cols]));
+                    rows.len() - 1
+                }
+            };
+            let row = &mut rows[row_idx].1;
+            let start = col(event.bar_pos);
+            let end =
+                ((event.bar_pos + event.duration.unwrap_or(0.)) * cols_per_bar).ceil() as usize;
+            // Held notes wrap around the loop end, without covering the other note ons
+            for c in (start + 1..end).take(nb_cols - 1) {
+                if row[c % nb_cols] == b'.' {
+                    row[c % nb_cols] = b'-';
+                }
+            }
+            row[start] = b'x';
+        }
+    }
+    rows.sort_by_key(|(pitch, _)| u8::MAX - pitch);
+    rows.into_iter()
+        .map(|(pitch, row)| {
+            let name = midi_pitch_to_note(pitch)
+                .map(|note| note_name(&note))
+                .unwrap_or_default();
+            format!("{name:<4}|{}|\n", String::from_utf8_lossy(&row))
+        })
+        .collect()
+}
+
 /// After http://cgm.cs.mcgill.ca/~godfried/publications/banff.pdf
-fn gen_euclid(pulses: u32, steps: u32) -> anyhow::Result<Vec<u8>> {
+pub fn gen_euclid(pulses: u32, steps: u32) -> anyhow::Result<Vec<u8>> {
     if steps < pulses {
         anyhow::bail!("Steps should be less than pulses.")
     }
@@ -152,11 +938,32 @@ fn gen_euclid(pulses: u32, steps: u32) -> anyhow::Result<Vec<u8>> {
 }
 
 pub fn gen_euclid_midi_vec(euclid_seq: &BaseSeq) -> anyhow::Result<Vec<Event>> {
+    let ty = euclid_seq.params.read().ty.clone();
+    if let Euclid(EuclidBase { pulses, steps }) = ty {
+        Ok(gen_rhythm_midi_vec(euclid_seq, &gen_euclid(pulses, steps)?))
+    } else {
+        eprintln!("Could not insert BaseSeq as its not Euclidean.");
+        Ok(vec![])
+    }
+}
+
+pub fn gen_step_midi_vec(step_seq: &BaseSeq) -> Vec<Event> {
+    let ty = step_seq.params.read().ty.clone();
+    if let Steps(StepsBase { pattern }) = ty {
+        let rhythm = pattern.iter().map(|s| *s as u8).collect::<Vec<u8>>();
+        gen_rhythm_midi_vec(step_seq, &rhythm)
+    } else {
+        eprintln!("Could not insert BaseSeq as its not a step sequence.");
+        vec![]
+    }
+}
+
+/// Generate the events of a rhythm given as a list of steps, 1 being a note and 0 a rest.
+/// The steps are spread evenly over the loop, on the root note.
+fn gen_rhythm_midi_vec(base_seq: &BaseSeq, rhythm: &[u8]) -> Vec<Event> {
     let mut events_buffer = vec![];
 
-    let params = euclid_seq.params.read();
-    if let BaseSeqParams {
-        ty: Euclid(EuclidBase { pulses, steps }),
+    let BaseSeqParams {
         root_note,
         note_len_avg,
         note_len_div,
@@ -164,59 +971,65 @@ pub fn gen_euclid_midi_vec(euclid_seq: &BaseSeq) -> anyhow::Result<Vec<Event>> {
         velocity_div,
         midi_ch,
         loop_length,
-    } = params.clone()
-    {
-        if loop_length % steps as f32 != 0. {
-            eprintln!("Could not generate euclidean rhythm for indivisible loop-length.");
-            return Ok(events_buffer);
-        }
+        vel_len_coupling,
+        wrap_note_off,
+        ..
+    } = base_seq.params.read().clone();
 
-        let mut rng = rand::thread_rng();
-        let velocity_distr = Normal::new(velocity_avg as f32, velocity_div).unwrap();
-        let note_len_distr = Normal::new(note_len_avg, note_len_div).unwrap();
+    if rhythm.is_empty() || loop_length % rhythm.len() as f32 != 0. {
+        eprintln!("Could not generate rhythm for indivisible loop-length.");
+        return events_buffer;
+    }
 
-        let euclid_step_len_bar = loop_length / (steps as f32);
-        let euclid_rhythm = gen_euclid(pulses, steps)?;
+    let rng = &mut *base_seq.rng.write();
+    let velocity_distr = Normal::new(velocity_avg as f32, velocity_div).unwrap();
+    let note_len_distr = Normal::new(note_len_avg, note_len_div).unwrap();
 
-        let pitch = note_to_midi_pitch(&root_note);
+    let step_len_bar = loop_length / (rhythm.len() as f32);
 
-        let mut time_offset = 0.;
-        for i in euclid_rhythm {
-            let velocity = velocity_distr.sample(&mut rng) as u8;
-            let note_len = note_len_distr.sample(&mut rng);
+    let pitch = note_to_midi_pitch(&root_note);
 
-            let event_midi_on = Event {
-                e_type: EventType::MidiNote(MidiNote {
-                    channel: midi_ch,
-                    pitch,
-                    velocity,
-                    on_off: true,
-                }),
-                bar_pos: time_offset,
-            };
-            let event_midi_off = Event {
-                e_type: EventType::MidiNote(MidiNote {
-                    channel: midi_ch,
-                    pitch,
-                    velocity,
-                    on_off: false,
-                }),
-                bar_pos: (time_offset + note_len) % loop_length,
-            };
+    let mut time_offset = 0.;
+    for i in rhythm {
+        let velocity = velocity_distr.sample(rng) as u8;
+        let note_len = coupled_note_len(
+            note_len_distr.sample(rng),
+            velocity,
+            velocity_avg,
+            vel_len_coupling,
+        );
 
-            time_offset += euclid_step_len_bar;
+        let event_midi_on = Event {
+            e_type: EventType::MidiNote(MidiNote {
+                channel: midi_ch,
+                pitch,
+                velocity,
+                on_off: true,
+            }),
+            bar_pos: time_offset,
+            duration: None,
+        };
+        let event_midi_off = Event {
+            e_type: EventType::MidiNote(MidiNote {
+                channel: midi_ch,
+                pitch,
+                velocity,
+                on_off: false,
+            }),
+            bar_pos: off_pos(time_offset + note_len, loop_length, wrap_note_off),
+            duration: None,
+        };
 
-            if i == 0 {
-                continue;
-            }
+        time_offset += step_len_bar;
 
-            events_buffer.push(event_midi_on);
-            events_buffer.push(event_midi_off);
+        if *i == 0 {
+            continue;
         }
-    } else {
-        eprintln!("Could not insert BaseSeq as its not Euclidean.")
+
+        events_buffer.push(event_midi_on);
+        events_buffer.push(event_midi_off);
     }
-    Ok(events_buffer)
+    events_buffer
 }
 
 #[test]
@@ -289,3 +1102,681 @@ fn test_midi_pitch_to_note() {
     assert_eq!(b6.octave, 6);
     assert_eq!(b6.pitch_class, PitchClass::B);
 }
+
+#[test]
+fn test_note_off_after_note_on() {
+    let loop_length = 16.;
+    let mut params = BaseSeqParams {
+        ty: Random(RandomBase {
+            nb_events: 500,
+            spacing: Spacing::Uniform,
+            octaves: 1,
+            octave_scales: vec![],
+            markov: None,
+            velocity_dist: VelocityDist::Normal,
+        }),
+        loop_length,
+        root_note: midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.01,
+        note_len_div: 1.,
+        velocity_avg: 100,
+        velocity_div: 10.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    };
+    let assert_offs_after_ons = |events: Vec<Event>| {
+        assert!(!events.is_empty());
+        for pair in events.chunks(2) {
+            let len = (pair[1].bar_pos - pair[0].bar_pos).rem_euclid(loop_length);
+            assert!(
+                len > 0.,
+                "Note off at {} precedes its note on",
+                pair[1].bar_pos
+            );
+        }
+    };
+
+    let rand_seq = BaseSeq::new_fill(
+        params.clone(),
+        0,
+        0,
+        crate::seq::DEFAULT_TICKS_PER_BAR,
+        &crate::seq::SeqInternal::new(),
+    )
+    .unwrap();
+    assert_offs_after_ons(gen_rand_midi_vec(&rand_seq));
+
+    params.ty = Euclid(EuclidBase {
+        pulses: 13,
+        steps: 16,
+    });
+    let euclid_seq = BaseSeq::new_fill(
+        params,
+        1,
+        0,
+        crate::seq::DEFAULT_TICKS_PER_BAR,
+        &crate::seq::SeqInternal::new(),
+    )
+    .unwrap();
+    assert_offs_after_ons(gen_euclid_midi_vec(&euclid_seq).unwrap());
+}
+
+#[test]
+fn test_octave_scales() {
+    let params = BaseSeqParams {
+        ty: Random(RandomBase {
+            nb_events: 500,
+            spacing: Spacing::Uniform,
+            octaves: 2,
+            octave_scales: vec![crate::seq::OctaveScale {
+                octaves: (1, 1),
+                mode: mode_from_code(2).unwrap(),
+            }],
+            markov: None,
+            velocity_dist: VelocityDist::Normal,
+        }),
+        loop_length: 4.,
+        root_note: midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    };
+    let rand_seq = BaseSeq::new_fill(
+        params,
+        0,
+        0,
+        crate::seq::DEFAULT_TICKS_PER_BAR,
+        &crate::seq::SeqInternal::new(),
+    )
+    .unwrap();
+    let pitches = gen_rand_midi_vec(&rand_seq)
+        .iter()
+        .filter_map(|e| match e.e_type {
+            EventType::MidiNote(MidiNote { pitch, .. }) => Some(pitch),
+            _ => None,
+        })
+        .collect::<Vec<u8>>();
+    let major = [0, 2, 4, 5, 7, 9, 11];
+    let phrygian = [0, 1, 3, 5, 7, 8, 10];
+    assert!(pitches.iter().all(|p| match p {
+        60..=71 => major.contains(&(p - 60)),
+        _ => phrygian.contains(&((p - 72) % 12)),
+    }));
+    // The flat second only exists in the upper octave
+    assert!(pitches.contains(&73));
+    assert!(mode_from_code(9).is_none());
+}
+
+#[test]
+fn test_durations_round_trip() {
+    let note = |on_off, pitch| {
+        EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        })
+    };
+    let event = |e_type, bar_pos| Event {
+        e_type,
+        bar_pos,
+        duration: None,
+    };
+    // The second note wraps around the loop end
+    let events = vec![
+        event(note(false, 62), 1.),
+        event(note(true, 60), 2.),
+        event(note(false, 60), 3.5),
+        event(note(true, 62), 15.),
+    ];
+
+    let folded = events_to_durations(events.clone(), 16.);
+    assert_eq!(folded.len(), 2);
+    assert_eq!(folded[0].bar_pos, 2.);
+    assert_eq!(folded[0].duration, Some(1.5));
+    assert_eq!(folded[1].bar_pos, 15.);
+    assert_eq!(folded[1].duration, Some(2.));
+
+    let expanded = durations_to_events(folded, 16.);
+    assert_eq!(expanded.len(), events.len());
+    for (e, expected) in expanded.iter().zip(events) {
+        assert_eq!(e.bar_pos, expected.bar_pos);
+        assert_eq!(e.duration, None);
+        assert!(matches!(
+            (&e.e_type, &expected.e_type),
+            (EventType::MidiNote(a), EventType::MidiNote(b)) if a.pitch == b.pitch && a.on_off == b.on_off
+        ));
+    }
+}
+
+#[test]
+fn test_mirrored_notes() {
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let events = vec![
+        note(false, 62, 0.5),
+        note(true, 60, 1.),
+        note(true, 60, 1.5),
+        note(false, 60, 2.),
+        note(true, 62, 3.),
+        note(true, 64, 3.5),
+    ];
+    // Each note on is matched to the next note off, wrapping around the loop
+    let notes = mirrored_notes(&events, 4.);
+    assert_eq!(
+        notes,
+        vec![
+            (1, 2., Some(1.)),
+            (2, 2., Some(0.5)),
+            (4, 3.5, Some(1.5)),
+            (5, 0.5, None),
+        ]
+    );
+    for (idx, _, note_len) in notes {
+        assert_eq!(note_len, note_len_at(&events, idx, 4.));
+    }
+}
+
+#[test]
+fn test_raw_note_bytes() {
+    let mut note = MidiNote {
+        on_off: true,
+        channel: 3,
+        pitch: 64,
+        velocity: 90,
+    };
+    assert_eq!(note.get_raw_note_on_bytes(false), [0x92, 64, 90]);
+    assert_eq!(note.get_raw_note_on_bytes(true), [0x92, 64, 90]);
+    note.on_off = false;
+    assert_eq!(note.get_raw_note_on_bytes(false), [0x82, 64, 90]);
+    assert_eq!(note.get_raw_note_on_bytes(true), [0x92, 64, 0]);
+}
+
+#[test]
+fn test_swing_pos() {
+    assert_eq!(swing_pos(0.25, 0., 16.), 0.25);
+    // Off steps are delayed, on steps are left in place
+    assert_eq!(swing_pos(0., 0.5, 16.), 0.);
+    assert_eq!(swing_pos(0.25, 0.5, 16.), 0.375);
+    assert_eq!(swing_pos(0.5, 0.5, 16.), 0.5);
+    assert_eq!(swing_pos(15.75, 0.5, 16.), 15.875);
+    // The order is preserved and positions stay in the loop
+    let mut last_pos = -1.;
+    for i in 0..1600 {
+        let pos = swing_pos(i as f32 / 100., 0.9, 16.);
+        assert!(last_pos < pos && pos < 16.);
+        last_pos = pos;
+    }
+}
+
+#[test]
+fn test_quantize_positions() {
+    let event = |bar_pos| Event {
+        e_type: EventType::_Fill,
+        bar_pos,
+        duration: None,
+    };
+    let events = vec![
+        event(0.02),
+        event(0.1),
+        event(0.47),
+        event(0.97),
+        event(0.3),
+    ];
+    let positions: Vec<f32> = quantize_positions(events, 16, 0., 1.)
+        .iter()
+        .map(|e| e.bar_pos)
+        .collect();
+    assert_eq!(positions, vec![0., 0., 0.125, 0.3125, 0.5]);
+}
+
+#[test]
+fn test_snap_to_ticks() {
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let positions = |events: &[Event]| -> Vec<(u8, bool, f32)> {
+        events
+            .iter()
+            .map(|e| match e.e_type {
+                EventType::MidiNote(n) => (n.pitch, n.on_off, e.bar_pos),
+                EventType::PitchBend(_) | EventType::_Fill => unreachable!(),
+            })
+            .collect()
+    };
+    let events = vec![
+        note(true, 60, 0.5),
+        note(true, 62, 0.503),
+        note(false, 60, 0.504),
+        note(false, 62, 0.8),
+        note(true, 64, 3.999),
+        note(false, 64, 3.9995),
+    ];
+    // The note of pitch 60 collapsed onto a single tick is kept a tick long, its note on first
+    assert_eq!(
+        positions(&snap_to_ticks(events, 8, 4.)),
+        vec![
+            (64, true, 0.),
+            (64, false, 0.125),
+            (60, true, 0.5),
+            (62, true, 0.5),
+            (60, false, 0.625),
+            (62, false, 0.75),
+        ]
+    );
+}
+
+#[test]
+fn test_quantize_with_swing() {
+    // The off sixteenths are delayed by half a sixteenth
+    assert_eq!(quantize_with_swing(0.3, 4, 0.), 0.25);
+    assert_eq!(quantize_with_swing(0.3, 4, 0.5), 0.375);
+    assert_eq!(quantize_with_swing(0.2, 4, 0.5), 0.375);
+    assert_eq!(quantize_with_swing(0.45, 4, 0.5), 0.5);
+    assert_eq!(quantize_with_swing(0.1, 4, 0.5), 0.);
+    assert_eq!(quantize_with_swing(1.4, 4, 0.5), 1.375);
+    // The finer steps are stretched in between, as at playback
+    assert_eq!(quantize_with_swing(0.08, 16, 0.5), 0.09375);
+    assert_eq!(quantize_with_swing(0.3, 16, 0.5), 0.28125);
+}
+
+#[test]
+fn test_off_before_on() {
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let on_offs = |events: &[Event]| -> Vec<(u8, bool)> {
+        events
+            .iter()
+            .map(|e| match e.e_type {
+                EventType::MidiNote(n) => (n.pitch, n.on_off),
+                EventType::PitchBend(_) | EventType::_Fill => unreachable!(),
+            })
+            .collect()
+    };
+    let mut events = vec![
+        note(true, 60, 0.),
+        note(true, 62, 0.5),
+        note(false, 60, 0.5),
+        note(false, 62, 1.),
+    ];
+    events.sort_by_key(Event::sort_key);
+    assert_eq!(
+        on_offs(&events),
+        vec![(60, true), (60, false), (62, true), (62, false)]
+    );
+}
+
+#[test]
+fn test_velocity_contour() {
+    let mut events: Vec<Event> = [0., 0.25, 0.5, 1.]
+        .iter()
+        .map(|bar_pos| Event {
+            e_type: EventType::MidiNote(MidiNote {
+                on_off: true,
+                channel: 1,
+                pitch: 60,
+                velocity: 60,
+            }),
+            bar_pos: *bar_pos,
+            duration: None,
+        })
+        .collect();
+    apply_velocity_contour(&mut events, &[1.5, 0.5, 0.]);
+    let velocities: Vec<u8> = events
+        .iter()
+        .map(|e| match e.e_type {
+            EventType::MidiNote(n) => n.velocity,
+            EventType::PitchBend(_) | EventType::_Fill => unreachable!(),
+        })
+        .collect();
+    assert_eq!(velocities, vec![90, 30, 1, 30]);
+}
+
+#[test]
+fn test_diatonic_shift() {
+    // A third up in C major
+    let third_up = |pitch| diatonic_shift(pitch, 60, 2);
+    assert_eq!(third_up(60), 64);
+    assert_eq!(third_up(64), 67);
+    assert_eq!(third_up(69), 72);
+    assert_eq!(third_up(71), 74);
+    assert_eq!(third_up(61), 65);
+    // In D major, below the root and down
+    assert_eq!(diatonic_shift(61, 62, 2), 64);
+    assert_eq!(diatonic_shift(62, 62, -2), 59);
+    assert_eq!(diatonic_shift(127, 60, 7), 127);
+}
+
+#[test]
+fn test_invert_pitch() {
+    assert_eq!(invert_pitch(64, 60), 56);
+    assert_eq!(invert_pitch(60, 60), 60);
+    // Inverting twice around the same pivot is the identity, unless clamped
+    for pitch in 0..128 {
+        assert_eq!(invert_pitch(invert_pitch(pitch, 64), 64), pitch.max(1));
+    }
+    assert_eq!(invert_pitch(invert_pitch(10, 100), 100), 73);
+}
+
+#[test]
+fn test_rhythm_mask() {
+    let note = |on_off, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch: 60,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let events: Vec<Event> = (0..5)
+        .flat_map(|i| [note(true, i as f32), note(false, i as f32 + 0.5)])
+        .collect();
+    // Steps of 5/3 bars, the notes at 2 and 3 fall in the inactive one
+    let masked = apply_rhythm_mask(events, &gen_euclid(2, 3).unwrap(), 5.);
+    let positions: Vec<f32> = masked.iter().map(|e| e.bar_pos).collect();
+    assert_eq!(gen_euclid(2, 3).unwrap(), vec![1, 0, 1]);
+    assert_eq!(positions, vec![0., 0.5, 1., 1.5, 4., 4.5]);
+}
+
+#[test]
+fn test_strum_chords() {
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let triad = vec![
+        note(true, 64, 1.),
+        note(true, 60, 1.),
+        note(true, 67, 1.),
+        note(false, 60, 1.5),
+        note(false, 64, 1.5),
+        note(false, 67, 1.5),
+    ];
+    let strummed = |spread| {
+        strum_chords(triad.clone(), spread, 4.)
+            .iter()
+            .filter_map(|e| match e.e_type {
+                EventType::MidiNote(n) => Some((n.on_off, n.pitch, e.bar_pos)),
+                _ => None,
+            })
+            .collect::<Vec<(bool, u8, f32)>>()
+    };
+    assert_eq!(
+        strummed(0.125),
+        vec![
+            (true, 60, 1.),
+            (true, 64, 1.125),
+            (true, 67, 1.25),
+            (false, 60, 1.5),
+            (false, 64, 1.625),
+            (false, 67, 1.75),
+        ]
+    );
+    assert_eq!(
+        strummed(-0.125),
+        vec![
+            (true, 67, 1.),
+            (true, 64, 1.125),
+            (true, 60, 1.25),
+            (false, 67, 1.5),
+            (false, 64, 1.625),
+            (false, 60, 1.75),
+        ]
+    );
+}
+
+#[test]
+fn test_double_notes() {
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let events = vec![
+        note(true, 60, 0.),
+        note(false, 60, 0.5),
+        note(true, 120, 1.),
+        note(false, 120, 1.5),
+    ];
+    let doubled = double_notes(events, 12, 4.);
+    let pitches = |on_off| -> Vec<u8> {
+        doubled
+            .iter()
+            .filter_map(|e| match e.e_type {
+                EventType::MidiNote(n) if n.on_off == on_off => Some(n.pitch),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(doubled.len(), 8);
+    assert_eq!(pitches(true), vec![60, 72, 120, 127]);
+    assert_eq!(pitches(false), vec![60, 72, 120, 127]);
+}
+
+#[test]
+fn test_markov_degree() {
+    let mut rng = rand::thread_rng();
+    for _ in 0..100 {
+        let degree = markov_degree(&mut rng, 3, 10, &[0., 1., 1.]);
+        assert!([1, 2, 4, 5].contains(&degree));
+    }
+    // No reachable degree, the pitch is repeated
+    assert_eq!(markov_degree(&mut rng, 0, 1, &[0., 1.]), 0);
+}
+
+#[test]
+fn test_grid_velocity() {
+    let note_on = |bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off: true,
+            channel: 1,
+            pitch: 60,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let velocities = |strength| {
+        let mut events = [note_on(0.25), note_on(0.375), note_on(0.4375)];
+        apply_grid_velocity(
+            &mut events,
+            GridVelocity {
+                division: 4,
+                strength,
+            },
+        );
+        events
+            .iter()
+            .map(|e| match e.e_type {
+                EventType::MidiNote(n) => n.velocity,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<u8>>()
+    };
+    assert_eq!(velocities(0.5), vec![100, 50, 75]);
+    assert_eq!(velocities(-1.), vec![100, 127, 127]);
+}
+
+#[test]
+fn test_coupled_note_len() {
+    assert_eq!(coupled_note_len(0.5, 100, 100, 1.), 0.5);
+    assert_eq!(coupled_note_len(0.5, 120, 100, 1.), 0.6);
+    assert_eq!(coupled_note_len(0.5, 120, 100, -0.5), 0.45);
+    assert_eq!(coupled_note_len(0.5, 120, 100, -10.), MIN_NOTE_LEN);
+}
+
+#[test]
+fn test_gate_ratio() {
+    let note = |on_off, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch: 60,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let events = vec![
+        note(true, 0.),
+        note(false, 0.1),
+        note(true, 1.),
+        note(false, 1.1),
+    ];
+    // The last note gap wraps around to the first note on
+    let gated = events_to_durations(apply_gate_ratio(events, 0.5, 4.), 4.);
+    let durations: Vec<Option<f32>> = gated.iter().map(|e| e.duration).collect();
+    assert_eq!(durations, vec![Some(0.5), Some(1.5)]);
+}
+
+#[test]
+fn test_note_name() {
+    assert_eq!(note_name(&midi_pitch_to_note(69).unwrap()), "A4");
+    assert_eq!(note_name(&midi_pitch_to_note(61).unwrap()), "C#4");
+    assert_eq!(note_to_midi_pitch(&parse_note_name("A4").unwrap()), 69);
+    assert_eq!(note_to_midi_pitch(&parse_note_name("Eb5").unwrap()), 75);
+    assert!(parse_note_name("A").is_err());
+    assert!(parse_note_name("H4").is_err());
+}
+
+#[test]
+fn test_connect_notes() {
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let off_pos = |events: &[Event], pitch| {
+        events
+            .iter()
+            .find(|e| matches!(e.e_type, EventType::MidiNote(n) if !n.on_off && n.pitch == pitch))
+            .unwrap()
+            .bar_pos
+    };
+    // 60 overlaps 62, 62 overlaps 62 again, the last 62 is followed by a gap
+    let events = vec![
+        note(true, 60, 0.),
+        note(true, 62, 0.5),
+        note(false, 60, 0.75),
+        note(true, 62, 1.),
+        note(false, 62, 1.25),
+        note(false, 62, 1.5),
+    ];
+    let events = connect_notes(events, 4.);
+    assert_eq!(events.len(), 6);
+    assert!((off_pos(&events, 60) - (0.5 + MIN_NOTE_LEN)).abs() < 1e-6);
+    let offs_62: Vec<f32> = events
+        .iter()
+        .filter(|e| matches!(e.e_type, EventType::MidiNote(n) if !n.on_off && n.pitch == 62))
+        .map(|e| e.bar_pos)
+        .collect();
+    assert_eq!(offs_62.len(), 2);
+    assert!((offs_62[0] - (1. - MIN_NOTE_LEN)).abs() < 1e-6);
+    assert!((offs_62[1] - 1.5).abs() < 1e-6);
+}
+
+#[test]
+fn test_rotate_pos() {
+    assert_eq!(rotate_pos(1., 0., 4.), 1.);
+    assert_eq!(rotate_pos(0., 1., 4.), 1.);
+    assert_eq!(rotate_pos(2., 1., 4.), 2.5);
+    assert_eq!(rotate_pos(5., 1., 4.), 5.);
+    let mut last_pos = -1.;
+    for i in 0..400 {
+        let pos = rotate_pos(i as f32 / 100., 0.5, 4.);
+        assert!(last_pos < pos && pos < 4.);
+        last_pos = pos;
+    }
+}
+
+#[test]
+fn test_ascii_piano_roll() {
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let events = vec![
+        note(true, 60, 0.),
+        note(false, 60, 0.25),
+        note(true, 67, 0.5),
+        note(false, 67, 0.5625),
+        note(true, 60, 0.875),
+        note(false, 60, 0.125),
+    ];
+    assert_eq!(
+        ascii_piano_roll(events.clone(), 1.),
+        "G4  |........x.......|\nC4  |x---..........x-|\n"
+    );
+    // The grid gets coarser on long loops to stay within the max width
+    let roll = ascii_piano_roll(events, 16.);
+    assert!(roll.lines().all(|l| l.len() == 4 + ASCII_MAX_COLS + 2));
+    assert_eq!(ascii_piano_roll(vec![], 1.), "");
+}
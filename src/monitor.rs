@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::{sync::Arc, thread, time::Duration};
+
+use crate::seq::{SeqStatus, Sequencer};
+
+/// Period at which the monitor table is printed
+const MONITOR_PERIOD: Duration = Duration::from_secs(1);
+
+/// Per channel counters of the midi messages received and sent.
+/// The jack process only increments the counters, formatting is left to the monitor thread.
+#[derive(Default)]
+pub struct MidiMonitor {
+    pub enabled: AtomicBool,
+    in_counts: [AtomicU32; 16],
+    out_counts: [AtomicU32; 16],
+    /// Messages sent since start, counted whether the monitor is on or not
+    pub sent_total: AtomicU64,
+    /// Messages that could not be written since start, the output buffer being full
+    pub dropped_total: AtomicU64,
+}
+
+impl MidiMonitor {
+    pub fn count_in(&self, bytes: &[u8]) {
+        if let Some(ch) = self.channel_idx(bytes) {
+            self.in_counts[ch].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn count_out(&self, bytes: &[u8]) {
+        self.sent_total.fetch_add(1, Ordering::Relaxed);
+        if let Some(ch) = self.channel_idx(bytes) {
+            self.out_counts[ch].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn count_dropped(&self) {
+        self.dropped_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counters are only updated when the monitor is on, for channel messages
+    fn channel_idx(&self, bytes: &[u8]) -> Option<usize> {
+        match bytes.first() {
+            Some(status @ 0x80..=0xEF) if self.enabled.load(Ordering::Relaxed) => {
+                Some((status & 0x0F) as usize)
+            }
+            _ => None,
+        }
+    }
+
+    /// Print the counts and rates of the channels that were active since the last flush
+    fn flush(&self, period: Duration) {
+        let mut table = String::new();
+        for ch in 0..16 {
+            let nb_in = self.in_counts[ch].swap(0, Ordering::Relaxed);
+            let nb_out = self.out_counts[ch].swap(0, Ordering::Relaxed);
+            if nb_in + nb_out > 0 {
+                table += &format!(
+                    "{:<5} {:<8} {:<8} {:<8.1} {:<8.1}\n",
+                    ch + 1,
+                    nb_in,
+                    nb_out,
+                    nb_in as f32 / period.as_secs_f32(),
+                    nb_out as f32 / period.as_secs_f32()
+                );
+            }
+        }
+        if !table.is_empty() {
+            println!(
+                "{:<5} {:<8} {:<8} {:<8} {:<8}\n{table}",
+                "Ch", "In", "Out", "In/s", "Out/s"
+            );
+        }
+    }
+}
+
+/// Returns the loop periodically printing the midi monitor table
+pub fn monitor_process_closure(seq: Arc<Sequencer>) -> impl FnOnce() {
+    move || {
+        while seq.params.read().status != SeqStatus::Shutdown {
+            thread::sleep(MONITOR_PERIOD);
+            if seq.monitor.enabled.load(Ordering::Relaxed) {
+                seq.monitor.flush(MONITOR_PERIOD);
+            }
+        }
+        println!("Monitor process shutdown gracefully.");
+    }
+}
@@ -1,14 +1,24 @@
 use anyhow::bail;
 use num_traits::FromPrimitive;
-use rosc::OscMessage;
-use std::{io::ErrorKind, net::UdpSocket, sync::Arc};
+use rosc::{OscBundle, OscMessage, OscMidiMessage, OscPacket, OscTime, OscType};
+use std::sync::atomic::Ordering;
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::Arc,
+};
 
+use crate::replay::CommandLog;
 use crate::seq::BaseSeqType::{Euclid, Random};
 use crate::{
-    midi::midi_pitch_to_note,
+    midi::{
+        midi_pitch_to_note, mode_from_code, note_name, note_to_midi_pitch, parse_note_name,
+        DEFAULT_MARKOV_WEIGHTS,
+    },
     seq::{
         BaseSeqParams::{self},
-        EuclidBase, RandomBase, SeqStatus,
+        EncoderCurve, EncoderTakeover, EuclidBase, FxKind, Lfo, LfoTarget, OctaveScale, RandomBase,
+        RandomizeBounds, SeqStatus, Spacing, VelocityCurve, VelocityDist,
     },
     Sequencer,
 };
@@ -18,140 +28,952 @@ use crate::{
 // const OSC_BUFFER_LEN: usize = 4096;
 const OSC_BUFFER_LEN: usize = rosc::decoder::MTU;
 pub const OSC_PORT: &str = "34254";
+/// Commands answering with a reply, which is sent back whatever reply_on_result,
+/// as well as their errors
+const OSC_QUERY_COMMANDS: [&str; 9] = [
+    "/note_name",
+    "/note_pitch",
+    "/get_bpm",
+    "/get_base_seq_seed",
+    "/render",
+    "/metrics",
+    "/get_combined_cycle",
+    "/channels",
+    "/ascii",
+];
+/// Note length change of a focused BaseSeq per encoder tick, in bars
+const ENC_NOTE_LEN_STEP: f32 = 1. / 64.;
+/// Max nb of datagrams drained from the socket to be handled as a batch
+const OSC_MAX_BATCH_LEN: usize = 256;
+/// Commands of which only the last of a batch is applied, as it overrides the previous ones.
+/// Commands setting parameters that other commands depend on (e.g. the time unit) are left out
+/// to keep the batch order meaningful, as well as the relative ones (e.g. the encoder deltas).
+const OSC_COALESCED_COMMANDS: [&str; 3] = ["/set_bpm", "/set_shuffle", "/set_meta_loop"];
+
+pub(crate) fn osc_handling(
+    osc_msg: &OscMessage,
+    seq: &Arc<Sequencer>,
+) -> anyhow::Result<Option<OscPacket>> {
+    command_handling(&osc_msg.addr, &osc_msg.args, seq)
+}
+
+/// Command of an address, i.e. its part after the addr prefix of the sequencer.
+/// The monome addresses are not prefixed and are their own command.
+fn addr_command<'a>(addr: &'a str, addr_prefix: &str) -> Option<&'a str> {
+    match addr.strip_prefix(addr_prefix) {
+        Some(command) if command.starts_with('/') => Some(command),
+        _ if addr.starts_with("/monome/") => Some(addr),
+        _ => None,
+    }
+}
 
-fn osc_handling(osc_msg: &OscMessage, seq: &Arc<Sequencer>) -> anyhow::Result<()> {
-    match osc_msg.addr.as_str() {
-        "/gisele/set_status" => {
-            let status = parse_to_int(osc_msg, 0)?;
+/// Dispatch of a command to the sequencer, independent of the transport it was received on.
+/// The args are given as OSC types, which other transports translate to.
+/// Queries return the reply to be sent back.
+pub(crate) fn command_handling(
+    addr: &str,
+    args: &[OscType],
+    seq: &Arc<Sequencer>,
+) -> anyhow::Result<Option<OscPacket>> {
+    let mut reply = None;
+    let addr_prefix = seq.params.read().addr_prefix.clone();
+    let command = addr_command(addr, &addr_prefix)
+        .ok_or_else(|| anyhow::format_err!("OSC path is not under the {addr_prefix} prefix"))?;
+    match command {
+        "/set_status" => {
+            let status = parse_to_int(args, 0)?;
             let mut seq_params_mut = seq.params.write();
             seq_params_mut.status = FromPrimitive::from_u32(status as u32)
                 .ok_or_else(|| anyhow::format_err!("OSC status arg was not in enum."))?;
             println!("Sequencer Status set to {:?}", seq_params_mut.status);
         }
-        "/gisele/set_bpm" => {
-            seq.params.write().bpm = parse_to_float(osc_msg, 0)?;
+        "/monitor" => {
+            let enabled = parse_to_int(args, 0)? != 0;
+            seq.monitor.enabled.store(enabled, Ordering::Relaxed);
+        }
+        "/set_note_off_as_velocity_zero" => {
+            seq.params.write().note_off_as_velocity_zero = parse_to_int(args, 0)? != 0;
+        }
+        "/set_shuffle" => {
+            let shuffle = parse_to_float(args, 0)?;
+            if !(0. ..1.).contains(&shuffle) {
+                bail!("Shuffle should be in [0, 1)");
+            }
+            seq.params.write().master_shuffle = shuffle;
+        }
+        "/reply_on_result" => {
+            seq.params.write().reply_on_result = parse_to_int(args, 0)? != 0;
+        }
+        "/set_bpm" => {
+            seq.set_bpm(parse_to_float(args, 0)?)?;
+        }
+        "/set_velocity_curve" => {
+            // Either a curve code, 0 linear, 1 exp or 2 log, or a float gamma
+            let velocity_curve = match args.first() {
+                Some(OscType::Float(gamma)) => VelocityCurve::Gamma(*gamma),
+                _ => match parse_to_int(args, 0)? {
+                    0 => VelocityCurve::Linear,
+                    1 => VelocityCurve::Exp,
+                    2 => VelocityCurve::Log,
+                    _ => bail!("OSC velocity curve arg was not in enum."),
+                },
+            };
+            seq.set_velocity_curve(velocity_curve)?;
+        }
+        "/set_addr_prefix" => {
+            seq.set_addr_prefix(parse_to_string(args, 0)?)?;
+        }
+        "/get_bpm" => {
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![OscType::Float(seq.params.read().bpm)],
+            }));
+        }
+        "/render" => {
+            // One message per midi message, with its frame offset from the window start.
+            // The bundle of a long window may not fit in a datagram.
+            let start = parse_to_bars(args, 0, seq)? as f64;
+            let end = parse_to_bars(args, 1, seq)? as f64;
+            let content = seq
+                .render_window(start, end)?
+                .into_iter()
+                .map(|(frame, [status, data1, data2])| {
+                    OscPacket::Message(OscMessage {
+                        addr: addr.to_string(),
+                        args: vec![
+                            OscType::Int(frame as i32),
+                            OscType::Midi(OscMidiMessage {
+                                port: 0,
+                                status,
+                                data1,
+                                data2,
+                            }),
+                        ],
+                    })
+                })
+                .collect();
+            reply = Some(OscPacket::Bundle(OscBundle {
+                timetag: OscTime::from((0, 1)),
+                content,
+            }));
+        }
+        "/metrics" => {
+            let metrics = seq.metrics();
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![
+                    OscType::Float(metrics.bpm),
+                    OscType::Int(metrics.status as i32),
+                    OscType::Int(metrics.nb_base_seqs as i32),
+                    OscType::Int(metrics.nb_events as i32),
+                    OscType::Long(metrics.events_sent as i64),
+                    OscType::Long(metrics.events_dropped as i64),
+                ],
+            }));
+        }
+        "/get_combined_cycle" => {
+            // In bars, 0 without base seqs
+            let cycle = seq.combined_cycle()?.unwrap_or(0.);
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![OscType::Float(cycle)],
+            }));
+        }
+        "/channels" => {
+            // Bitmask of the used channels, bit 0 being channel 1, then the (channel, nb of
+            // base seqs) pairs
+            let usage = seq.channel_usage();
+            let mask = usage
+                .iter()
+                .fold(0, |mask, (ch, _)| mask | 1 << (ch.saturating_sub(1) % 16));
+            let mut reply_args = vec![OscType::Int(mask)];
+            for (ch, count) in usage {
+                reply_args.push(OscType::Int(ch as i32));
+                reply_args.push(OscType::Int(count as i32));
+            }
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: reply_args,
+            }));
+        }
+        "/ascii" => {
+            // Printed as well, for a look over the terminal of the sequencer
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let roll = seq.ascii(base_seq_id)?;
+            println!("Base seq {base_seq_id}:\n{roll}");
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![OscType::Int(base_seq_id as i32), OscType::String(roll)],
+            }));
+        }
+        "/sync_meta_loop" => {
+            seq.sync_meta_loop()?;
+        }
+        "/set_bpm_range" => {
+            seq.set_bpm_range(parse_to_float(args, 0)?, parse_to_float(args, 1)?)?;
+        }
+        "/note_name" => {
+            let pitch = parse_to_int(args, 0)?.clamp(0, 127) as u8;
+            let name = note_name(&midi_pitch_to_note(pitch)?);
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![OscType::Int(pitch as i32), OscType::String(name)],
+            }));
+        }
+        "/note_pitch" => {
+            let name = parse_to_string(args, 0)?;
+            let pitch = note_to_midi_pitch(&parse_note_name(&name)?);
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![OscType::String(name), OscType::Int(pitch as i32)],
+            }));
+        }
+        "/set_resolution" => {
+            let ticks_per_bar = parse_to_int(args, 0)?.max(0) as u32;
+            seq.set_resolution(ticks_per_bar)?;
         }
-        "/gisele/set_loop_length" => {
-            let base_seq_id = parse_to_int(osc_msg, 0)? as u32;
-            let loop_len = parse_to_float(osc_msg, 1)?;
+        "/set_meta_loop" => {
+            let meta_loop_bars = parse_to_int(args, 0)?;
+            if meta_loop_bars <= 0 {
+                bail!("The meta loop should be at least a bar long.");
+            }
+            seq.params.write().meta_loop_bars = meta_loop_bars as u32;
+        }
+        "/set_direction" => {
+            let direction = FromPrimitive::from_u32(parse_to_int(args, 0)? as u32)
+                .ok_or_else(|| anyhow::format_err!("OSC direction arg was not in enum."))?;
+            seq.set_direction(direction);
+        }
+        "/set_time_unit" => {
+            let mut seq_params_mut = seq.params.write();
+            seq_params_mut.time_unit = FromPrimitive::from_u32(parse_to_int(args, 0)? as u32)
+                .ok_or_else(|| anyhow::format_err!("OSC time unit arg was not in enum."))?;
+            println!("Time unit set to {:?}", seq_params_mut.time_unit);
+        }
+        "/set_loop_length" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let loop_len = parse_to_bars(args, 1, seq)?;
             seq.change_loop_len(base_seq_id, loop_len)?;
         }
-        "/gisele/regenerate" => {
-            let base_seq_id = parse_to_int(osc_msg, 0)? as u32;
+        "/set_loop_length_all" => {
+            let loop_len = parse_to_bars(args, 0, seq)?;
+            let nb_updated = seq.change_loop_len_all(loop_len)?;
+            println!("Loop length set to {loop_len} for {nb_updated} base sequences");
+        }
+        "/regenerate" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
             seq.regen_base_seq(base_seq_id)?;
         }
-        "/gisele/set_root" => {
-            let base_seq_id = parse_to_int(osc_msg, 0)? as u32;
-            let target_note = midi_pitch_to_note(parse_to_int(osc_msg, 1)? as u8)?;
+        "/set_root" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let target_note = midi_pitch_to_note(parse_to_int(args, 1)? as u8)?;
             seq.transpose(base_seq_id, target_note)?;
         }
-        "/gisele/set_note_len" => {
-            let base_seq_id = parse_to_int(osc_msg, 0)? as u32;
-            let note_len = parse_to_float(osc_msg, 1)?;
+        "/set_root_multi" => {
+            let root_pitch = parse_to_int(args, 0)?;
+            if !(12..=127).contains(&root_pitch) {
+                bail!("The root pitch should be within 12-127.");
+            }
+            let target_note = midi_pitch_to_note(root_pitch as u8)?;
+            for base_seq_id in parse_to_int_list(args, 1)? {
+                if let Err(e) = seq.transpose(base_seq_id as u32, target_note.clone()) {
+                    eprintln!("Skipping base seq {base_seq_id}: {e}");
+                }
+            }
+        }
+        "/transpose_diatonic" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let degrees = parse_to_int(args, 1)?;
+            seq.transpose_diatonic(base_seq_id, degrees)?;
+        }
+        "/invert" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let pivot = parse_to_int(args, 1)?.clamp(0, 127) as u8;
+            seq.invert(base_seq_id, pivot)?;
+        }
+        "/set_wrap_note_off" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let wrap_note_off = parse_to_int(args, 1)? != 0;
+            seq.set_wrap_note_off(base_seq_id, wrap_note_off)?;
+        }
+        "/set_regen_on_loop" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let regen_on_loop = parse_to_int(args, 1)? != 0;
+            seq.set_regen_on_loop(base_seq_id, regen_on_loop)?;
+        }
+        "/set_evolve" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let evolve = parse_to_int(args, 1)? != 0;
+            seq.set_evolve(base_seq_id, evolve)?;
+        }
+        "/get_base_seq_seed" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let seed = seq.base_seq_seed(base_seq_id)?;
+            reply = Some(OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args: vec![OscType::Int(base_seq_id as i32), OscType::Long(seed as i64)],
+            }));
+        }
+        "/set_base_seq_seed" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let seed = parse_to_seed(args, 1)?;
+            seq.set_base_seq_seed(base_seq_id, seed)?;
+        }
+        "/set_master_seed" => {
+            seq.set_master_seed(parse_to_int(args, 0)? as u32 as u64);
+        }
+        "/set_gate_ratio" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let ratio = parse_to_float(args, 1)?;
+            seq.set_gate_ratio(base_seq_id, ratio)?;
+        }
+        "/set_grid_velocity" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let division = parse_to_int(args, 1)?.max(0) as u32;
+            let strength = parse_to_float(args, 2)?;
+            seq.set_grid_velocity(base_seq_id, division, strength)?;
+        }
+        "/set_vel_len_coupling" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let amount = parse_to_float(args, 1)?;
+            seq.set_vel_len_coupling(base_seq_id, amount)?;
+        }
+        "/set_legato" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let legato = parse_to_int(args, 1)? != 0;
+            seq.set_legato(base_seq_id, legato)?;
+        }
+        "/set_velocity_contour" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let contour = parse_to_float_array(args, 1)?;
+            seq.set_velocity_contour(base_seq_id, contour)?;
+        }
+        "/set_explicit_durations" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let explicit_durations = parse_to_int(args, 1)? != 0;
+            seq.set_explicit_durations(base_seq_id, explicit_durations)?;
+        }
+        "/set_decay" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let factor = parse_to_float(args, 1)?;
+            seq.set_decay(base_seq_id, factor)?;
+        }
+        "/route_override" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let channel = parse_to_midi_ch(args, 1)?;
+            let bars = parse_to_bars(args, 2, seq)?;
+            seq.route_override(base_seq_id, channel, bars)?;
+        }
+        "/set_mpe" => {
+            // A low channel of 0 puts the notes back on the base seq channel
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let range = if parse_to_int(args, 1)? == 0 {
+                None
+            } else {
+                Some((parse_to_midi_ch(args, 1)?, parse_to_midi_ch(args, 2)?))
+            };
+            seq.set_mpe(base_seq_id, range)?;
+        }
+        "/set_note_off_velocity" => {
+            // A negative velocity releases the notes with their note on velocity
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let velocity = parse_to_int(args, 1)?;
+            let velocity = (velocity >= 0).then(|| velocity.min(128) as u8);
+            seq.set_note_off_velocity(base_seq_id, velocity)?;
+        }
+        "/set_notes_off_velocity" => {
+            let velocity = parse_to_int(args, 0)?;
+            if !(0..=127).contains(&velocity) {
+                bail!("The notes off velocity should be between 0 and 127.");
+            }
+            seq.params.write().notes_off_velocity = velocity as u8;
+        }
+        "/seek" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let bar_pos = parse_to_bars(args, 1, seq)?;
+            seq.seek(base_seq_id, bar_pos)?;
+        }
+        "/phase_lock" => {
+            let leader_id = parse_to_int(args, 0)? as u32;
+            let follower_id = parse_to_int(args, 1)? as u32;
+            let offset = parse_to_bars(args, 2, seq)?;
+            seq.phase_lock(leader_id, follower_id, offset)?;
+        }
+        "/phase_unlock" => {
+            let base_seq_id_a = parse_to_int(args, 0)? as u32;
+            let base_seq_id_b = parse_to_int(args, 1)? as u32;
+            if !seq.phase_unlock(base_seq_id_a, base_seq_id_b) {
+                bail!("Base sequences {base_seq_id_a} and {base_seq_id_b} are not phase locked.");
+            }
+        }
+        "/auto_fill" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let bars_before = parse_to_bars(args, 1, seq)?;
+            seq.set_auto_fill(base_seq_id, bars_before)?;
+        }
+        "/set_start_offset" => {
+            seq.set_start_offset(parse_to_int(args, 0)? as u32, parse_to_float(args, 1)?)?;
+        }
+        "/set_note_repeat" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let prob = parse_to_float(args, 1)?;
+            let count = parse_to_int(args, 2)?.max(0) as u32;
+            seq.set_note_repeat(base_seq_id, prob, count)?;
+        }
+        "/set_gain" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let scale = parse_to_float(args, 1)?;
+            let offset = parse_to_int(args, 2)?;
+            seq.set_gain(base_seq_id, scale, offset)?;
+        }
+        "/reset_decay" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            seq.reset_decay(base_seq_id)?;
+        }
+        "/stutter" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let slice_len = parse_to_float(args, 1)?;
+            let repeats = parse_to_int(args, 2)?;
+            seq.stutter(base_seq_id, slice_len, repeats)?;
+        }
+        "/set_note_len" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let note_len = parse_to_bars(args, 1, seq)?;
             seq.change_note_len(base_seq_id, note_len)?;
         }
-        "/gisele/empty" => {
+        "/empty" => {
             seq.empty();
         }
-        "/gisele/remove_base_seq" => {
-            let base_seq_id = parse_to_int(osc_msg, 0)? as u32;
+        "/notes_off_channel" => {
+            seq.flush_channel(parse_to_midi_ch(args, 0)?);
+        }
+        "/clear_fx" => {
+            seq.clear_fx();
+        }
+        "/remove_base_seq" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
             seq.remove_base_seq(base_seq_id)?;
         }
-        "/gisele/add_random_base" => {
-            let loop_length = parse_to_float(osc_msg, 0)?;
-            let root_note = parse_to_int(osc_msg, 1)? as u8;
-            let nb_events = parse_to_int(osc_msg, 2)? as u32;
-            let note_len_avg = parse_to_float(osc_msg, 3)?;
-            let note_len_div = parse_to_float(osc_msg, 4)?;
-            let velocity_avg = parse_to_int(osc_msg, 5)? as u8;
-            let velocity_div = parse_to_float(osc_msg, 6)?;
-            let midi_ch = parse_to_midi_ch(osc_msg, 7)?;
-            let base_seq_params = BaseSeqParams {
-                ty: Random(RandomBase { nb_events }),
-                loop_length,
-                root_note: midi_pitch_to_note(root_note)?,
-                note_len_avg,
-                note_len_div,
-                velocity_avg,
-                velocity_div,
-                midi_ch,
-            };
-            seq.add_base_seq(base_seq_params)?;
+        "/add_random_base" => {
+            seq.add_base_seq(parse_random_base_params(args, seq)?)?;
         }
-        "/gisele/add_euclid_base" => {
-            let loop_length = parse_to_float(osc_msg, 0)?;
-            let root_note = parse_to_int(osc_msg, 1)? as u8;
-            let pulses = parse_to_int(osc_msg, 2)? as u32;
-            let steps = parse_to_int(osc_msg, 3)? as u32;
-            let note_len_avg = parse_to_float(osc_msg, 4)?;
-            let note_len_div = parse_to_float(osc_msg, 5)?;
-            let velocity_avg = parse_to_int(osc_msg, 6)? as u8;
-            let velocity_div = parse_to_float(osc_msg, 7)?;
-            let midi_ch = parse_to_midi_ch(osc_msg, 8)?;
-
-            let base_seq_params = BaseSeqParams {
-                ty: Euclid(EuclidBase { pulses, steps }),
-                loop_length,
-                root_note: midi_pitch_to_note(root_note)?,
-                note_len_avg,
-                note_len_div,
-                velocity_avg,
-                velocity_div,
-                midi_ch,
-            };
+        "/add_random_base_from_input" => {
+            // Same args as add_random_base, pitches are drawn from the currently held input notes
+            let held_notes = seq.held_notes();
+            if held_notes.is_empty() {
+                bail!("No midi input notes are currently held.");
+            }
+            let mut base_seq_params = parse_random_base_params(args, seq)?;
+            base_seq_params.pitch_pool = Some(held_notes);
             seq.add_base_seq(base_seq_params)?;
         }
-        "/gisele/random_base/set_nb_events" => {
-            let base_seq_id = parse_to_int(osc_msg, 0)? as u32;
-            let nb_events = parse_to_int(osc_msg, 1)? as u32;
+        "/add_euclid_base" => {
+            seq.add_base_seq(parse_euclid_base_params(args, seq)?)?;
+        }
+        "/ensure_base_seq" => {
+            // The caller chosen id, then the args of add_random_base
+            let base_seq_id = parse_to_int(args, 0)?;
+            if base_seq_id < 0 {
+                bail!("The base sequence id should be positive.");
+            }
+            seq.ensure_base_seq(
+                base_seq_id as u32,
+                parse_random_base_params(&args[1..], seq)?,
+            )?;
+        }
+        "/ensure_euclid_base" => {
+            // The caller chosen id, then the args of add_euclid_base
+            let base_seq_id = parse_to_int(args, 0)?;
+            if base_seq_id < 0 {
+                bail!("The base sequence id should be positive.");
+            }
+            seq.ensure_base_seq(
+                base_seq_id as u32,
+                parse_euclid_base_params(&args[1..], seq)?,
+            )?;
+        }
+        "/random_base/set_nb_events" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let nb_events = parse_to_int(args, 1)? as u32;
             seq.set_nb_events(base_seq_id, nb_events)?;
         }
+        "/random_base/set_spacing" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let spacing = FromPrimitive::from_u32(parse_to_int(args, 1)? as u32)
+                .ok_or_else(|| anyhow::format_err!("OSC spacing arg was not in enum."))?;
+            seq.set_spacing(base_seq_id, spacing)?;
+        }
+        "/set_octave_range" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let octaves = parse_to_int(args, 1)?.max(0) as u32;
+            seq.set_octave_range(base_seq_id, octaves)?;
+        }
+        "/random_base/set_octave_scales" => {
+            // Triplets of first octave, last octave and mode code follow the base seq id
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let octave_scales = (1..args.len())
+                .step_by(3)
+                .map(|i| {
+                    let mode_code = parse_to_int(args, i + 2)? as u32;
+                    Ok(OctaveScale {
+                        octaves: (
+                            parse_to_int(args, i)?.max(0) as u32,
+                            parse_to_int(args, i + 1)?.max(0) as u32,
+                        ),
+                        mode: mode_from_code(mode_code)
+                            .ok_or_else(|| anyhow::format_err!("OSC mode arg was not in enum."))?,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+            seq.set_octave_scales(base_seq_id, octave_scales)?;
+        }
+        "/random_base/set_markov" => {
+            // Optional move weights follow, the default ones favouring small steps
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let weights = if parse_to_int(args, 1)? == 0 {
+                None
+            } else if args.len() > 2 {
+                Some(parse_to_float_array(args, 2)?)
+            } else {
+                Some(DEFAULT_MARKOV_WEIGHTS.to_vec())
+            };
+            seq.set_markov(base_seq_id, weights)?;
+        }
+        "/random_base/set_velocity_dist" => {
+            // 0 for the normal distribution of the velocity avg and div, 1 for a uniform one
+            // between the min and max that follow
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let velocity_dist = match parse_to_int(args, 1)? {
+                0 => VelocityDist::Normal,
+                1 => VelocityDist::Uniform {
+                    min: parse_to_int(args, 2)?.clamp(0, 127) as u8,
+                    max: parse_to_int(args, 3)?.clamp(0, 127) as u8,
+                },
+                code => bail!("Unknown velocity distribution code {code}."),
+            };
+            seq.set_velocity_dist(base_seq_id, velocity_dist)?;
+        }
+        "/euclid/set_pulses" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let pulses = parse_to_int(args, 1)?.max(0) as u32;
+            seq.set_euclid_pulses(base_seq_id, pulses)?;
+        }
+        "/euclid/set_steps_count" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let steps = parse_to_int(args, 1)?.max(0) as u32;
+            seq.set_euclid_steps(base_seq_id, steps)?;
+        }
+        "/euclid_to_steps" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            seq.euclid_to_steps(base_seq_id)?;
+        }
+        "/step/toggle" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let step_idx = parse_to_int(args, 1)? as usize;
+            seq.toggle_step(base_seq_id, step_idx)?;
+        }
+        "/add_harmony" => {
+            let source_id = parse_to_int(args, 0)? as u32;
+            let interval = parse_to_int(args, 1)?;
+            let midi_ch = parse_to_midi_ch(args, 2)?;
+            seq.add_harmony(source_id, interval, midi_ch)?;
+        }
+        "/add_euclid_complement" => {
+            let source_id = parse_to_int(args, 0)? as u32;
+            let midi_ch = parse_to_midi_ch(args, 1)?;
+            seq.add_euclid_complement(source_id, midi_ch)?;
+        }
+        "/set_grid_feedback" => {
+            // Host and port of the grid serialosc device, and 0 to turn the feedback off
+            let enabled = args.len() < 3 || parse_to_int(args, 2)? != 0;
+            seq.params.write().grid_feedback = if enabled {
+                let host = parse_to_string(args, 0)?;
+                let port = parse_to_int(args, 1)? as u16;
+                let target = (host.as_str(), port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| anyhow::format_err!("Could not resolve {host}:{port}."))?;
+                Some(target)
+            } else {
+                None
+            };
+        }
+        "/set_max_events_per_cycle" => {
+            // 0 to write all the events of a cycle
+            let max_events = parse_to_int(args, 0)?;
+            seq.params.write().max_events_per_cycle = (max_events > 0).then_some(max_events as u32);
+        }
+        "/set_max_polyphony" => {
+            // 0 to write all the note ons of a cycle
+            let max_notes = parse_to_int(args, 0)?;
+            seq.params.write().max_polyphony = (max_notes > 0).then_some(max_notes as u32);
+        }
+        "/set_capture_quantize" => {
+            // 0 to record events unquantized, the grid is straight without swing
+            let division = parse_to_int(args, 0)?;
+            let swing = if args.len() > 1 {
+                parse_to_float(args, 1)?
+            } else {
+                0.
+            };
+            if !(0. ..1.).contains(&swing) {
+                bail!("Swing should be in [0, 1)");
+            }
+            let mut seq_params = seq.params.write();
+            seq_params.capture_quantize = (division > 0).then_some(division as u32);
+            seq_params.capture_swing = swing;
+        }
+        "/quantize" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let division = parse_to_int(args, 1)?;
+            seq.quantize(base_seq_id, division.max(0) as u32)?;
+        }
+        "/strum" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let spread = parse_to_bars(args, 1, seq)?;
+            seq.strum(base_seq_id, spread)?;
+        }
+        "/double" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let interval = parse_to_int(args, 1)?;
+            seq.double(base_seq_id, interval)?;
+        }
+        "/dedup" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            seq.dedup(base_seq_id)?;
+        }
+        "/mask" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let pulses = parse_to_int(args, 1)?.max(0) as u32;
+            let steps = parse_to_int(args, 2)?.max(0) as u32;
+            seq.mask(base_seq_id, pulses, steps)?;
+        }
+        "/record_start" => {
+            seq.record_start();
+        }
+        "/record_stop" => {
+            let loop_length = parse_to_bars(args, 0, seq)?;
+            seq.record_stop(loop_length)?;
+        }
+        "/save_scene" => {
+            let index = parse_to_int(args, 0)? as u32;
+            seq.save_scene(index);
+        }
+        "/trim" => {
+            // Both the leading and trailing silence are trimmed by default
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let leading = args.len() < 2 || parse_to_int(args, 1)? != 0;
+            let trailing = args.len() < 3 || parse_to_int(args, 2)? != 0;
+            seq.trim(base_seq_id, leading, trailing)?;
+        }
+        "/recall_scene" => {
+            let index = parse_to_int(args, 0)? as u32;
+            let quantized = args.len() > 1 && parse_to_int(args, 1)? != 0;
+            seq.recall_scene(index, quantized)?;
+        }
+        "/next_scene" => {
+            let quantized = !args.is_empty() && parse_to_int(args, 0)? != 0;
+            seq.next_scene(quantized)?;
+        }
+        "/randomize_base_seq" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            seq.randomize_base_seq(base_seq_id)?;
+        }
+        "/set_randomize_bounds" => {
+            // Min and max of nb_events, pulses, steps, note_len, velocity and root
+            let int = |idx| parse_to_int(args, idx).map(|v| v.max(0) as u32);
+            let bounds = RandomizeBounds {
+                nb_events: (int(0)?, int(1)?),
+                pulses: (int(2)?, int(3)?),
+                steps: (int(4)?, int(5)?),
+                note_len: (parse_to_bars(args, 6, seq)?, parse_to_bars(args, 7, seq)?),
+                velocity: (int(8)?.min(255) as u8, int(9)?.min(255) as u8),
+                root: (int(10)?.min(255) as u8, int(11)?.min(255) as u8),
+            };
+            bounds.validate()?;
+            seq.params.write().randomize_bounds = bounds;
+        }
+        "/add_velocity" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let delta = parse_to_int(args, 1)?;
+            seq.add_velocity(base_seq_id, delta)?;
+        }
+        "/edit_event" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let note_idx = parse_to_int(args, 1)? as usize;
+            let bar_pos = parse_to_float(args, 2)?;
+            let velocity = parse_to_int(args, 3)?;
+            if !(0..=127).contains(&velocity) {
+                bail!("The velocity should be in 0..=127.");
+            }
+            seq.edit_event(base_seq_id, note_idx, bar_pos, velocity as u8)?;
+        }
         "/monome/enc/delta" => {
-            let enc_nb = parse_to_int(osc_msg, 0)?; // Is 0-3
-            let delta = parse_to_int(osc_msg, 1)? as f32;
-            let rot_sign = delta.signum();
-            let new_bpm = seq.params.read().bpm + rot_sign * delta * delta / 100.; // Arbitrary input acceleration
-            seq.params.write().bpm = if new_bpm < 0. { 0. } else { new_bpm };
-            eprintln!("BPM set to {}", seq.params.read().bpm);
-        }
-        "/gisele/add_fx_processor" => {
+            let enc_nb = parse_to_int(args, 0)?; // Is 0-3
+            let delta = parse_to_int(args, 1)?;
+            let focused_base_seq = seq.params.read().focused_base_seq;
+            if enc_nb == 0 {
+                let new_bpm = {
+                    let seq_params = seq.params.read();
+                    seq_params.bpm + seq_params.encoder_curve.apply(delta)
+                };
+                eprintln!("BPM set to {}", seq.set_bpm(new_bpm)?);
+            } else if let Some(base_seq_id) = focused_base_seq {
+                let engaged = {
+                    let mut seq_params = seq.params.write();
+                    !seq_params.soft_takeover || seq_params.encoder_takeover.engage(enc_nb, delta)
+                };
+                if engaged {
+                    focused_encoder_delta(seq, base_seq_id, enc_nb, delta)?;
+                }
+            }
+        }
+        "/set_encoder_curve" => {
+            let shape = FromPrimitive::from_u32(parse_to_int(args, 0)? as u32)
+                .ok_or_else(|| anyhow::format_err!("OSC encoder curve arg was not in enum."))?;
+            let gain = parse_to_float(args, 1)?;
+            seq.set_encoder_curve(EncoderCurve { shape, gain })?;
+        }
+        "/set_soft_takeover" => {
+            let mut seq_params = seq.params.write();
+            seq_params.soft_takeover = parse_to_int(args, 0)? != 0;
+            // Engaged until the next focus change
+            seq_params.encoder_takeover = EncoderTakeover::default();
+        }
+        "/focus" => {
+            // A negative id clears the focus
+            let base_seq_id = parse_to_int(args, 0)?;
+            seq.set_focus((base_seq_id >= 0).then_some(base_seq_id as u32))?;
+        }
+        "/add_fx_processor" => {
             // The id of the base seq that will be connected to this fx_proc
-            let base_seq_id = parse_to_int(osc_msg, 0)? as u32;
-            seq.add_fx_processor(base_seq_id)?;
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            seq.add_fx_processor(base_seq_id, FxKind::Jitter)?;
+        }
+        "/add_fx_transpose" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let semitones = parse_to_int(args, 1)?;
+            seq.add_fx_processor(base_seq_id, FxKind::Transpose { semitones })?;
+        }
+        "/set_fx_enabled" => {
+            let fx_proc_id = parse_to_int(args, 0)? as u32;
+            let enabled = parse_to_int(args, 1)? != 0;
+            seq.set_fx_enabled(fx_proc_id, enabled)?;
+        }
+        "/set_loop_rotation_jitter" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let amount = parse_to_float(args, 1)?;
+            seq.set_loop_rotation_jitter(base_seq_id, amount)?;
+        }
+        "/add_duck" => {
+            let trigger_id = parse_to_int(args, 0)? as u32;
+            let target_id = parse_to_int(args, 1)? as u32;
+            let amount = parse_to_float(args, 2)?;
+            let release = parse_to_bars(args, 3, seq)?;
+            seq.add_duck(trigger_id, target_id, amount, release)?;
+        }
+        "/pause_base_seq" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let paused = parse_to_int(args, 1)? != 0;
+            seq.pause_base_seq(base_seq_id, paused)?;
+        }
+        "/glide" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let from_pitch = parse_to_int(args, 1)?.clamp(0, 127) as u8;
+            let to_pitch = parse_to_int(args, 2)?.clamp(0, 127) as u8;
+            let bars = parse_to_bars(args, 3, seq)?;
+            seq.glide(base_seq_id, from_pitch, to_pitch, bars)?;
+        }
+        "/mute_region" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let start = parse_to_bars(args, 1, seq)?;
+            let end = parse_to_bars(args, 2, seq)?;
+            let muted = parse_to_int(args, 3)? != 0;
+            seq.mute_region(base_seq_id, start, end, muted)?;
+        }
+        "/add_lfo" => {
+            let shape = FromPrimitive::from_u32(parse_to_int(args, 0)? as u32)
+                .ok_or_else(|| anyhow::format_err!("OSC LFO shape arg was not in enum."))?;
+            let period = parse_to_bars(args, 1, seq)?;
+            let depth = parse_to_float(args, 2)?;
+            // 0: bpm, 1: CC, followed by its channel and controller
+            let target = match parse_to_int(args, 3)? {
+                0 => LfoTarget::Bpm,
+                1 => LfoTarget::Cc {
+                    channel: parse_to_midi_ch(args, 4)?,
+                    controller: parse_to_int(args, 5)?.clamp(0, 127) as u8,
+                },
+                _ => bail!("OSC LFO target arg was not recognized."),
+            };
+            seq.add_lfo(Lfo {
+                shape,
+                period,
+                depth,
+                target,
+            })?;
+        }
+        "/clear_lfos" => {
+            seq.clear_lfos();
+        }
+        "/fx_bypass" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let bypassed = parse_to_int(args, 1)? != 0;
+            seq.set_fx_bypass(base_seq_id, bypassed)?;
+        }
+        "/reorder_fx" => {
+            let base_seq_id = parse_to_int(args, 0)? as u32;
+            let fx_proc_ids = parse_to_int_list(args, 1)?
+                .into_iter()
+                .map(|id| id as u32)
+                .collect();
+            seq.reorder_fx(base_seq_id, fx_proc_ids)?;
         }
         _ => bail!("OSC path was not recognized"),
     }
     println!("Osc command success.");
-    Ok(())
+    Ok(reply)
+}
+
+/// Route the delta of the encoders 1 to 3 to the note length, velocity and root of the focused BaseSeq
+fn focused_encoder_delta(
+    seq: &Sequencer,
+    base_seq_id: u32,
+    enc_nb: i32,
+    delta: i32,
+) -> anyhow::Result<()> {
+    let (note_len, root_pitch) = {
+        let base_seq = seq.get_base_seq(base_seq_id)?;
+        let params = base_seq.params.read();
+        (params.note_len_avg, note_to_midi_pitch(&params.root_note))
+    };
+    match enc_nb {
+        1 => {
+            let note_len = (note_len + delta as f32 * ENC_NOTE_LEN_STEP).max(ENC_NOTE_LEN_STEP);
+            seq.change_note_len(base_seq_id, note_len)
+        }
+        2 => seq.add_velocity(base_seq_id, delta),
+        3 => {
+            // The lowest octave is left out, see midi_pitch_to_note
+            let root_pitch = (root_pitch as i32 + delta).clamp(12, 127) as u8;
+            seq.transpose(base_seq_id, midi_pitch_to_note(root_pitch)?)
+        }
+        _ => bail!("Encoder nb {enc_nb} is out of 0-3."),
+    }
+}
+
+fn parse_random_base_params(args: &[OscType], seq: &Sequencer) -> anyhow::Result<BaseSeqParams> {
+    let loop_length = parse_to_bars(args, 0, seq)?;
+    let root_note = parse_to_int(args, 1)? as u8;
+    let nb_events = parse_to_int(args, 2)? as u32;
+    let note_len_avg = parse_to_bars(args, 3, seq)?;
+    let note_len_div = parse_to_bars(args, 4, seq)?;
+    let velocity_avg = parse_to_int(args, 5)? as u8;
+    let velocity_div = parse_to_float(args, 6)?;
+    let midi_ch = parse_to_midi_ch(args, 7)?;
+    Ok(BaseSeqParams {
+        ty: Random(RandomBase {
+            nb_events,
+            spacing: Spacing::Uniform,
+            octaves: 1,
+            octave_scales: vec![],
+            markov: None,
+            velocity_dist: VelocityDist::Normal,
+        }),
+        loop_length,
+        root_note: midi_pitch_to_note(root_note)?,
+        note_len_avg,
+        note_len_div,
+        velocity_avg,
+        velocity_div,
+        midi_ch,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+}
+
+fn parse_euclid_base_params(args: &[OscType], seq: &Sequencer) -> anyhow::Result<BaseSeqParams> {
+    let loop_length = parse_to_bars(args, 0, seq)?;
+    let root_note = parse_to_int(args, 1)? as u8;
+    let pulses = parse_to_int(args, 2)? as u32;
+    let steps = parse_to_int(args, 3)? as u32;
+    // The rhythm would otherwise be generated empty
+    if steps == 0 || loop_length % steps as f32 != 0. {
+        bail!("The loop length should be a multiple of the steps.");
+    }
+    let note_len_avg = parse_to_bars(args, 4, seq)?;
+    let note_len_div = parse_to_bars(args, 5, seq)?;
+    let velocity_avg = parse_to_int(args, 6)? as u8;
+    let velocity_div = parse_to_float(args, 7)?;
+    let midi_ch = parse_to_midi_ch(args, 8)?;
+    Ok(BaseSeqParams {
+        ty: Euclid(EuclidBase { pulses, steps }),
+        loop_length,
+        root_note: midi_pitch_to_note(root_note)?,
+        note_len_avg,
+        note_len_div,
+        velocity_avg,
+        velocity_div,
+        midi_ch,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
 }
 
 /// Returns the main osc receiving loop
+/// The received commands are logged to the command log, when given, before being handled
 pub fn osc_process_closure(
     udp_socket: UdpSocket,
     seq: Arc<Sequencer>,
+    mut command_log: Option<CommandLog>,
 ) -> impl FnOnce() -> anyhow::Result<()> {
     move || {
         let mut rec_buffer = [0; OSC_BUFFER_LEN];
         while seq.params.read().status != SeqStatus::Shutdown {
-            match udp_socket.recv(&mut rec_buffer) {
-                Ok(received) => {
-                    let (_, packet) =
-                        if let Ok(v) = rosc::decoder::decode_udp(&rec_buffer[..received]) {
-                            v
-                        } else {
-                            eprintln!("OSC message could not be decoded.");
-                            continue;
-                        };
-                    match packet {
-                        rosc::OscPacket::Message(msg) => {
-                            println!("Received osc msg {msg:?}");
-                            let r = osc_handling(&msg, &seq);
-                            if let Err(e) = r {
-                                eprintln!("OSC message handling failed with: {e:?}");
+            seq.drop_retired_base_seqs();
+            seq.drain_record_queue();
+            match udp_socket.recv_from(&mut rec_buffer) {
+                Ok((received, sender)) => {
+                    let mut batch = vec![];
+                    push_decoded(&mut batch, &rec_buffer[..received], sender);
+                    // Drain the datagrams already queued, so that floods of messages
+                    // can be coalesced instead of lagging behind
+                    udp_socket.set_nonblocking(true)?;
+                    while batch.len() < OSC_MAX_BATCH_LEN {
+                        match udp_socket.recv_from(&mut rec_buffer) {
+                            Ok((received, sender)) => {
+                                push_decoded(&mut batch, &rec_buffer[..received], sender)
+                            }
+                            Err(e) => {
+                                if e.kind() != ErrorKind::WouldBlock {
+                                    eprintln!("recv function failed: {e:?}");
+                                }
+                                break;
                             }
                         }
-                        rosc::OscPacket::Bundle(_) => unimplemented!(),
                     }
+                    udp_socket.set_nonblocking(false)?;
+                    if let Some(command_log) = command_log.as_mut() {
+                        for (msg, _) in &batch {
+                            command_log.write(msg);
+                        }
+                    }
+                    handle_batch(&udp_socket, &batch, &seq);
                 }
                 Err(e) => {
                     // Letting timeout errs pass silently
@@ -166,30 +988,507 @@ pub fn osc_process_closure(
     }
 }
 
-fn parse_to_int(osc_msg: &OscMessage, arg_idx: usize) -> anyhow::Result<i32> {
-    osc_msg
-        .args
-        .get(arg_idx)
+fn push_decoded(batch: &mut Vec<(OscMessage, SocketAddr)>, datagram: &[u8], sender: SocketAddr) {
+    let (_, packet) = if let Ok(v) = rosc::decoder::decode_udp(datagram) {
+        v
+    } else {
+        eprintln!("OSC message could not be decoded.");
+        return;
+    };
+    match packet {
+        rosc::OscPacket::Message(msg) => batch.push((msg, sender)),
+        rosc::OscPacket::Bundle(_) => unimplemented!(),
+    }
+}
+
+/// Handle the messages in order, skipping the coalesced commands that are superseded
+/// later in the batch
+fn handle_batch(udp_socket: &UdpSocket, batch: &[(OscMessage, SocketAddr)], seq: &Arc<Sequencer>) {
+    for (idx, (msg, sender)) in batch.iter().enumerate() {
+        // Read for every message, as it can be changed by the batch itself
+        let addr_prefix = seq.params.read().addr_prefix.clone();
+        let command = addr_command(&msg.addr, &addr_prefix);
+        let r = if is_superseded(batch, idx, &addr_prefix) {
+            println!("Skipping superseded osc msg {msg:?}");
+            Ok(None)
+        } else {
+            println!("Received osc msg {msg:?}");
+            osc_handling(msg, seq)
+        };
+        if let Err(ref e) = r {
+            eprintln!("OSC message handling failed with: {e:?}");
+        }
+        match r {
+            Ok(Some(reply)) => send_reply(udp_socket, *sender, reply),
+            _ if seq.params.read().reply_on_result
+                || command.is_some_and(|c| OSC_QUERY_COMMANDS.contains(&c)) =>
+            {
+                reply_result(udp_socket, *sender, &addr_prefix, &msg.addr, &r)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_superseded(batch: &[(OscMessage, SocketAddr)], idx: usize, addr_prefix: &str) -> bool {
+    let addr = &batch[idx].0.addr;
+    addr_command(addr, addr_prefix).is_some_and(|c| OSC_COALESCED_COMMANDS.contains(&c))
+        && batch[idx + 1..].iter().any(|(m, _)| m.addr == *addr)
+}
+
+/// Send back the result of the handling of an OSC message to its sender
+fn reply_result(
+    udp_socket: &UdpSocket,
+    sender: SocketAddr,
+    addr_prefix: &str,
+    addr: &str,
+    result: &anyhow::Result<Option<OscPacket>>,
+) {
+    let reply = match result {
+        Ok(_) => OscMessage {
+            addr: format!("{addr_prefix}/ok"),
+            args: vec![OscType::String(addr.to_string())],
+        },
+        Err(e) => OscMessage {
+            addr: format!("{addr_prefix}/err"),
+            args: vec![
+                OscType::String(addr.to_string()),
+                OscType::String(e.to_string()),
+            ],
+        },
+    };
+    send_reply(udp_socket, sender, OscPacket::Message(reply));
+}
+
+pub(crate) fn send_reply(udp_socket: &UdpSocket, target: SocketAddr, reply: OscPacket) {
+    match rosc::encoder::encode(&reply) {
+        Ok(buffer) => {
+            if let Err(e) = udp_socket.send_to(&buffer, target) {
+                eprintln!("OSC reply could not be sent: {e:?}");
+            }
+        }
+        Err(e) => eprintln!("OSC reply could not be encoded: {e:?}"),
+    }
+}
+
+fn parse_to_int(args: &[OscType], arg_idx: usize) -> anyhow::Result<i32> {
+    args.get(arg_idx)
         .ok_or_else(|| anyhow::format_err!("OSC arg nb {} is missing.", arg_idx))?
         .to_owned()
         .int()
         .ok_or_else(|| anyhow::format_err!("OSC arg nb {} was not recognized.", arg_idx))
 }
 
-fn parse_to_midi_ch(osc_msg: &OscMessage, arg_idx: usize) -> anyhow::Result<u8> {
-    let midi_ch = parse_to_int(osc_msg, arg_idx)? as u8;
+/// Parse all the trailing args, starting at arg_idx, as ints
+fn parse_to_int_list(args: &[OscType], arg_idx: usize) -> anyhow::Result<Vec<i32>> {
+    (arg_idx..args.len())
+        .map(|idx| parse_to_int(args, idx))
+        .collect()
+}
+
+fn parse_to_midi_ch(args: &[OscType], arg_idx: usize) -> anyhow::Result<u8> {
+    let midi_ch = parse_to_int(args, arg_idx)? as u8;
     if !(1..17).contains(&midi_ch) {
         bail!("Midi channel should be between 1 to 16");
     }
     Ok(midi_ch)
 }
 
-fn parse_to_float(osc_msg: &OscMessage, arg_idx: usize) -> anyhow::Result<f32> {
-    osc_msg
-        .args
+/// Parse a length or time arg, given in the sequencer time unit, to bars
+fn parse_to_bars(args: &[OscType], arg_idx: usize, seq: &Sequencer) -> anyhow::Result<f32> {
+    let value = parse_to_float(args, arg_idx)?;
+    let seq_params = seq.params.read();
+    Ok(seq_params.time_unit.to_bars(value, seq_params.bpm))
+}
+
+fn parse_to_string(args: &[OscType], arg_idx: usize) -> anyhow::Result<String> {
+    args.get(arg_idx)
+        .ok_or_else(|| anyhow::format_err!("OSC arg nb {} is missing.", arg_idx))?
+        .to_owned()
+        .string()
+        .ok_or_else(|| anyhow::format_err!("OSC arg nb {} was not recognized.", arg_idx))
+}
+
+/// Parse a 64 bits seed, as replied by the seed queries, or a 32 bits one
+fn parse_to_seed(args: &[OscType], arg_idx: usize) -> anyhow::Result<u64> {
+    match args.get(arg_idx) {
+        Some(OscType::Long(seed)) => Ok(*seed as u64),
+        Some(OscType::Int(seed)) => Ok(*seed as u32 as u64),
+        Some(_) => bail!("OSC arg nb {} was not recognized.", arg_idx),
+        None => bail!("OSC arg nb {} is missing.", arg_idx),
+    }
+}
+
+/// Parse a float, rejecting NaN and infinities which would silently corrupt the time math
+fn parse_to_float(args: &[OscType], arg_idx: usize) -> anyhow::Result<f32> {
+    let value = args
         .get(arg_idx)
         .ok_or_else(|| anyhow::format_err!("OSC arg nb {} is missing.", arg_idx))?
         .to_owned()
         .float()
-        .ok_or_else(|| anyhow::format_err!("OSC arg nb {} was not recognized.", arg_idx))
+        .ok_or_else(|| anyhow::format_err!("OSC arg nb {} was not recognized.", arg_idx))?;
+    if !value.is_finite() {
+        bail!("OSC arg nb {} should be a finite number.", arg_idx);
+    }
+    Ok(value)
+}
+
+/// Parse a list of floats starting at arg_idx, given either as the trailing args or as a single
+/// array or blob arg, the blob packing big-endian f32s like the other OSC numbers
+fn parse_to_float_array(args: &[OscType], arg_idx: usize) -> anyhow::Result<Vec<f32>> {
+    match args.get(arg_idx) {
+        Some(OscType::Array(array)) if args.len() == arg_idx + 1 => (0..array.content.len())
+            .map(|idx| parse_to_float(&array.content, idx))
+            .collect(),
+        Some(OscType::Blob(blob)) if args.len() == arg_idx + 1 => {
+            if blob.len() % 4 != 0 {
+                bail!("OSC arg nb {} should be a blob of f32s.", arg_idx);
+            }
+            let values: Vec<f32> = blob
+                .chunks_exact(4)
+                .map(|bytes| f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            if !values.iter().all(|v| v.is_finite()) {
+                bail!("OSC arg nb {} should hold finite numbers.", arg_idx);
+            }
+            Ok(values)
+        }
+        _ => (arg_idx..args.len())
+            .map(|idx| parse_to_float(args, idx))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_is_superseded() {
+    let sender: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+    let msg = |addr: &str| {
+        (
+            OscMessage {
+                addr: addr.to_string(),
+                args: vec![],
+            },
+            sender,
+        )
+    };
+    let batch = vec![
+        msg("/gisele/set_bpm"),
+        msg("/gisele/regen"),
+        msg("/gisele/set_bpm"),
+        msg("/gisele/regen"),
+    ];
+    assert!(is_superseded(&batch, 0, "/gisele"));
+    assert!(!is_superseded(&batch, 1, "/gisele"));
+    assert!(!is_superseded(&batch, 2, "/gisele"));
+    assert!(!is_superseded(&batch, 3, "/gisele"));
+    assert!(!is_superseded(&batch, 0, "/gisele2"));
+    // The encoder deltas add up
+    let batch = vec![msg("/monome/enc/delta"), msg("/monome/enc/delta")];
+    assert!(!is_superseded(&batch, 0, "/gisele"));
+}
+
+#[test]
+fn test_focused_encoders() {
+    let seq = Arc::new(Sequencer::new(120.));
+    let enc_delta = |enc_nb, delta| {
+        command_handling(
+            "/monome/enc/delta",
+            &[OscType::Int(enc_nb), OscType::Int(delta)],
+            &seq,
+        )
+    };
+    let root_pitch = || note_to_midi_pitch(&seq.get_base_seq(0).unwrap().params.read().root_note);
+    command_handling(
+        "/gisele/add_euclid_base",
+        &[
+            OscType::Float(4.),
+            OscType::Int(60),
+            OscType::Int(4),
+            OscType::Int(4),
+            OscType::Float(0.5),
+            OscType::Float(0.),
+            OscType::Int(100),
+            OscType::Float(0.),
+            OscType::Int(1),
+        ],
+        &seq,
+    )
+    .unwrap();
+
+    // Ignored without a focus
+    enc_delta(3, 2).unwrap();
+    assert_eq!(root_pitch(), 60);
+
+    command_handling("/gisele/focus", &[OscType::Int(0)], &seq).unwrap();
+    enc_delta(3, 2).unwrap();
+    assert_eq!(root_pitch(), 62);
+    enc_delta(1, 4).unwrap();
+    assert_eq!(
+        seq.get_base_seq(0).unwrap().params.read().note_len_avg,
+        0.5 + 4. * ENC_NOTE_LEN_STEP
+    );
+    assert!(command_handling("/gisele/focus", &[OscType::Int(1)], &seq).is_err());
+}
+
+#[test]
+fn test_non_finite_floats() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Arc::new(Sequencer::new(120.));
+    for value in [f32::NAN, f32::INFINITY] {
+        assert!(command_handling("/gisele/set_bpm", &[OscType::Float(value)], &seq).is_err());
+    }
+    assert!(seq.set_bpm(f32::NAN).is_err());
+    assert_eq!(seq.params.read().bpm, 120.);
+
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    assert!(command_handling(
+        "/gisele/set_loop_length",
+        &[OscType::Int(0), OscType::Float(f32::NAN)],
+        &seq
+    )
+    .is_err());
+    assert!(seq.change_loop_len(0, 0.).is_err());
+    assert!(seq.change_loop_len_all(f32::NEG_INFINITY).is_err());
+    assert_eq!(seq.get_base_seq(0).unwrap().params.read().loop_length, 4.);
+}
+
+#[test]
+fn test_int_args_out_of_range() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+    use crate::EventType;
+
+    let seq = Arc::new(Sequencer::new(120.));
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let edit_event = |velocity| {
+        command_handling(
+            "/gisele/edit_event",
+            &[
+                OscType::Int(0),
+                OscType::Int(0),
+                OscType::Float(0.),
+                OscType::Int(velocity),
+            ],
+            &seq,
+        )
+    };
+    let velocity = || match seq.get_base_seq(0).unwrap().event_buffer.read()[0].e_type {
+        EventType::MidiNote(note) => note.velocity,
+        _ => unreachable!(),
+    };
+    // Not truncated to 44
+    assert!(edit_event(300).is_err());
+    assert!(edit_event(-1).is_err());
+    assert_eq!(velocity(), 100);
+    edit_event(90).unwrap();
+    assert_eq!(velocity(), 90);
+
+    // Not wrapped to ~4e9 repeats
+    let stutter = |repeats| {
+        command_handling(
+            "/gisele/stutter",
+            &[OscType::Int(0), OscType::Float(0.25), OscType::Int(repeats)],
+            &seq,
+        )
+    };
+    assert!(stutter(-1).is_err());
+    assert!(stutter(0).is_err());
+    assert!(seq.internal.read().stutters.is_empty());
+    stutter(2).unwrap();
+    assert_eq!(seq.internal.read().stutters[0].repeats, 2);
+}
+
+#[test]
+fn test_parse_to_float_array() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+    use rosc::OscArray;
+
+    let values = [0.5, 1., 1.5];
+    let flat = [OscType::Int(0)]
+        .into_iter()
+        .chain(values.iter().map(|v| OscType::Float(*v)))
+        .collect::<Vec<OscType>>();
+    assert_eq!(parse_to_float_array(&flat, 1).unwrap(), values);
+
+    let array = [
+        OscType::Int(0),
+        OscType::Array(values.iter().copied().collect::<OscArray>()),
+    ];
+    assert_eq!(parse_to_float_array(&array, 1).unwrap(), values);
+
+    let blob = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+    let blob = [OscType::Int(0), OscType::Blob(blob)];
+    assert_eq!(parse_to_float_array(&blob, 1).unwrap(), values);
+    assert!(parse_to_float_array(&[OscType::Blob(vec![0; 3])], 0).is_err());
+    assert!(parse_to_float_array(&[OscType::Blob(f32::NAN.to_be_bytes().to_vec())], 0).is_err());
+    assert!(parse_to_float_array(
+        &[OscType::Array([OscType::Int(1)].into_iter().collect())],
+        0
+    )
+    .is_err());
+
+    // Through a command taking a list of floats
+    let seq = Arc::new(Sequencer::new(120.));
+    seq.add_base_seq(BaseSeqParams {
+        ty: BaseSeqType::Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    command_handling("/gisele/set_velocity_contour", &array, &seq).unwrap();
+    assert_eq!(
+        seq.get_base_seq(0).unwrap().params.read().velocity_contour,
+        Some(values.to_vec())
+    );
+}
+
+#[test]
+fn test_soft_takeover() {
+    use crate::seq::{BaseSeqParams, BaseSeqType, StepsBase};
+
+    let seq = Arc::new(Sequencer::new(120.));
+    let enc_delta = |delta| {
+        command_handling(
+            "/monome/enc/delta",
+            &[OscType::Int(3), OscType::Int(delta)],
+            &seq,
+        )
+        .unwrap()
+    };
+    let root_pitch =
+        |id| note_to_midi_pitch(&seq.get_base_seq(id).unwrap().params.read().root_note);
+    for _ in 0..2 {
+        seq.add_base_seq(BaseSeqParams {
+            ty: BaseSeqType::Steps(StepsBase {
+                pattern: vec![true],
+            }),
+            loop_length: 4.,
+            root_note: midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        })
+        .unwrap();
+    }
+    command_handling("/gisele/set_soft_takeover", &[OscType::Int(1)], &seq).unwrap();
+    command_handling("/gisele/focus", &[OscType::Int(0)], &seq).unwrap();
+
+    // A stray tick and a change of direction are ignored until caught
+    enc_delta(1);
+    enc_delta(-1);
+    enc_delta(-2);
+    assert_eq!(root_pitch(0), 60);
+    enc_delta(-1);
+    assert_eq!(root_pitch(0), 59);
+
+    // Caught again on the next focus
+    command_handling("/gisele/focus", &[OscType::Int(1)], &seq).unwrap();
+    enc_delta(2);
+    assert_eq!(root_pitch(1), 60);
+    enc_delta(1);
+    enc_delta(1);
+    assert_eq!(root_pitch(1), 61);
+}
+
+#[test]
+fn test_set_root_multi() {
+    let seq = Arc::new(Sequencer::new(120.));
+    let add_euclid = || {
+        let args = [
+            OscType::Float(4.),
+            OscType::Int(60),
+            OscType::Int(4),
+            OscType::Int(4),
+            OscType::Float(0.5),
+            OscType::Float(0.),
+            OscType::Int(100),
+            OscType::Float(0.),
+            OscType::Int(1),
+        ];
+        command_handling("/gisele/add_euclid_base", &args, &seq).unwrap();
+    };
+    add_euclid();
+    add_euclid();
+    let root_pitch =
+        |id| note_to_midi_pitch(&seq.get_base_seq(id).unwrap().params.read().root_note);
+
+    // The unknown id 5 is skipped
+    let args = [62, 0, 5, 1].map(OscType::Int);
+    command_handling("/gisele/set_root_multi", &args, &seq).unwrap();
+    assert_eq!((root_pitch(0), root_pitch(1)), (62, 62));
+    let args = [200, 0].map(OscType::Int);
+    assert!(command_handling("/gisele/set_root_multi", &args, &seq).is_err());
 }
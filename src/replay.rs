@@ -0,0 +1,192 @@
+use anyhow::{anyhow, bail};
+use rosc::{OscMessage, OscType};
+use std::{
+    fs::File,
+    io::Write,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    json::{self, Json},
+    osc::command_handling,
+    seq::SeqStatus,
+    Sequencer,
+};
+
+/// Longest sleep of the replay between two checks of the sequencer status
+const REPLAY_POLL_PERIOD: Duration = Duration::from_millis(100);
+
+/// Log of the received OSC commands, one JSON object per line, as
+/// `{ "time": 1.5, "addr": "/gisele/set_bpm", "args": [140.0] }`, the time being in seconds
+/// since the start of the log. The commands are given as for the config and the http endpoint.
+/// The args that JSON cannot hold (longs, doubles, blobs...) are logged as null, and their
+/// commands fail on replay.
+pub struct CommandLog {
+    file: File,
+    start: Instant,
+}
+
+impl CommandLog {
+    pub fn create(path: &str) -> anyhow::Result<CommandLog> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Could not create the command log {path}: {e}"))?;
+        Ok(CommandLog {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    /// Written through right away, so that the log is complete up to a crash
+    pub fn write(&mut self, msg: &OscMessage) {
+        let line = command_line(self.start.elapsed().as_secs_f64(), &msg.addr, &msg.args);
+        if let Err(e) = writeln!(self.file, "{line}") {
+            eprintln!("OSC command could not be logged: {e:?}");
+        }
+    }
+}
+
+fn command_line(time: f64, addr: &str, args: &[OscType]) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| match arg {
+            OscType::Int(i) => i.to_string(),
+            // Debug always has a fraction or an exponent, for the arg to be parsed back as a float
+            OscType::Float(f) if f.is_finite() => format!("{f:?}"),
+            OscType::String(s) => json_string(s),
+            OscType::Bool(b) => b.to_string(),
+            _ => "null".to_string(),
+        })
+        .collect();
+    format!(
+        "{{ \"time\": {time:.6}, \"addr\": {}, \"args\": [{}] }}",
+        json_string(addr),
+        args.join(", ")
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Command of a log with its time in seconds since the log start, or the reason why it cannot
+/// be replayed
+pub type LoggedCommand = (f64, anyhow::Result<(String, Vec<OscType>)>);
+
+/// Commands of a log, see [CommandLog]
+pub fn load_log(path: &str) -> anyhow::Result<Vec<LoggedCommand>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Could not read the command log {path}: {e}"))?;
+    parse_log(&text).map_err(|e| anyhow!("Invalid command log {path}: {e}"))
+}
+
+fn parse_log(text: &str) -> anyhow::Result<Vec<LoggedCommand>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            let Json::Object(mut fields) = json::parse(line)? else {
+                bail!("Line {} should be a JSON object.", idx + 1);
+            };
+            let time_idx = fields
+                .iter()
+                .position(|(key, _)| key == "time")
+                .ok_or_else(|| anyhow!("Line {} is missing its time.", idx + 1))?;
+            let time = match fields.remove(time_idx).1 {
+                Json::Float(time) => time as f64,
+                Json::Int(time) => time as f64,
+                _ => bail!("The time of line {} should be a number.", idx + 1),
+            };
+            Ok((time, Json::Object(fields).into_command()))
+        })
+        .collect()
+}
+
+/// Whether the command would shut the sequencer down
+fn is_shutdown(addr: &str, args: &[OscType]) -> bool {
+    addr.ends_with("/set_status")
+        && args.first().and_then(|arg| arg.clone().int()) == Some(SeqStatus::Shutdown as i32)
+}
+
+/// Returns the loop feeding the commands of a log back to the sequencer, at their logged times
+/// from its start. The shutdown of the logged session is left out, for the sequencer to stay up
+/// once the session is reproduced. The replay ends early on a shutdown of the sequencer.
+pub fn replay_process_closure(commands: Vec<LoggedCommand>, seq: Arc<Sequencer>) -> impl FnOnce() {
+    move || {
+        let start = Instant::now();
+        for (idx, (time, command)) in commands.into_iter().enumerate() {
+            let due = start + Duration::from_secs_f64(time.max(0.));
+            loop {
+                if seq.params.read().status == SeqStatus::Shutdown {
+                    println!("Replay interrupted by the shutdown.");
+                    return;
+                }
+                let now = Instant::now();
+                if due <= now {
+                    break;
+                }
+                thread::sleep((due - now).min(REPLAY_POLL_PERIOD));
+            }
+            let (addr, args) = match command {
+                Ok(command) => command,
+                Err(e) => {
+                    eprintln!("Logged command nb {idx} could not be replayed: {e}");
+                    continue;
+                }
+            };
+            if is_shutdown(&addr, &args) {
+                println!("Skipping the replay of the shutdown {addr}");
+                continue;
+            }
+            println!("Replaying osc msg {addr} {args:?}");
+            if let Err(e) = command_handling(&addr, &args, &seq) {
+                eprintln!("Replayed OSC message handling failed with: {e:?}");
+            }
+        }
+        println!("Replay done.");
+    }
+}
+
+#[test]
+fn test_command_log() {
+    let args = vec![
+        OscType::Int(2),
+        OscType::Float(140.),
+        OscType::Float(1e-7),
+        OscType::String("C#4 \"x\"".to_string()),
+        OscType::Bool(true),
+    ];
+    let line = command_line(1.5, "/gisele/set_bpm", &args);
+    let skipped = command_line(2., "/gisele/set_status", &[OscType::Int(3)]);
+    let commands = parse_log(&format!("{line}\n\n{skipped}\n")).unwrap();
+    assert_eq!(commands.len(), 2);
+    let (time, Ok((addr, parsed_args))) = &commands[0] else {
+        panic!("The command should be parsed back");
+    };
+    assert_eq!(*time, 1.5);
+    assert_eq!(addr, "/gisele/set_bpm");
+    assert_eq!(*parsed_args, args);
+    let Ok((addr, args)) = &commands[1].1 else {
+        panic!("The command should be parsed back");
+    };
+    assert!(is_shutdown(addr, args));
+    assert!(!is_shutdown("/gisele/set_bpm", parsed_args));
+
+    // Not representable in JSON, logged as null and only failing on its own
+    let line = command_line(0., "/gisele/set_base_seq_seed", &[OscType::Long(7)]);
+    assert!(parse_log(&line).unwrap()[0].1.is_err());
+    assert!(parse_log("{ \"addr\": \"/gisele/start\" }").is_err());
+}
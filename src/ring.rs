@@ -0,0 +1,74 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Fixed capacity queue between a producer and a consumer thread, lock free and preallocated so
+/// that the jack process can push to it without blocking nor allocating
+pub struct SpscRing<T> {
+    slots: Box<[UnsafeCell<Option<T>>]>,
+    /// Index of the next slot to pop, only moved by the consumer
+    head: AtomicUsize,
+    /// Index of the next slot to push, only moved by the producer
+    tail: AtomicUsize,
+}
+
+// The slots in [head, tail) are only accessed by the consumer, the others by the producer
+unsafe impl<T: Send> Sync for SpscRing<T> {}
+
+impl<T> SpscRing<T> {
+    pub fn new(capacity: usize) -> Self {
+        // One slot is kept empty to tell a full ring from an empty one
+        SpscRing {
+            slots: (0..=capacity).map(|_| UnsafeCell::new(None)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Push an item, handed back when the ring is full
+    ///
+    /// # Safety
+    /// Only one thread at a time may push
+    pub unsafe fn push(&self, item: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % self.slots.len();
+        if next == self.head.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        // The slot was emptied by its last pop, nothing is freed here
+        *self.slots[tail].get() = Some(item);
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Pop the oldest item, if any
+    ///
+    /// # Safety
+    /// Only one thread at a time may pop
+    pub unsafe fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let item = (*self.slots[head].get()).take();
+        self.head
+            .store((head + 1) % self.slots.len(), Ordering::Release);
+        item
+    }
+}
+
+#[test]
+fn test_spsc_ring() {
+    let ring = SpscRing::new(2);
+    unsafe {
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.push(1), Ok(()));
+        assert_eq!(ring.push(2), Ok(()));
+        assert_eq!(ring.push(3), Err(3));
+        assert_eq!(ring.pop(), Some(1));
+        // Wrapping around the slots
+        assert_eq!(ring.push(3), Ok(()));
+        assert_eq!(ring.pop(), Some(2));
+        assert_eq!(ring.pop(), Some(3));
+        assert_eq!(ring.pop(), None);
+    }
+}
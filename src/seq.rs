@@ -1,30 +1,58 @@
 use anyhow::{anyhow, bail};
-use jack::{MidiWriter, ProcessScope};
 use num_derive::FromPrimitive;
 use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard};
 use rand::rngs::StdRng;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rand_distr::{Distribution, Normal};
 use rust_music_theory::note::Note;
+use rust_music_theory::scale::Mode;
 use std::cmp::min;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use strum::EnumString;
 
-use crate::jackp::send_event;
-use crate::midi::{gen_euclid_midi_vec, gen_rand_midi_vec, note_to_midi_pitch, MidiNote};
-use crate::seq::BaseSeqType::{Euclid, Random};
+use crate::jackp::{frame_offset, send_event, MidiSink};
+use crate::midi::{
+    apply_gate_ratio, apply_grid_velocity, apply_rhythm_mask, apply_velocity_contour,
+    ascii_piano_roll, connect_notes, dedup_notes, diatonic_shift, double_notes,
+    durations_to_events, events_to_durations, gen_euclid, gen_euclid_midi_vec, gen_rand_midi_vec,
+    gen_step_midi_vec, get_raw_cc_bytes, glide_events, invert_pitch, midi_pitch_to_note,
+    mirrored_notes, note_to_midi_pitch, quantize_positions, rotate_pos, snap_to_ticks,
+    strum_chords, swing_pos, trim_silence, MidiNote, MirroredNote, PitchBend, PITCH_BEND_RANGE,
+};
+use crate::monitor::MidiMonitor;
+use crate::ring::SpscRing;
+use crate::seq::BaseSeqType::{Custom, Euclid, Harmony, Random, Steps};
 
 #[derive(Debug, Clone)]
 pub struct Event {
     pub e_type: EventType,
     /// Nb bars from sequence start (i.e. position on grid)
     pub bar_pos: f32,
+    /// In bars. Length of a note on whose note off is derived at send time,
+    /// None if the note off is a separate event of the EventBuffer.
+    pub duration: Option<f32>,
 }
 
 impl Event {
-    fn _is_note_on_off(&self) -> bool {
+    /// Key by which the EventBuffer is sorted: the bar_pos, then the note offs before the note ons,
+    /// so that a note ending where another one starts is released first
+    pub fn sort_key(&self) -> (u32, bool) {
+        let is_note_on = matches!(
+            self.e_type,
+            EventType::MidiNote(MidiNote { on_off: true, .. })
+        );
+        (
+            (self.bar_pos * SORT_TICKS_PER_BAR as f32) as u32,
+            is_note_on,
+        )
+    }
+
+    pub fn is_note_on(&self) -> bool {
         match self.e_type {
-            EventType::MidiNoteOn(n) | EventType::MidiNoteOff(n) => n.on_off,
+            EventType::MidiNote(n) => n.on_off,
+            EventType::PitchBend(_) => false,
             EventType::_Fill => unimplemented!(),
         }
     }
@@ -32,8 +60,8 @@ impl Event {
 
 #[derive(Debug, Clone)]
 pub enum EventType {
-    MidiNoteOn(MidiNote),
-    MidiNoteOff(MidiNote),
+    MidiNote(MidiNote),
+    PitchBend(PitchBend),
     _Fill,
 }
 
@@ -47,45 +75,270 @@ pub struct Sequencer {
     /// Internal sequencer parameters
     /// Write: Jack process, Read: OSC process
     pub internal: Arc<RwLock<SeqInternal>>,
+    /// Notes currently held on the midi input, indexed by pitch
+    /// Write: Jack process, Read: OSC process
+    pub held_notes: Arc<RwLock<[bool; 128]>>,
+    /// Counters of the midi messages going through, lock free to be updated by the Jack process
+    pub monitor: Arc<MidiMonitor>,
+    /// Random generator picking the BaseSeq params when randomizing, seeded from the master seed
+    pub dice_rng: Arc<RwLock<StdRng>>,
+    /// Tempo synced modulations of the sequencer parameters
+    /// Write: OSC process, Read: Jack process
+    pub lfos: Arc<RwLock<Vec<Lfo>>>,
+    /// Events captured so far by the recording in progress, see [Sequencer::record_start]
+    /// Write: OSC process
+    pub recording: Arc<RwLock<Option<Recording>>>,
+    /// Whether the events sent are pushed to the record queue, lock free for the Jack process
+    pub recording_on: Arc<AtomicBool>,
+    /// Events sent while recording, with their send time in bars since start, drained into the
+    /// recording by the OSC process, see [Sequencer::drain_record_queue]
+    /// Write: Jack process, Read: OSC process
+    pub record_queue: Arc<SpscRing<(f64, Event)>>,
+    /// Stored BaseSeqs to switch between, see [Sequencer::save_scene]
+    /// Write: OSC process
+    pub scenes: Arc<RwLock<Vec<Scene>>>,
+    /// Fixed phase relationships between BaseSeqs, see [Sequencer::phase_lock]
+    /// Write: OSC process
+    pub phase_locks: Arc<RwLock<Vec<PhaseLock>>>,
+    /// Fills of the BaseSeqs before the quantized scene changes, see [Sequencer::set_auto_fill]
+    /// Write: OSC process, Read: Jack process
+    pub auto_fills: Arc<RwLock<Vec<AutoFill>>>,
+}
+
+/// Phase of a follower BaseSeq kept at an offset from the one of its leader
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhaseLock {
+    pub leader_id: u32,
+    pub follower_id: u32,
+    /// In bars, the follower position being ahead of the leader one by the offset
+    pub offset: f32,
+}
+
+/// First of the BaseSeq ids chosen by the caller, see [Sequencer::ensure_base_seq].
+/// The auto assigned ids below it are counted by [SeqParams::incr], shared by the BaseSeqs and
+/// the fx processors, so that the fx processor ids use up the BaseSeq ones too.
+pub const CALLER_ID_START: u32 = 1 << 16;
+
+/// Nb of events sent that can wait in the record queue until the OSC process drains it
+const RECORD_QUEUE_CAPACITY: usize = 4_096;
+
+/// Max nb of octaves of a Random BaseSeq, about the span of the midi pitches
+const MAX_OCTAVES: u32 = 10;
+
+/// Fill of a BaseSeq over the last bars of the meta loop before a quantized scene change
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoFill {
+    pub base_seq_id: u32,
+    pub bars_before: f32,
 }
 
 impl Sequencer {
     pub fn new(bpm: f32) -> Self {
+        let master_seed = rand::random();
         let seq_params = SeqParams {
             status: SeqStatus::Stop,
             bpm,
             incr: 0,
+            master_seed,
+            time_unit: TimeUnit::Bars,
+            note_off_as_velocity_zero: false,
+            notes_off_velocity: 1,
+            master_shuffle: 0.,
+            reply_on_result: false,
+            meta_loop_bars: 16,
+            capture_quantize: None,
+            capture_swing: 0.,
+            ticks_per_bar: DEFAULT_TICKS_PER_BAR,
+            randomize_bounds: RandomizeBounds::default(),
+            bpm_range: DEFAULT_BPM_RANGE,
+            velocity_curve: VelocityCurve::Linear,
+            addr_prefix: DEFAULT_ADDR_PREFIX.to_string(),
+            focused_base_seq: None,
+            encoder_curve: EncoderCurve::default(),
+            soft_takeover: false,
+            encoder_takeover: EncoderTakeover::default(),
+            max_events_per_cycle: None,
+            max_polyphony: None,
+            grid_feedback: None,
+            direction: Direction::Forward,
         };
         Sequencer {
             params: Arc::new(RwLock::new(seq_params)),
             base_seqs: Arc::new(RwLock::new(vec![])),
             internal: Arc::new(RwLock::new(SeqInternal::new())),
             fx_procs: Arc::new(RwLock::new(vec![])),
+            held_notes: Arc::new(RwLock::new([false; 128])),
+            monitor: Arc::new(MidiMonitor::default()),
+            dice_rng: Arc::new(RwLock::new(StdRng::seed_from_u64(master_seed))),
+            lfos: Arc::new(RwLock::new(vec![])),
+            recording: Arc::new(RwLock::new(None)),
+            recording_on: Arc::new(AtomicBool::new(false)),
+            record_queue: Arc::new(SpscRing::new(RECORD_QUEUE_CAPACITY)),
+            scenes: Arc::new(RwLock::new(vec![])),
+            phase_locks: Arc::new(RwLock::new(vec![])),
+            auto_fills: Arc::new(RwLock::new(vec![])),
         }
     }
 
+    /// The events are generated before taking the sequencer locks, which are only held to insert
+    /// the BaseSeq, so that a long generation does not block the jack process
     pub fn add_base_seq(&self, base_seq_params: BaseSeqParams) -> anyhow::Result<()> {
+        self.insert_base_seq(base_seq_params, None)?;
+        Ok(())
+    }
+
+    /// Create the BaseSeq under the id chosen by the caller, or update its params and regenerate
+    /// it when it already exists, so that setup scripts can be run again.
+    /// The caller ids start at [CALLER_ID_START], out of the range of the auto assigned ones, so
+    /// that a caller id never ends up on a BaseSeq added in another way.
+    /// Returns whether the BaseSeq was created.
+    pub fn ensure_base_seq(
+        &self,
+        base_seq_id: u32,
+        base_seq_params: BaseSeqParams,
+    ) -> anyhow::Result<bool> {
+        if base_seq_id < CALLER_ID_START {
+            bail!("The caller chosen base sequence ids start at {CALLER_ID_START}.");
+        }
+        self.insert_base_seq(base_seq_params, Some(base_seq_id))
+    }
+
+    /// Insert a new BaseSeq under the given id, or the next auto assigned one. A BaseSeq already
+    /// under the given id is updated in place instead. Returns whether the BaseSeq was inserted.
+    fn insert_base_seq(
+        &self,
+        base_seq_params: BaseSeqParams,
+        base_seq_id: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        // The id, and the playback seed derived from it, are only known once the params are locked
+        let ticks_per_bar = self.params.read().ticks_per_bar;
+        let mut base_seq = BaseSeq::new(base_seq_params, 0, 0, ticks_per_bar);
+        let events = base_seq.gen_events()?;
+
+        let mut seq_params = self.params.write();
+        let mut base_seqs = self.base_seqs.write();
+        // Looked up under the insertion guard, so that concurrent calls insert it only once
+        if let Some(existing) = base_seq_id.and_then(|id| base_seqs.iter().find(|b| b.id == id)) {
+            let mut existing_params = existing.params.write();
+            if std::mem::discriminant(&existing_params.ty)
+                != std::mem::discriminant(&base_seq.params.read().ty)
+            {
+                bail!(
+                    "Base sequence {} is of another type, remove it first to change its type.",
+                    existing.id
+                );
+            }
+            *existing_params = base_seq.params.read().clone();
+            drop(existing_params);
+            if let Some(events) = events {
+                existing.load_events(events, &self.internal.read());
+            }
+            return Ok(false);
+        }
+        *base_seq.event_buffer.write() = events.unwrap_or_default();
+        base_seq.id = match base_seq_id {
+            Some(id) => id,
+            None => seq_params.next_id()?,
+        };
+        base_seq.reseed_playback(seq_params.master_seed);
+        // Synced with the base seqs locked, so that no jack cycle goes by before the insertion
+        base_seq.sync_event_head(&self.internal.read());
+        println!("Inserted base sequence id {}", base_seq.id);
+        base_seqs.push(base_seq);
+        Ok(true)
+    }
+
+    /// Add a Harmony BaseSeq following the source, see [BaseSeq::mirror].
+    /// The source keeps a handle to its followers and updates them whenever its events change,
+    /// when regenerated, evolved or edited. Removing the source leaves the followers as they are.
+    pub fn add_harmony(&self, source_id: u32, interval: i32, midi_ch: u8) -> anyhow::Result<()> {
+        // A handle to the source, not to keep the base seqs locked
+        let source = self.get_base_seq(source_id)?.clone();
+        let mut params = source.params.read().clone();
+        params.ty = Harmony(HarmonyBase {
+            source_id,
+            interval,
+        });
+        params.midi_ch = midi_ch;
+        params.pitch_pool = None;
+        let ticks_per_bar = self.params.read().ticks_per_bar;
+        let mut follower = BaseSeq::new(params, 0, 0, ticks_per_bar);
+        {
+            let mut seq_params = self.params.write();
+            follower.id = seq_params.next_id()?;
+            follower.reseed_playback(seq_params.master_seed);
+        }
+        // Registered before the copy, so that no edit of the source goes by unmirrored
+        source.followers.write().push(follower.clone());
+        // Also syncs its head, resynced below in case a jack cycle went by meanwhile
+        follower.mirror(&source, &self.internal.read());
+
+        let mut base_seqs = self.base_seqs.write();
+        follower.resync_event_head(&self.internal.read());
+        println!("Inserted harmony base sequence id {}", follower.id);
+        base_seqs.push(follower);
+        Ok(())
+    }
+
+    /// Add a Steps BaseSeq playing on the rests of a Euclid one, the off pulses of the source
+    /// becoming its on pulses. Its loop and steps are the ones of the source.
+    pub fn add_euclid_complement(&self, source_id: u32, midi_ch: u8) -> anyhow::Result<()> {
+        let mut params = self.get_base_seq(source_id)?.params.read().clone();
+        let Euclid(EuclidBase { pulses, steps }) = params.ty else {
+            bail!("The source base sequence should be Euclidean.");
+        };
+        let pattern = gen_euclid(pulses, steps)?.iter().map(|s| *s == 0).collect();
+        params.ty = Steps(StepsBase { pattern });
+        params.midi_ch = midi_ch;
+        self.add_base_seq(params)
+    }
+
+    /// Add a Custom BaseSeq playing the given events, which should be sorted by bar_pos
+    pub fn add_custom_base_seq(
+        &self,
+        base_seq_params: BaseSeqParams,
+        events: Vec<Event>,
+    ) -> anyhow::Result<()> {
+        // Built before taking the sequencer locks, as in [Sequencer::add_base_seq]
+        let ticks_per_bar = self.params.read().ticks_per_bar;
+        let (loop_length, explicit_durations) = (
+            base_seq_params.loop_length,
+            base_seq_params.explicit_durations,
+        );
+        let mut events = snap_to_ticks(events, ticks_per_bar, loop_length);
+        if explicit_durations {
+            events = events_to_durations(events, loop_length);
+        }
+        let mut base_seq = BaseSeq::new(base_seq_params, 0, 0, ticks_per_bar);
+        *base_seq.event_buffer.write() = events;
+        base_seq.sync_event_head(&self.internal.read());
+
         let mut seq_params = self.params.write();
-        let base_seq = BaseSeq::new_fill(base_seq_params, seq_params.incr, &self.internal.read())?;
-        self.base_seqs.write().push(base_seq);
-        println!("Inserted base sequence id {}", seq_params.incr);
-        seq_params.incr += 1;
+        base_seq.id = seq_params.next_id()?;
+        base_seq.reseed_playback(seq_params.master_seed);
+        let mut base_seqs = self.base_seqs.write();
+        // Only the head is resynced with the base seqs locked, in case a jack cycle went by
+        base_seq.resync_event_head(&self.internal.read());
+        println!("Inserted custom base sequence id {}", base_seq.id);
+        base_seqs.push(base_seq);
         Ok(())
     }
 
-    pub fn add_fx_processor(&self, base_seq_id: u32) -> anyhow::Result<()> {
+    pub fn add_fx_processor(&self, base_seq_id: u32, kind: FxKind) -> anyhow::Result<()> {
         let mut seq_params = self.params.write();
-        let fx_proc = FxProcessor::new(seq_params.incr);
         let base_seq = self.get_base_seq(base_seq_id)?;
+        let fx_proc = FxProcessor::new(seq_params.next_id()?, kind);
+        println!("Inserted fx processor id {}", fx_proc.id);
         base_seq.fx_proc_ids.write().push(fx_proc.id);
         self.fx_procs.write().push(fx_proc);
-        println!("Inserted fx processor id {}", seq_params.incr);
-        seq_params.incr += 1;
         Ok(())
     }
 
     /// BaseSeq getter, mapping the lock contents in order to preserve the lifetime
-    pub fn get_base_seq(&self, base_seq_id: u32) -> anyhow::Result<MappedRwLockReadGuard<BaseSeq>> {
+    pub fn get_base_seq(
+        &self,
+        base_seq_id: u32,
+    ) -> anyhow::Result<MappedRwLockReadGuard<'_, BaseSeq>> {
         RwLockReadGuard::try_map(self.base_seqs.read(), |p| {
             p.iter().find(|s| s.id == base_seq_id)
         })
@@ -96,401 +349,5767 @@ impl Sequencer {
     pub fn get_fx_proc(
         &self,
         fx_proc_id: u32,
-    ) -> anyhow::Result<MappedRwLockReadGuard<FxProcessor>> {
+    ) -> anyhow::Result<MappedRwLockReadGuard<'_, FxProcessor>> {
         RwLockReadGuard::try_map(self.fx_procs.read(), |p| {
             p.iter().find(|f| f.id == fx_proc_id)
         })
         .map_err(|_| anyhow::format_err!("Base sequence {fx_proc_id} could not be found."))
     }
 
-    pub fn regen_base_seq(&self, base_seq_id: u32) -> anyhow::Result<()> {
-        let base_seq = self.get_base_seq(base_seq_id)?;
-        base_seq.gen_fill(&self.internal.read())?;
+    pub fn set_fx_enabled(&self, fx_proc_id: u32, enabled: bool) -> anyhow::Result<()> {
+        let fx_proc = self.get_fx_proc(fx_proc_id)?;
+        *fx_proc.enabled.write() = enabled;
         Ok(())
     }
 
-    pub fn change_note_len(&self, base_seq_id: u32, target_note_len: f32) -> anyhow::Result<()> {
+    /// Rewrite the order in which the fx processors of a BaseSeq are applied.
+    /// The given ids must be a permutation of the ones currently attached to the BaseSeq.
+    pub fn reorder_fx(&self, base_seq_id: u32, fx_proc_ids: Vec<u32>) -> anyhow::Result<()> {
         let base_seq = self.get_base_seq(base_seq_id)?;
-        base_seq.change_note_len(target_note_len, &self.internal.read())
+        let mut curr_ids = base_seq.fx_proc_ids.read().clone();
+        let mut new_ids = fx_proc_ids.clone();
+        curr_ids.sort();
+        new_ids.sort();
+        if curr_ids != new_ids {
+            bail!(
+                "The given fx processor ids do not match the ones of base sequence {base_seq_id}."
+            );
+        }
+        *base_seq.fx_proc_ids.write() = fx_proc_ids;
+        Ok(())
     }
 
-    pub fn change_loop_len(&self, base_seq_id: u32, target_loop_len: f32) -> anyhow::Result<()> {
+    pub fn regen_base_seq(&self, base_seq_id: u32) -> anyhow::Result<()> {
         let base_seq = self.get_base_seq(base_seq_id)?;
-        base_seq.params.write().loop_length = target_loop_len;
+        base_seq.gen_fill(&self.internal.read())?;
         Ok(())
     }
 
-    pub fn set_nb_events(&self, base_seq_id: u32, target_nb_events: u32) -> anyhow::Result<()> {
+    /// Freeze a BaseSeq while the others keep playing. Its channel is flushed on pause,
+    /// and its head synced on unpause to resume from the current position.
+    pub fn pause_base_seq(&self, base_seq_id: u32, paused: bool) -> anyhow::Result<()> {
         let base_seq = self.get_base_seq(base_seq_id)?;
-        base_seq.set_nb_events(target_nb_events, &self.internal.read())?;
+        let mut base_seq_paused = base_seq.paused.write();
+        if *base_seq_paused == paused {
+            return Ok(());
+        }
+        *base_seq_paused = paused;
+        if paused {
+            let midi_ch = base_seq.params.read().midi_ch;
+            self.internal.write().channel_flushes.push(midi_ch);
+        } else {
+            base_seq.sync_event_head(&self.internal.read());
+        }
         Ok(())
     }
 
-    pub fn transpose(&self, base_seq_id: u32, target_root_note: Note) -> anyhow::Result<()> {
+    /// Play a one-shot glide on the channel of a BaseSeq, starting with the next jack cycle,
+    /// see [glide_events]. The pitches should be within the pitch bend range of each other.
+    pub fn glide(
+        &self,
+        base_seq_id: u32,
+        from_pitch: u8,
+        to_pitch: u8,
+        bars: f32,
+    ) -> anyhow::Result<()> {
+        if (to_pitch as f32 - from_pitch as f32).abs() > PITCH_BEND_RANGE {
+            bail!("The glide should stay within the {PITCH_BEND_RANGE} semitones of the pitch bend range.");
+        }
+        if bars <= 0. {
+            bail!("The glide should last more than 0 bars.");
+        }
         let base_seq = self.get_base_seq(base_seq_id)?;
-        base_seq.transpose(target_root_note)?;
+        let (channel, velocity) = {
+            let params = base_seq.params.read();
+            (params.midi_ch, params.velocity_avg)
+        };
+        let events = glide_events(channel, from_pitch, to_pitch, velocity, bars);
+        let mut seq_int = self.internal.write();
+        let start = seq_int.j_window_time_end;
+        seq_int.scheduled_events.extend(
+            events
+                .into_iter()
+                .map(|e| (start + e.bar_pos as f64, base_seq_id, e)),
+        );
         Ok(())
     }
 
-    /// Delete all BaseSeqs, empty the EventBuffers
-    pub fn empty(&self) {
-        *self.base_seqs.write() = vec![];
-        let mut seq_params = self.params.write();
-        seq_params.incr = 0;
+    /// Mute or unmute a region of a BaseSeq. Only its note ons are skipped, so that the notes
+    /// started before the region still end.
+    pub fn mute_region(
+        &self,
+        base_seq_id: u32,
+        start: f32,
+        end: f32,
+        muted: bool,
+    ) -> anyhow::Result<()> {
+        if start < 0. || end < 0. || start == end {
+            bail!("The region should have a positive start and end, and not be empty.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        let mut muted_regions = base_seq.muted_regions.write();
+        muted_regions.retain(|region| *region != (start, end));
+        if muted {
+            muted_regions.push((start, end));
+        }
+        Ok(())
     }
 
-    pub fn reset_base_seqs(&self) {
-        for base_seq in &*self.base_seqs.read() {
-            *base_seq.event_head.write() = 0;
+    /// Bypass the whole fx chain of a BaseSeq. As the pitches of the notes sounding may differ
+    /// from the ones of their note offs, its channel is flushed on change.
+    pub fn set_fx_bypass(&self, base_seq_id: u32, bypassed: bool) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        let mut fx_bypassed = base_seq.fx_bypassed.write();
+        if *fx_bypassed != bypassed {
+            *fx_bypassed = bypassed;
+            let midi_ch = base_seq.params.read().midi_ch;
+            self.internal.write().channel_flushes.push(midi_ch);
         }
+        Ok(())
     }
 
-    pub fn notes_off(&self, ps: &ProcessScope, out_buff: &mut MidiWriter) {
-        let mut midi_chs = self
-            .base_seqs
-            .read()
-            .iter()
-            .map(|b| b.params.read().midi_ch)
-            .collect::<Vec<u8>>();
-        midi_chs.sort();
-        midi_chs.dedup();
-        for ch in midi_chs {
-            for pitch in 0..128 {
-                send_event(
-                    ps,
-                    out_buff,
-                    &Event {
-                        e_type: EventType::MidiNoteOff(MidiNote {
-                            on_off: false,
-                            channel: ch,
-                            pitch,
-                            velocity: 1u8,
-                        }),
-                        bar_pos: 0.,
-                    },
-                )
-            }
-        }
+    pub fn set_evolve(&self, base_seq_id: u32, evolve: bool) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        *base_seq.evolve.write() = evolve;
+        Ok(())
     }
 
-    pub fn remove_base_seq(&self, base_seq_id: u32) -> anyhow::Result<()> {
-        let index = self
-            .base_seqs
-            .read()
-            .iter()
-            .position(|b| b.id == base_seq_id)
-            .ok_or_else(|| anyhow!("Could not find base sequence of id {base_seq_id}"))?;
-        self.base_seqs.write().remove(index);
+    /// Defer the regenerations of a BaseSeq to its loop boundary. Turning it off swaps in
+    /// the pending events right away.
+    pub fn set_regen_on_loop(&self, base_seq_id: u32, regen_on_loop: bool) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        *base_seq.regen_on_loop.write() = regen_on_loop;
+        if !regen_on_loop {
+            base_seq.swap_pending(&self.internal.read());
+        }
         Ok(())
     }
 
-    pub fn process_event(&self, proc_ids: &Vec<u32>, event: &mut Event) {
-        for fx_proc_id in proc_ids {
-            if let Ok(fx_proc) = self.get_fx_proc(*fx_proc_id) {
-                fx_proc.process(event);
-            }
+    pub fn set_wrap_note_off(&self, base_seq_id: u32, wrap_note_off: bool) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        if !wrap_note_off && base_seq.params.read().rewraps_note_offs() {
+            bail!("The note offs are wrapped by the legato, gate ratio, doublings or durations.");
         }
+        base_seq.params.write().wrap_note_off = wrap_note_off;
+        base_seq.gen_fill(&self.internal.read())
     }
-}
 
-#[derive(Clone, PartialEq, Eq, EnumString, Debug, FromPrimitive)]
-pub enum SeqStatus {
-    /// Pause ans reset sequencer to start position
-    Stop,
-    Start,
-    Pause,
-    /// Sequencer is set to shutdown gracefully
-    Shutdown,
-}
+    pub fn set_legato(&self, base_seq_id: u32, legato: bool) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        if legato {
+            base_seq.check_wrap_note_off()?;
+        }
+        base_seq.params.write().legato = legato;
+        base_seq.gen_fill(&self.internal.read())
+    }
 
-pub struct SeqParams {
-    pub status: SeqStatus,
-    pub bpm: f32,
-    /// Counter of total nb of BaseSeqs/FxProcessor ever created, used for id
-    pub incr: u32,
-}
+    /// Seed from which the events of the BaseSeq were last generated
+    pub fn base_seq_seed(&self, base_seq_id: u32) -> anyhow::Result<u64> {
+        Ok(*self.get_base_seq(base_seq_id)?.seed.read())
+    }
 
-//////////////////////////////////////////////////////////////////////////
-/// Base Sequences
+    /// Regenerate the events of the BaseSeq from a seed, see [Sequencer::base_seq_seed]
+    pub fn set_base_seq_seed(&self, base_seq_id: u32, seed: u64) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.reseed(seed, &self.internal.read())
+    }
 
-#[derive(Clone, Debug)]
-pub enum BaseSeqType {
-    Random(RandomBase),
-    Euclid(EuclidBase),
-}
+    /// Scale the velocities by the distance of the notes to a grid of the given division,
+    /// a strength of 0 removing it
+    pub fn set_grid_velocity(
+        &self,
+        base_seq_id: u32,
+        division: u32,
+        strength: f32,
+    ) -> anyhow::Result<()> {
+        if division == 0 {
+            bail!("The grid division should be at least 1.");
+        }
+        if !(-1. ..=1.).contains(&strength) {
+            bail!("The grid velocity strength should be between -1 and 1.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.params.write().grid_velocity =
+            Some(GridVelocity { division, strength }).filter(|_| strength != 0.);
+        base_seq.gen_fill(&self.internal.read())
+    }
 
-#[derive(Clone, Debug)]
-pub struct BaseSeqParams {
-    pub ty: BaseSeqType,
-    /// In bars, 16 is 4 measures
-    pub loop_length: f32,
-    pub root_note: Note,
-    /// In bars
-    pub note_len_avg: f32,
-    /// Standard deviation from average value note_len in normal random generation
-    /// In bars
-    pub note_len_div: f32,
-    /// In midi range (0-127)
-    pub velocity_avg: u8,
-    /// Standard deviation from average value velocity in normal random generation
-    pub velocity_div: f32,
-    /// Channel, should be 1-16
-    pub midi_ch: u8,
-}
+    pub fn set_vel_len_coupling(&self, base_seq_id: u32, amount: f32) -> anyhow::Result<()> {
+        if !amount.is_finite() {
+            bail!("The velocity to length coupling should be finite.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.params.write().vel_len_coupling = amount;
+        base_seq.gen_fill(&self.internal.read())
+    }
 
-/// State of a base sequence that is generated and inserted into the EventBuffer
-pub struct BaseSeq {
-    pub params: Arc<RwLock<BaseSeqParams>>,
-    /// Current position in the event buffer.
-    /// Write: OSC + Jack process
-    pub event_head: Arc<RwLock<usize>>,
-    /// Identifies events in the EventBuffer
-    /// Event Buffer
-    /// Events are ordered by their times
-    /// Write: OSC process, Read: Jack process
-    pub event_buffer: Arc<RwLock<Vec<Event>>>,
-    /// FxProcessor ids to which the BaseSeq feeds events
-    pub fx_proc_ids: Arc<RwLock<Vec<u32>>>,
-    /// Unique identifier to the base_seq
-    pub id: u32,
-}
+    /// Set the note lengths to the ratio, clamped to 1, of the gap to the next note on.
+    /// A ratio of 0 removes it, the notes getting their generated lengths back.
+    pub fn set_gate_ratio(&self, base_seq_id: u32, ratio: f32) -> anyhow::Result<()> {
+        if !(ratio.is_finite() && ratio >= 0.) {
+            bail!("The gate ratio should be positive.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        if ratio == 0. {
+            base_seq.params.write().gate_ratio = None;
+            base_seq.gen_fill(&self.internal.read())
+        } else {
+            base_seq.check_wrap_note_off()?;
+            base_seq.set_gate_ratio(ratio.min(1.), &self.internal.read());
+            Ok(())
+        }
+    }
 
-impl BaseSeq {
-    /// Create a new base sequence and fill its event buffer.
-    /// The jack process window end time gives a reference point to the present time for the synchronizing
-    /// of the BaseSeq event_head
-    fn new_fill(params: BaseSeqParams, id: u32, seq_int: &SeqInternal) -> anyhow::Result<BaseSeq> {
-        let base_seq = BaseSeq {
-            params: Arc::new(RwLock::new(params)),
-            event_head: Arc::new(RwLock::new(0)),
-            event_buffer: Arc::new(RwLock::new(vec![])),
-            fx_proc_ids: Arc::new(RwLock::new(vec![])),
-            id,
-        };
-        base_seq.gen_fill(seq_int)?;
-        Ok(base_seq)
+    /// Set the velocity multipliers of the sixteenth steps, an empty contour removes it
+    pub fn set_velocity_contour(&self, base_seq_id: u32, contour: Vec<f32>) -> anyhow::Result<()> {
+        if contour.iter().any(|m| !(m.is_finite() && *m >= 0.)) {
+            bail!("The velocity contour multipliers should be positive.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.params.write().velocity_contour = Some(contour).filter(|c| !c.is_empty());
+        base_seq.gen_fill(&self.internal.read())
     }
 
-    /// Fill the event buffer of a BaseSeq.
-    /// The jack process window end time gives a reference point to the present time for the synchronizing
-    /// of the BaseSeq event_head
-    fn gen_fill(&self, seq_int: &SeqInternal) -> anyhow::Result<()> {
-        //Insert events
-        let mut events = match self.params.read().ty {
-            Random(_) => gen_rand_midi_vec(self),
-            Euclid(_) => gen_euclid_midi_vec(self)?,
-        };
-        events.sort_by_key(|e| (e.bar_pos * 1_000.) as u32); //TODO use FP32 instead
-        *self.event_buffer.write() = events;
-        self.sync_event_head(seq_int);
+    pub fn set_explicit_durations(
+        &self,
+        base_seq_id: u32,
+        explicit_durations: bool,
+    ) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        if explicit_durations {
+            base_seq.check_wrap_note_off()?;
+        }
+        base_seq.set_explicit_durations(explicit_durations, &self.internal.read());
         Ok(())
     }
 
-    fn sync_event_head(&self, seq_int: &SeqInternal) {
-        // Reset event_head to next idx right after the current jack window
-        // The preliminary binary search is an optional optimization.
-        let event_buffer = self.event_buffer.read();
-        let mut new_head = match event_buffer.binary_search_by_key(
-            &(1_000
-                * ((seq_int.j_window_time_end % (self.params.read().loop_length as f64)) as u32)),
-            |e| ((e.bar_pos * 1_000.) as u32),
-        ) {
-            Ok(idx) | Err(idx) => idx,
-        };
-
-        if new_head == event_buffer.len() {
-            new_head = 0;
-        } else if let Some(idx) = event_buffer[new_head..]
-            .iter()
-            .position(|e| e.bar_pos > event_buffer[new_head].bar_pos)
-        {
-            // As the return of the binary search for multiple matches is arbitrary,
-            // we look for the exact event.
-            new_head += idx;
-        } else {
-            new_head = 0;
+    /// Each loop, the play probability of the BaseSeq note ons is multiplied by the factor
+    pub fn set_decay(&self, base_seq_id: u32, factor: f32) -> anyhow::Result<()> {
+        if !(0. ..=1.).contains(&factor) {
+            bail!("Decay factor should be between 0 and 1.");
         }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.decay.write().factor = factor;
+        Ok(())
+    }
 
-        *self.event_head.write() = min(new_head, event_buffer.len().saturating_sub(1));
+    pub fn set_note_repeat(&self, base_seq_id: u32, prob: f32, count: u32) -> anyhow::Result<()> {
+        if !(0. ..=1.).contains(&prob) {
+            bail!("Note repeat probability should be between 0 and 1.");
+        }
+        if count == 0 {
+            bail!("Note repeat count should be at least 1.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        *base_seq.note_repeat.write() = NoteRepeat { prob, count };
+        Ok(())
+    }
 
-        println!("Event head synced!")
+    /// Set the velocity gain of the note ons of a BaseSeq, see [Gain]
+    pub fn set_gain(&self, base_seq_id: u32, scale: f32, offset: i32) -> anyhow::Result<()> {
+        if scale < 0. {
+            bail!("The gain scale should be positive.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        *base_seq.gain.write() = Gain { scale, offset };
+        Ok(())
     }
 
-    pub(self) fn change_note_len(
+    /// Set the release velocity of the note offs of a BaseSeq, None to keep the note on velocity
+    pub fn set_note_off_velocity(
         &self,
-        target_note_len: f32,
-        seq_int: &SeqInternal,
+        base_seq_id: u32,
+        velocity: Option<u8>,
     ) -> anyhow::Result<()> {
-        let mut params = self.params.write();
-        let mut event_buff = self.event_buffer.write();
-        for event in event_buff.iter_mut() {
-            if let EventType::MidiNoteOn(MidiNote { on_off, .. })
-            | EventType::MidiNoteOff(MidiNote { on_off, .. }) = event.e_type
-            {
-                if !on_off {
-                    event.bar_pos = event.bar_pos + target_note_len - params.note_len_avg;
-                    event.bar_pos %= params.loop_length;
-                }
-            }
+        if velocity.is_some_and(|v| 127 < v) {
+            bail!("The note off velocity should be between 0 and 127.");
         }
-        params.note_len_avg = target_note_len;
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.params.write().note_off_velocity = velocity;
+        Ok(())
+    }
 
-        event_buff.sort_by_key(|e| (e.bar_pos * 1_000.) as u32);
-        self.sync_event_head(seq_int);
+    /// Delay the entry of a BaseSeq by the given nb of bars after every Start. The offset is
+    /// compared to the time since the Start, not to the meta loop position, so it always ends.
+    pub fn set_start_offset(&self, base_seq_id: u32, bars: f32) -> anyhow::Result<()> {
+        if !(0. ..).contains(&bars) {
+            bail!("The start offset should be positive.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        *base_seq.start_offset.write() = bars;
         Ok(())
     }
 
-    pub(self) fn set_nb_events(
+    pub fn reset_decay(&self, base_seq_id: u32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        *base_seq.decay.write() = Decay::default();
+        Ok(())
+    }
+
+    /// Set the bpm, clamped to the bpm range. Returns the bpm set.
+    pub fn set_bpm(&self, bpm: f32) -> anyhow::Result<f32> {
+        // A NaN would pass through the clamp and into the time increments
+        if !bpm.is_finite() {
+            bail!("The bpm should be a finite number.");
+        }
+        let mut seq_params = self.params.write();
+        let (min_bpm, max_bpm) = seq_params.bpm_range;
+        seq_params.bpm = bpm.clamp(min_bpm, max_bpm);
+        Ok(seq_params.bpm)
+    }
+
+    /// Set the bpm range, and clamp the current bpm to it
+    pub fn set_bpm_range(&self, min_bpm: f32, max_bpm: f32) -> anyhow::Result<()> {
+        if !(0. < min_bpm && min_bpm <= max_bpm && max_bpm.is_finite()) {
+            bail!("The bpm range should be positive and given as min then max.");
+        }
+        let bpm = {
+            let mut seq_params = self.params.write();
+            seq_params.bpm_range = (min_bpm, max_bpm);
+            seq_params.bpm
+        };
+        self.set_bpm(bpm)?;
+        Ok(())
+    }
+
+    pub fn set_encoder_curve(&self, encoder_curve: EncoderCurve) -> anyhow::Result<()> {
+        if !(encoder_curve.gain.is_finite() && encoder_curve.gain > 0.) {
+            bail!("The encoder gain should be positive.");
+        }
+        self.params.write().encoder_curve = encoder_curve;
+        Ok(())
+    }
+
+    /// Set the playback direction. The sounding notes are flushed, as their note offs
+    /// are not met anymore once reversed.
+    pub fn set_direction(&self, direction: Direction) {
+        let mut seq_params = self.params.write();
+        if seq_params.direction == direction {
+            return;
+        }
+        seq_params.direction = direction;
+        let base_seqs = self.base_seqs.read();
+        let mut seq_int = self.internal.write();
+        for base_seq in &*base_seqs {
+            let midi_ch = base_seq.params.read().midi_ch;
+            if !seq_int.channel_flushes.contains(&midi_ch) {
+                seq_int.channel_flushes.push(midi_ch);
+            }
+            base_seq.sync_event_head(&seq_int);
+        }
+    }
+
+    /// Focus a BaseSeq for the encoders, or none
+    pub fn set_focus(&self, base_seq_id: Option<u32>) -> anyhow::Result<()> {
+        if let Some(id) = base_seq_id {
+            let _ = self.get_base_seq(id)?;
+        }
+        let mut seq_params = self.params.write();
+        if seq_params.soft_takeover && seq_params.focused_base_seq != base_seq_id {
+            seq_params.encoder_takeover.release();
+        }
+        seq_params.focused_base_seq = base_seq_id;
+        Ok(())
+    }
+
+    /// Set the prefix of the command addresses, e.g. /gisele2
+    pub fn set_addr_prefix(&self, addr_prefix: String) -> anyhow::Result<()> {
+        if !addr_prefix.starts_with('/')
+            || addr_prefix.ends_with('/')
+            || addr_prefix.contains(char::is_whitespace)
+        {
+            bail!("The addr prefix should start with a / and not end with one, e.g. /gisele2.");
+        }
+        self.params.write().addr_prefix = addr_prefix;
+        Ok(())
+    }
+
+    pub fn set_velocity_curve(&self, velocity_curve: VelocityCurve) -> anyhow::Result<()> {
+        let gamma = velocity_curve.gamma();
+        if !(gamma.is_finite() && gamma > 0.) {
+            bail!("The velocity curve gamma should be positive.");
+        }
+        self.params.write().velocity_curve = velocity_curve;
+        Ok(())
+    }
+
+    /// Set the master seed, from which the playback generators of the BaseSeqs are reseeded
+    pub fn set_master_seed(&self, master_seed: u64) {
+        self.params.write().master_seed = master_seed;
+        *self.dice_rng.write() = StdRng::seed_from_u64(master_seed);
+        for base_seq in self.base_seqs.read().iter() {
+            base_seq.reseed_playback(master_seed);
+        }
+    }
+
+    pub fn stutter(&self, base_seq_id: u32, slice_len: f32, repeats: i32) -> anyhow::Result<()> {
+        if slice_len <= 0. {
+            bail!("Stutter slice length should be positive.");
+        }
+        if repeats <= 0 {
+            bail!("Stutter nb of repeats should be positive.");
+        }
+        // Check that the base seq exists
+        drop(self.get_base_seq(base_seq_id)?);
+        self.internal
+            .write()
+            .add_stutter(base_seq_id, slice_len, repeats as u32);
+        Ok(())
+    }
+
+    /// Send the events of the BaseSeq to another channel for the given nb of bars, leaving its
+    /// midi_ch untouched. The stored channel is flushed when the override starts, and the
+    /// override one when it ends, so that no note is left hanging on either.
+    pub fn route_override(&self, base_seq_id: u32, channel: u8, bars: f32) -> anyhow::Result<()> {
+        if bars <= 0. {
+            bail!("Route override length should be positive.");
+        }
+        let midi_ch = self.get_base_seq(base_seq_id)?.params.read().midi_ch;
+        let mut seq_int = self.internal.write();
+        let end = seq_int.j_window_time_end + bars as f64;
+        // A new override of the same BaseSeq replaces the current one
+        if let Some(idx) = seq_int
+            .route_overrides
+            .iter()
+            .position(|r| r.base_seq_id == base_seq_id)
+        {
+            let replaced = seq_int.route_overrides.remove(idx);
+            if !seq_int.channel_flushes.contains(&replaced.channel) {
+                seq_int.channel_flushes.push(replaced.channel);
+            }
+        }
+        if !seq_int.channel_flushes.contains(&midi_ch) {
+            seq_int.channel_flushes.push(midi_ch);
+        }
+        seq_int.route_overrides.push(RouteOverride {
+            base_seq_id,
+            channel,
+            end,
+        });
+        Ok(())
+    }
+
+    /// Spread the notes of the BaseSeq over the channel range, one channel per sounding note,
+    /// or put them back on its midi_ch with None. The channels left are flushed.
+    /// The allocation takes precedence over the route overrides.
+    pub fn set_mpe(&self, base_seq_id: u32, range: Option<(u8, u8)>) -> anyhow::Result<()> {
+        if let Some((low, high)) = range {
+            if !(1 <= low && low <= high && high <= 16) {
+                bail!("The mpe channel range should be ordered, between 1 and 16.");
+            }
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        let midi_ch = base_seq.params.read().midi_ch;
+        let mut mpe_range = base_seq.mpe_range.write();
+        let mut seq_int = self.internal.write();
+        match *mpe_range {
+            Some((low, high)) => seq_int.channel_flushes.extend(low..=high),
+            None => seq_int.channel_flushes.push(midi_ch),
+        }
+        seq_int.mpe_voices.retain(|v| v.base_seq_id != base_seq_id);
+        *mpe_range = range;
+        Ok(())
+    }
+
+    /// Rotate each loop of the BaseSeq by a random offset of up to the amount,
+    /// a fraction of the loop in [0, 1)
+    pub fn set_loop_rotation_jitter(&self, base_seq_id: u32, amount: f32) -> anyhow::Result<()> {
+        if !(0. ..1.).contains(&amount) {
+            bail!("Loop rotation jitter should be between 0 and 1.");
+        }
+        // Check that the base seq exists
+        drop(self.get_base_seq(base_seq_id)?);
+        self.internal.write().set_loop_rotation(base_seq_id, amount);
+        Ok(())
+    }
+
+    /// Duck the velocities of the target BaseSeq by the amount, in [0, 1], on every note on
+    /// of the trigger BaseSeq, recovering linearly over the release
+    pub fn add_duck(
         &self,
-        target_nb_events: u32,
-        seq_int: &SeqInternal,
+        trigger_id: u32,
+        target_id: u32,
+        amount: f32,
+        release: f32,
     ) -> anyhow::Result<()> {
-        let mut params = self.params.write();
-        if let BaseSeqParams {
-            ty: Random(RandomBase { ref mut nb_events }),
-            ..
-        } = *params
+        if !(0. ..=1.).contains(&amount) {
+            bail!("Duck amount should be between 0 and 1.");
+        }
+        if release <= 0. {
+            bail!("Duck release should be positive.");
+        }
+        // Check that the base seqs exist
+        drop(self.get_base_seq(trigger_id)?);
+        drop(self.get_base_seq(target_id)?);
+        self.internal
+            .write()
+            .add_duck(trigger_id, target_id, amount, release);
+        Ok(())
+    }
+
+    pub fn change_note_len(&self, base_seq_id: u32, target_note_len: f32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.change_note_len(target_note_len, &self.internal.read())
+    }
+
+    pub fn change_loop_len(&self, base_seq_id: u32, target_loop_len: f32) -> anyhow::Result<()> {
+        check_loop_len(target_loop_len)?;
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.change_loop_len(target_loop_len, &self.internal.read());
+        Ok(())
+    }
+
+    /// Set the same loop length on all BaseSeqs, returns the nb of BaseSeqs updated
+    pub fn change_loop_len_all(&self, target_loop_len: f32) -> anyhow::Result<usize> {
+        check_loop_len(target_loop_len)?;
+        let base_seqs = self.base_seqs.read();
+        for base_seq in base_seqs.iter() {
+            base_seq.change_loop_len(target_loop_len, &self.internal.read());
+        }
+        Ok(base_seqs.len())
+    }
+
+    pub fn set_nb_events(&self, base_seq_id: u32, target_nb_events: u32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_nb_events(target_nb_events, &self.internal.read())?;
+        Ok(())
+    }
+
+    pub fn set_spacing(&self, base_seq_id: u32, target_spacing: Spacing) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_spacing(target_spacing, &self.internal.read())?;
+        Ok(())
+    }
+
+    pub fn set_octave_range(&self, base_seq_id: u32, octaves: u32) -> anyhow::Result<()> {
+        if octaves == 0 || octaves > MAX_OCTAVES {
+            bail!("The octave range should be within [1, {MAX_OCTAVES}].");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_octave_range(octaves, &self.internal.read())
+    }
+
+    /// Set the scales used on octave ranges of a random base seq, an empty list restores the major scale
+    pub fn set_octave_scales(
+        &self,
+        base_seq_id: u32,
+        octave_scales: Vec<OctaveScale>,
+    ) -> anyhow::Result<()> {
+        if octave_scales
+            .iter()
+            .any(|scale| scale.octaves.0 > scale.octaves.1)
         {
-            *nb_events = target_nb_events;
+            bail!("Octave ranges should start before they end.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_octave_scales(octave_scales, &self.internal.read())
+    }
+
+    /// Draw the pitches of a Random BaseSeq as a Markov chain over the scale degrees,
+    /// with the given move weights, or independently with None
+    pub fn set_markov(&self, base_seq_id: u32, weights: Option<Vec<f32>>) -> anyhow::Result<()> {
+        if let Some(weights) = &weights {
+            if weights.iter().any(|w| !(w.is_finite() && *w >= 0.))
+                || !weights.iter().any(|w| *w > 0.)
+            {
+                bail!("The Markov weights should be positive, at least one being non zero.");
+            }
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_markov(weights, &self.internal.read())
+    }
+
+    /// Draw the velocities of a Random BaseSeq from the given distribution
+    pub fn set_velocity_dist(
+        &self,
+        base_seq_id: u32,
+        velocity_dist: VelocityDist,
+    ) -> anyhow::Result<()> {
+        if let VelocityDist::Uniform { min, max } = velocity_dist {
+            if !(1 <= min && min <= max && max <= 127) {
+                bail!("The velocity bounds should be ordered, within [1, 127].");
+            }
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_velocity_dist(velocity_dist, &self.internal.read())
+    }
+
+    pub fn set_euclid_pulses(&self, base_seq_id: u32, pulses: u32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_euclid(Some(pulses), None, &self.internal.read())
+    }
+
+    pub fn set_euclid_steps(&self, base_seq_id: u32, steps: u32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.set_euclid(None, Some(steps), &self.internal.read())
+    }
+
+    pub fn euclid_to_steps(&self, base_seq_id: u32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.euclid_to_steps()
+    }
+
+    pub fn toggle_step(&self, base_seq_id: u32, step_idx: usize) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.toggle_step(step_idx, &self.internal.read())
+    }
+
+    pub fn edit_event(
+        &self,
+        base_seq_id: u32,
+        note_idx: usize,
+        bar_pos: f32,
+        velocity: u8,
+    ) -> anyhow::Result<()> {
+        if velocity > 127 {
+            bail!("The velocity should be in 0..=127.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.edit_event(note_idx, bar_pos, velocity, &self.internal.read())
+    }
+
+    /// Pick new params for the BaseSeq within the randomize bounds, and regenerate it.
+    /// The sequence of picks is reproducible from the master seed.
+    pub fn randomize_base_seq(&self, base_seq_id: u32) -> anyhow::Result<()> {
+        let bounds = self.params.read().randomize_bounds;
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.randomize(&bounds, &mut self.dice_rng.write(), &self.internal.read())
+    }
+
+    pub fn add_velocity(&self, base_seq_id: u32, delta: i32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.add_velocity(delta, &self.internal.read());
+        Ok(())
+    }
+
+    pub fn quantize(&self, base_seq_id: u32, division: u32) -> anyhow::Result<()> {
+        if division == 0 {
+            bail!("The quantize division should be at least 1.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.quantize(division, &self.internal.read());
+        Ok(())
+    }
+
+    pub fn double(&self, base_seq_id: u32, interval: i32) -> anyhow::Result<()> {
+        if interval == 0 {
+            bail!("The doubling interval should not be 0.");
+        }
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.check_wrap_note_off()?;
+        base_seq.double(interval, &self.internal.read());
+        Ok(())
+    }
+
+    /// Remove the duplicated notes of a BaseSeq, see [dedup_notes]
+    pub fn dedup(&self, base_seq_id: u32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.dedup_events(&self.internal.read());
+        Ok(())
+    }
+
+    /// Trim the leading and/or trailing silence of a Custom BaseSeq, see [trim_silence].
+    /// Generated BaseSeqs are left out, as their regeneration would bring the silence back.
+    pub fn trim(&self, base_seq_id: u32, leading: bool, trailing: bool) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        if !matches!(base_seq.params.read().ty, Custom) {
+            bail!("Only custom base sequences can be trimmed.");
+        }
+        base_seq.trim(leading, trailing, &self.internal.read());
+        Ok(())
+    }
+
+    /// Stagger the notes of the chords of a BaseSeq, see [strum_chords]
+    pub fn strum(&self, base_seq_id: u32, spread: f32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.strum(spread, &self.internal.read());
+        Ok(())
+    }
+
+    /// Intersect the notes of a BaseSeq with an Euclidean rhythm, see [apply_rhythm_mask]
+    pub fn mask(&self, base_seq_id: u32, pulses: u32, steps: u32) -> anyhow::Result<()> {
+        if steps == 0 {
+            bail!("Steps should be at least 1.");
+        }
+        let mask = gen_euclid(pulses, steps)?;
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.mask(&mask, &self.internal.read());
+        Ok(())
+    }
+
+    pub fn transpose(&self, base_seq_id: u32, target_root_note: Note) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.transpose(target_root_note, &self.internal.read())?;
+        Ok(())
+    }
+
+    pub fn transpose_diatonic(&self, base_seq_id: u32, degrees: i32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.transpose_diatonic(degrees, &self.internal.read());
+        Ok(())
+    }
+
+    pub fn invert(&self, base_seq_id: u32, pivot: u8) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        base_seq.invert(pivot, &self.internal.read());
+        Ok(())
+    }
+
+    /// Ascii piano roll of the events of the BaseSeq, see [BaseSeq::to_ascii]
+    pub fn ascii(&self, base_seq_id: u32) -> anyhow::Result<String> {
+        Ok(self.get_base_seq(base_seq_id)?.to_ascii())
+    }
+
+    /// Snapshot of the runtime state, the entry point for introspection of the sequencer.
+    /// Only read locks are taken, and nothing is allocated.
+    pub fn metrics(&self) -> SeqMetrics {
+        let (bpm, status) = {
+            let seq_params = self.params.read();
+            (seq_params.bpm, seq_params.status.clone())
+        };
+        let base_seqs = self.base_seqs.read();
+        SeqMetrics {
+            bpm,
+            status,
+            nb_base_seqs: base_seqs.len(),
+            nb_events: base_seqs.iter().map(|b| b.event_buffer.read().len()).sum(),
+            events_sent: self.monitor.sent_total.load(Ordering::Relaxed),
+            events_dropped: self.monitor.dropped_total.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Length in bars after which all the BaseSeqs line up again, the least common multiple
+    /// of their loop lengths. None without BaseSeqs. See [combined_cycle_ticks] for the rounding.
+    pub fn combined_cycle(&self) -> anyhow::Result<Option<f32>> {
+        let loop_lens = self
+            .base_seqs
+            .read()
+            .iter()
+            .map(|b| b.params.read().loop_length)
+            .collect::<Vec<f32>>();
+        if loop_lens.is_empty() {
+            return Ok(None);
+        }
+        let ticks = combined_cycle_ticks(&loop_lens)?;
+        Ok(Some(ticks as f32 / CYCLE_GRID_DIV as f32))
+    }
+
+    /// Set the meta loop to the combined cycle of the BaseSeqs, rounded up to a whole multiple
+    /// of it in bars, so that the meta loop quantized changes land on a common downbeat
+    pub fn sync_meta_loop(&self) -> anyhow::Result<u32> {
+        let Some(cycle) = self.combined_cycle()? else {
+            bail!("There is no base sequence to sync the meta loop to.");
+        };
+        let bars = (combined_cycle_ticks(&[cycle, 1.])? / CYCLE_GRID_DIV) as u32;
+        self.params.write().meta_loop_bars = bars;
+        Ok(bars)
+    }
+
+    /// Set the resolution of the event positions, in ticks per bar, see [SeqParams::ticks_per_bar].
+    /// The generated BaseSeqs are regenerated from their seeds on the new ticks, the Custom ones
+    /// are snapped in place.
+    pub fn set_resolution(&self, ticks_per_bar: u32) -> anyhow::Result<()> {
+        if !(1..=MAX_TICKS_PER_BAR).contains(&ticks_per_bar) {
+            bail!("The resolution should be within 1-{MAX_TICKS_PER_BAR} ticks per bar.");
+        }
+        self.params.write().ticks_per_bar = ticks_per_bar;
+        let base_seqs = self.base_seqs.read();
+        let seq_int = self.internal.read();
+        for base_seq in &*base_seqs {
+            *base_seq.ticks_per_bar.write() = ticks_per_bar;
+            let ty = base_seq.params.read().ty.clone();
+            match ty {
+                Custom => base_seq.snap_to_ticks(&seq_int),
+                // Mirrored from their source
+                Harmony(_) => {}
+                _ => {
+                    let seed = *base_seq.seed.read();
+                    base_seq.reseed(seed, &seq_int)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete all BaseSeqs, empty the EventBuffers
+    pub fn empty(&self) {
+        *self.base_seqs.write() = vec![];
+        let mut seq_params = self.params.write();
+        seq_params.incr = 0;
+    }
+
+    /// Remove the fx processors, keeping the BaseSeqs. The fx shifting the pitches, the channels
+    /// that were fed to them are flushed, as the note offs in flight would not match their note ons.
+    pub fn clear_fx(&self) {
+        let base_seqs = self.base_seqs.read();
+        let mut seq_int = self.internal.write();
+        for base_seq in &*base_seqs {
+            let mut fx_proc_ids = base_seq.fx_proc_ids.write();
+            let midi_ch = base_seq.params.read().midi_ch;
+            if !fx_proc_ids.is_empty() && !seq_int.channel_flushes.contains(&midi_ch) {
+                seq_int.channel_flushes.push(midi_ch);
+            }
+            fx_proc_ids.clear();
+        }
+        drop(seq_int);
+        drop(base_seqs);
+        self.fx_procs.write().clear();
+    }
+
+    /// Bring the BaseSeqs back to the start of their loops, the phase locked ones keeping
+    /// their offsets
+    pub fn reset_base_seqs(&self, seq_int: &SeqInternal) {
+        for base_seq in &*self.base_seqs.read() {
+            *base_seq.phase.write() = 0.;
+            *base_seq.event_head.write() = 0;
+        }
+        for lock in &*self.phase_locks.read() {
+            if let Err(e) = self.apply_phase_lock(lock, lock.leader_id, seq_int) {
+                eprintln!("Could not keep the phase lock {lock:?}: {e}");
+            }
+        }
+    }
+
+    /// Move the playback of a BaseSeq to the given position of its loop, the other BaseSeqs
+    /// going on. The BaseSeqs phase locked to it follow.
+    pub fn seek(&self, base_seq_id: u32, bar_pos: f32) -> anyhow::Result<()> {
+        let base_seq = self.get_base_seq(base_seq_id)?;
+        let loop_len = base_seq.params.read().loop_length;
+        if !(0. ..loop_len).contains(&bar_pos) {
+            bail!("The seek position should be within the loop.");
+        }
+        {
+            let mut seq_int = self.internal.write();
+            let time = seq_int.j_window_time_end;
+            *base_seq.phase.write() = (time - bar_pos as f64).rem_euclid(loop_len as f64) as f32;
+            base_seq.sync_event_head(&seq_int);
+            let midi_ch = base_seq.params.read().midi_ch;
+            if !seq_int.channel_flushes.contains(&midi_ch) {
+                seq_int.channel_flushes.push(midi_ch);
+            }
+        }
+        drop(base_seq);
+        let locks = self.phase_locks.read().clone();
+        for lock in locks {
+            if lock.leader_id == base_seq_id || lock.follower_id == base_seq_id {
+                self.apply_phase_lock(&lock, base_seq_id, &self.internal.read())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the position of a BaseSeq ahead of the one of another by the offset in bars,
+    /// through the seeks and resets of either. Replaces any lock between them.
+    pub fn phase_lock(&self, leader_id: u32, follower_id: u32, offset: f32) -> anyhow::Result<()> {
+        if leader_id == follower_id {
+            bail!("A base sequence cannot be phase locked to itself.");
+        }
+        let _ = self.get_base_seq(leader_id)?;
+        let _ = self.get_base_seq(follower_id)?;
+        self.phase_unlock(leader_id, follower_id);
+        let lock = PhaseLock {
+            leader_id,
+            follower_id,
+            offset,
+        };
+        self.phase_locks.write().push(lock);
+        self.apply_phase_lock(&lock, leader_id, &self.internal.read())
+    }
+
+    /// Remove the phase locks between two BaseSeqs, returns whether there was any
+    pub fn phase_unlock(&self, base_seq_id_a: u32, base_seq_id_b: u32) -> bool {
+        let mut phase_locks = self.phase_locks.write();
+        let nb_locks = phase_locks.len();
+        phase_locks.retain(|l| {
+            !(l.leader_id == base_seq_id_a && l.follower_id == base_seq_id_b
+                || l.leader_id == base_seq_id_b && l.follower_id == base_seq_id_a)
+        });
+        nb_locks != phase_locks.len()
+    }
+
+    /// Set the phase of one side of the lock from the other, the one of the given id.
+    /// Seeking a follower moves its leader, so that the last seek is the one heard.
+    fn apply_phase_lock(
+        &self,
+        lock: &PhaseLock,
+        from_id: u32,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let (from, to, offset) = if from_id == lock.follower_id {
+            (lock.follower_id, lock.leader_id, -lock.offset)
         } else {
-            bail!("The given base_seq_id is wrong.");
+            (lock.leader_id, lock.follower_id, lock.offset)
         };
-        drop(params);
-        self.gen_fill(seq_int)?;
+        let from = self.get_base_seq(from)?;
+        let to = self.get_base_seq(to)?;
+        let time = seq_int.j_window_time_end;
+        let from_len = from.params.read().loop_length as f64;
+        let to_len = to.params.read().loop_length as f64;
+        let from_pos = (time - *from.phase.read() as f64).rem_euclid(from_len);
+        let to_pos = (from_pos + offset as f64).rem_euclid(to_len);
+        *to.phase.write() = (time - to_pos).rem_euclid(to_len) as f32;
+        to.sync_event_head(seq_int);
         Ok(())
     }
 
-    pub(self) fn transpose(&self, target_root_note: Note) -> anyhow::Result<()> {
-        let mut params = self.params.write();
-        let root_note_midi = note_to_midi_pitch(&params.root_note);
-        let target_root_note_midi = note_to_midi_pitch(&target_root_note);
-        let pitch_diff = target_root_note_midi as i32 - root_note_midi as i32;
-        for event in self.event_buffer.write().iter_mut() {
-            if let EventType::MidiNoteOn(MidiNote { ref mut pitch, .. })
-            | EventType::MidiNoteOff(MidiNote { ref mut pitch, .. }) = event.e_type
-            {
-                *pitch = (*pitch as i32 + pitch_diff).clamp(0, 127) as u8;
+    /// Send the notes off of all the channels used, including the ones of the route overrides
+    pub fn notes_off(
+        &self,
+        sink: &mut impl MidiSink,
+        seq_params: &SeqParams,
+        seq_int: &SeqInternal,
+    ) {
+        let mut midi_chs = vec![];
+        for base_seq in &*self.base_seqs.read() {
+            midi_chs.push(base_seq.params.read().midi_ch);
+            if let Some((low, high)) = *base_seq.mpe_range.read() {
+                midi_chs.extend(low..=high);
+            }
+        }
+        midi_chs.extend(seq_int.route_overrides.iter().map(|r| r.channel));
+        midi_chs.sort();
+        midi_chs.dedup();
+        for ch in midi_chs {
+            self.notes_off_channel(sink, ch, seq_params);
+        }
+    }
+
+    /// Midi channels used by the BaseSeqs, sorted, with the nb of BaseSeqs on each
+    pub fn channel_usage(&self) -> Vec<(u8, u32)> {
+        let mut usage: Vec<(u8, u32)> = vec![];
+        for base_seq in &*self.base_seqs.read() {
+            let midi_ch = base_seq.params.read().midi_ch;
+            match usage.iter_mut().find(|(ch, _)| *ch == midi_ch) {
+                Some((_, count)) => *count += 1,
+                None => usage.push((midi_ch, 1)),
+            }
+        }
+        usage.sort_by_key(|(ch, _)| *ch);
+        usage
+    }
+
+    /// Request the notes off of a single midi channel, sent by the jack process on its next cycle,
+    /// see [Sequencer::notes_off_channel]
+    pub fn flush_channel(&self, midi_ch: u8) {
+        let mut seq_int = self.internal.write();
+        if !seq_int.channel_flushes.contains(&midi_ch) {
+            seq_int.channel_flushes.push(midi_ch);
+        }
+    }
+
+    pub fn notes_off_channel(&self, sink: &mut impl MidiSink, ch: u8, seq_params: &SeqParams) {
+        for pitch in 0..128 {
+            // Best effort, a full output buffer will get flushed on the next panic
+            let _ = send_event(
+                sink,
+                &self.monitor,
+                &Event {
+                    e_type: EventType::MidiNote(MidiNote {
+                        on_off: false,
+                        channel: ch,
+                        pitch,
+                        velocity: seq_params.notes_off_velocity,
+                    }),
+                    bar_pos: 0.,
+                    duration: None,
+                },
+                seq_params.note_off_as_velocity_zero,
+            );
+        }
+    }
+
+    /// Update the held notes from a raw midi input message
+    pub fn handle_midi_in(&self, bytes: &[u8]) {
+        self.monitor.count_in(bytes);
+        if let [status, pitch, velocity] = *bytes {
+            let held = &mut self.held_notes.write()[(pitch & 0x7F) as usize];
+            match status >> 4 {
+                0x9 => *held = velocity > 0,
+                0x8 => *held = false,
+                _ => {}
+            }
+        }
+    }
+
+    /// Midi pitches of the notes currently held on the midi input
+    pub fn held_notes(&self) -> Vec<u8> {
+        let held_notes = self.held_notes.read();
+        (0..128u8).filter(|p| held_notes[*p as usize]).collect()
+    }
+
+    pub fn remove_base_seq(&self, base_seq_id: u32) -> anyhow::Result<()> {
+        let index = self
+            .base_seqs
+            .read()
+            .iter()
+            .position(|b| b.id == base_seq_id)
+            .ok_or_else(|| anyhow!("Could not find base sequence of id {base_seq_id}"))?;
+        let removed = self.base_seqs.write().remove(index);
+        self.phase_locks
+            .write()
+            .retain(|l| l.leader_id != base_seq_id && l.follower_id != base_seq_id);
+        self.auto_fills
+            .write()
+            .retain(|f| f.base_seq_id != base_seq_id);
+        // Its playback state, so that none of it applies to a BaseSeq later added under the id
+        let mut seq_int = self.internal.write();
+        seq_int
+            .ducks
+            .retain(|d| d.trigger_id != base_seq_id && d.target_id != base_seq_id);
+        seq_int
+            .route_overrides
+            .retain(|r| r.base_seq_id != base_seq_id);
+        seq_int.stutters.retain(|s| s.base_seq_id != base_seq_id);
+        seq_int.loop_rotations.retain(|(id, _)| *id != base_seq_id);
+        seq_int.mpe_voices.retain(|v| v.base_seq_id != base_seq_id);
+        seq_int.mpe_cursors.retain(|(id, _)| *id != base_seq_id);
+        drop(seq_int);
+        // Unlink a follower from its source
+        if let Harmony(HarmonyBase { source_id, .. }) = removed.params.read().ty {
+            if let Ok(source) = self.get_base_seq(source_id) {
+                source.followers.write().retain(|f| f.id != base_seq_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Store a copy of the current BaseSeqs under the index, replacing the scene stored there
+    pub fn save_scene(&self, index: u32) {
+        let base_seqs = snapshot_base_seqs(&self.base_seqs.read());
+        let mut scenes = self.scenes.write();
+        scenes.retain(|s| s.index != index);
+        scenes.push(Scene { index, base_seqs });
+        scenes.sort_by_key(|s| s.index);
+    }
+
+    /// Replace the BaseSeqs by a copy of the ones of the scene, so that the scene is left as saved.
+    /// When quantized, the scene is swapped in by the jack process on the next meta loop boundary.
+    pub fn recall_scene(&self, index: u32, quantized: bool) -> anyhow::Result<()> {
+        let base_seqs = self
+            .scenes
+            .read()
+            .iter()
+            .find(|s| s.index == index)
+            .map(|s| snapshot_base_seqs(&s.base_seqs))
+            .ok_or_else(|| anyhow!("Could not find scene of index {index}"))?;
+        // Base seqs added after the recall should not reuse the ids of the scene
+        let mut seq_params = self.params.write();
+        // The caller chosen ids are left out, being out of the auto assigned range
+        if let Some(max_id) = base_seqs
+            .iter()
+            .map(|b| b.id)
+            .filter(|id| *id < CALLER_ID_START)
+            .max()
+        {
+            seq_params.incr = seq_params.incr.max(max_id + 1);
+        }
+        let scene = Scene { index, base_seqs };
+        if quantized {
+            self.internal.write().pending_scene = Some(scene);
+        } else {
+            drop(self.swap_scene(scene));
+        }
+        Ok(())
+    }
+
+    /// Recall the scene following the last recalled one, wrapping around to the first
+    pub fn next_scene(&self, quantized: bool) -> anyhow::Result<()> {
+        let current = self.internal.read().current_scene;
+        let index = {
+            let scenes = self.scenes.read();
+            let next = scenes.iter().find(|s| current.is_none_or(|c| s.index > c));
+            next.or(scenes.first())
+                .map(|s| s.index)
+                .ok_or_else(|| anyhow!("No scene saved"))?
+        };
+        self.recall_scene(index, quantized)
+    }
+
+    /// Play a fill on the BaseSeq over the given nb of bars before every quantized scene change,
+    /// its note ons being rolled twice as fast as its note repeat, see [NoteRepeat].
+    /// The fill ends with the scene change. 0 bars removes the fill.
+    pub fn set_auto_fill(&self, base_seq_id: u32, bars_before: f32) -> anyhow::Result<()> {
+        if bars_before < 0. {
+            bail!("The nb of bars of a fill should be positive.");
+        }
+        let _ = self.get_base_seq(base_seq_id)?;
+        let mut auto_fills = self.auto_fills.write();
+        auto_fills.retain(|f| f.base_seq_id != base_seq_id);
+        if 0. < bars_before {
+            auto_fills.push(AutoFill {
+                base_seq_id,
+                bars_before,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the BaseSeq plays its fill at the given time in bars since start, a scene
+    /// change being pending at the end of the meta loop
+    pub fn in_auto_fill(&self, base_seq_id: u32, time: f64, meta_loop_bars: u32) -> bool {
+        let Some(fill) = self
+            .auto_fills
+            .read()
+            .iter()
+            .find(|f| f.base_seq_id == base_seq_id)
+            .copied()
+        else {
+            return false;
+        };
+        let meta_loop_bars = meta_loop_bars as f64;
+        self.internal.read().pending_scene.is_some()
+            && meta_loop_bars - fill.bars_before as f64 <= time.rem_euclid(meta_loop_bars)
+    }
+
+    /// Swap the BaseSeqs for the ones of the scene, with their heads synced, and request the
+    /// notes off of the midi channels of both. The replaced BaseSeqs are returned, for the jack
+    /// process to leave their drop to the OSC process, see [Sequencer::drop_retired_base_seqs].
+    pub(crate) fn swap_scene(&self, scene: Scene) -> Vec<BaseSeq> {
+        let mut base_seqs = self.base_seqs.write();
+        let mut seq_int = self.internal.write();
+        for base_seq in base_seqs.iter().chain(&scene.base_seqs) {
+            let ch = base_seq.params.read().midi_ch;
+            if !seq_int.channel_flushes.contains(&ch) {
+                seq_int.channel_flushes.push(ch);
+            }
+        }
+        for base_seq in &scene.base_seqs {
+            base_seq.sync_event_head(&seq_int);
+        }
+        seq_int.current_scene = Some(scene.index);
+        println!("Recalled scene {}", scene.index);
+        std::mem::replace(&mut *base_seqs, scene.base_seqs)
+    }
+
+    /// Drop the BaseSeqs replaced by the jack process, whose thread should not free memory
+    pub fn drop_retired_base_seqs(&self) {
+        let retired = self.internal.write().retired_base_seqs.take();
+        drop(retired);
+    }
+
+    pub fn add_lfo(&self, lfo: Lfo) -> anyhow::Result<()> {
+        if lfo.period <= 0. {
+            bail!("LFO period should be positive.");
+        }
+        self.lfos.write().push(lfo);
+        Ok(())
+    }
+
+    pub fn clear_lfos(&self) {
+        self.lfos.write().clear();
+    }
+
+    /// Bpm modulated by the LFOs targeting it, at the given time in bars since start
+    pub fn lfo_bpm(&self, bpm: f32, time: f64) -> f32 {
+        let offset: f32 = self
+            .lfos
+            .read()
+            .iter()
+            .filter(|lfo| lfo.target == LfoTarget::Bpm)
+            .map(|lfo| lfo.value(time))
+            .sum();
+        (bpm + offset).max(0.)
+    }
+
+    /// Send the control changes modulated by the LFOs, around the middle of the CC range.
+    /// CCs are only sent when their value changes.
+    pub fn send_lfo_ccs(&self, seq_int: &mut SeqInternal, sink: &mut impl MidiSink, time: f64) {
+        let lfos = self.lfos.read();
+        for lfo in lfos.iter() {
+            let LfoTarget::Cc {
+                channel,
+                controller,
+            } = lfo.target
+            else {
+                continue;
+            };
+            let offset: f32 = lfos
+                .iter()
+                .filter(|l| l.target == lfo.target)
+                .map(|l| l.value(time))
+                .sum();
+            let value = (64. + offset).round().clamp(0., 127.) as u8;
+            let last_value = seq_int
+                .lfo_cc_values
+                .iter_mut()
+                .find(|(target, _)| *target == (channel, controller));
+            match last_value {
+                Some((_, last)) if *last == value => continue,
+                Some((_, last)) => *last = value,
+                None => seq_int.lfo_cc_values.push(((channel, controller), value)),
+            }
+            let raw_midi = get_raw_cc_bytes(channel, controller, value);
+            if let Err(e) = sink.write(&raw_midi) {
+                eprintln!("Could not insert in jack output buffer: {e}");
+            } else {
+                self.monitor.count_out(&raw_midi);
+            }
+        }
+    }
+
+    /// Start capturing the events sent, from the start of the next jack cycle
+    pub fn record_start(&self) {
+        let start = self.internal.read().j_window_time_end;
+        let mut recording = self.recording.write();
+        // Left over from the last recording, pushed while it was stopping
+        // SAFETY: the queue is only popped with the recording locked
+        while unsafe { self.record_queue.pop() }.is_some() {}
+        *recording = Some(Recording {
+            start,
+            events: vec![],
+        });
+        self.recording_on.store(true, Ordering::Release);
+        println!("Recording started.");
+    }
+
+    /// Log an event sent at the given time in bars since start, if recording.
+    /// Only called by the Jack process, which neither locks nor allocates here.
+    pub(crate) fn record(&self, event: &Event, time: f64) {
+        if !self.recording_on.load(Ordering::Acquire) {
+            return;
+        }
+        // SAFETY: the queue is only pushed to by the Jack process
+        if unsafe { self.record_queue.push((time, event.clone())) }.is_err() {
+            eprintln!("The record queue is full, an event was not recorded.");
+        }
+    }
+
+    /// Move the events of the record queue to the recording in progress, if any, so that
+    /// the queue does not fill up over long recordings
+    pub fn drain_record_queue(&self) {
+        let mut recording = self.recording.write();
+        let Some(recording) = recording.as_mut() else {
+            return;
+        };
+        // SAFETY: the queue is only popped with the recording locked
+        while let Some(event) = unsafe { self.record_queue.pop() } {
+            recording.events.push(event);
+        }
+    }
+
+    /// Stop the recording and turn the captured events into a Custom BaseSeq,
+    /// wrapping them to the given loop length
+    pub fn record_stop(&self, loop_length: f32) -> anyhow::Result<()> {
+        if !(loop_length.is_finite() && loop_length > 0.) {
+            bail!("The loop length should be positive.");
+        }
+        self.recording_on.store(false, Ordering::Release);
+        self.drain_record_queue();
+        let recording = self
+            .recording
+            .write()
+            .take()
+            .ok_or_else(|| anyhow!("The sequencer is not recording."))?;
+        let mut events: Vec<Event> = recording
+            .events
+            .into_iter()
+            .map(|(time, event)| Event {
+                bar_pos: (time - recording.start).rem_euclid(loop_length as f64) as f32,
+                // Note offs were recorded too
+                duration: None,
+                ..event
+            })
+            .collect();
+        events.sort_by_key(Event::sort_key);
+        let (capture_quantize, capture_swing) = {
+            let seq_params = self.params.read();
+            (seq_params.capture_quantize, seq_params.capture_swing)
+        };
+        if let Some(division) = capture_quantize {
+            events = quantize_positions(events, division, capture_swing, loop_length);
+        }
+        let (midi_ch, pitch) = events
+            .iter()
+            .find_map(|e| match e.e_type {
+                EventType::MidiNote(MidiNote { channel, pitch, .. }) => Some((channel, pitch)),
+                EventType::PitchBend(_) | EventType::_Fill => None,
+            })
+            .ok_or_else(|| anyhow!("No note was recorded."))?;
+        let base_seq_params = BaseSeqParams {
+            ty: Custom,
+            loop_length,
+            root_note: midi_pitch_to_note(pitch).or_else(|_| midi_pitch_to_note(60))?,
+            note_len_avg: 0.,
+            note_len_div: 0.,
+            velocity_avg: 0,
+            velocity_div: 0.,
+            midi_ch,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        };
+        self.add_custom_base_seq(base_seq_params, events)
+    }
+
+    /// Raw midi that would be sent over the window [start, end), in bars since start, with the
+    /// frame offsets of the messages from the window start at the current bpm.
+    /// Neither JACK nor the playback state are touched, for testing and debugging.
+    /// The fx chains, muted regions, swing, loop rotations and velocity curve are applied,
+    /// but not the play probability and ducking, which are drawn live.
+    pub fn render_window(&self, start: f64, end: f64) -> anyhow::Result<Vec<(u32, [u8; 3])>> {
+        if !(0. <= start && start < end) {
+            bail!("The render window should start at a positive time before its end.");
+        }
+        let seq_params = self.params.read();
+        let seq_int = self.internal.read();
+        let mut rendered: Vec<(f64, MidiNote)> = vec![];
+        for base_seq in self.base_seqs.read().iter() {
+            if *base_seq.paused.read() {
+                continue;
+            }
+            let loop_len = base_seq.params.read().loop_length;
+            let loops = (start / loop_len as f64) as u64..(end / loop_len as f64).ceil() as u64;
+            for event in base_seq.event_buffer.read().iter() {
+                if loop_len <= event.bar_pos {
+                    continue;
+                }
+                let swung_pos = swing_pos(event.bar_pos, seq_params.master_shuffle, loop_len);
+                for loop_count in loops.clone() {
+                    let pos = seq_int.rotated_pos_in_loop(
+                        swung_pos,
+                        base_seq.id,
+                        seq_params.master_seed,
+                        loop_len,
+                        loop_count,
+                    );
+                    let time = loop_count as f64 * loop_len as f64 + pos as f64;
+                    if time < start
+                        || end <= time
+                        || (event.is_note_on() && base_seq.is_muted_at(pos))
+                    {
+                        continue;
+                    }
+                    let mut event = event.clone();
+                    self.process_event(base_seq, &mut event);
+                    if let EventType::MidiNote(mut note) = event.e_type {
+                        if note.on_off {
+                            note.velocity = seq_params.velocity_curve.apply(note.velocity);
+                        }
+                        rendered.push((time, note));
+                        let off_time = time + event.duration.unwrap_or(f32::INFINITY) as f64;
+                        if off_time < end {
+                            let note_off = MidiNote {
+                                on_off: false,
+                                ..note
+                            };
+                            rendered.push((off_time, note_off));
+                        }
+                    }
+                }
+            }
+        }
+        // Note offs first at the same time, as in the EventBuffers
+        rendered.sort_by(|(t1, n1), (t2, n2)| t1.total_cmp(t2).then(n1.on_off.cmp(&n2.on_off)));
+        let frames_per_bar = 60. / seq_params.bpm as f64 * seq_int.sample_rate as f64;
+        Ok(rendered
+            .into_iter()
+            .map(|(time, note)| {
+                (
+                    ((time - start) * frames_per_bar).round() as u32,
+                    note.get_raw_note_on_bytes(seq_params.note_off_as_velocity_zero),
+                )
+            })
+            .collect())
+    }
+
+    /// Run an event of a BaseSeq through its fx chain, unless bypassed
+    pub fn process_event(&self, base_seq: &BaseSeq, event: &mut Event) {
+        if *base_seq.fx_bypassed.read() {
+            return;
+        }
+        for fx_proc_id in base_seq.fx_proc_ids.read().iter() {
+            if let Ok(fx_proc) = self.get_fx_proc(*fx_proc_id) {
+                if *fx_proc.enabled.read() {
+                    fx_proc.process(event);
+                }
+            }
+        }
+    }
+}
+
+/// Snapshot of the runtime state of the sequencer, see [Sequencer::metrics]
+#[derive(Clone, Debug)]
+pub struct SeqMetrics {
+    pub bpm: f32,
+    pub status: SeqStatus,
+    pub nb_base_seqs: usize,
+    /// Over all the EventBuffers
+    pub nb_events: usize,
+    /// Midi messages written to the output since start
+    pub events_sent: u64,
+    /// Midi messages that could not be written since start, the output buffer being full
+    pub events_dropped: u64,
+}
+
+/// Copy of the BaseSeqs saved under an index, recalled in place of the current ones
+pub struct Scene {
+    pub index: u32,
+    pub base_seqs: Vec<BaseSeq>,
+}
+
+/// Events captured between a record start and stop, see [Sequencer::record_queue]
+pub struct Recording {
+    /// In bars since start
+    pub start: f64,
+    /// Events with their send time in bars since start
+    pub events: Vec<(f64, Event)>,
+}
+
+#[derive(Clone, PartialEq, Eq, EnumString, Debug, FromPrimitive)]
+pub enum SeqStatus {
+    /// Pause ans reset sequencer to start position
+    Stop,
+    Start,
+    Pause,
+    /// Sequencer is set to shutdown gracefully
+    Shutdown,
+}
+
+pub struct SeqParams {
+    pub status: SeqStatus,
+    pub bpm: f32,
+    /// Counter of total nb of BaseSeqs/FxProcessor ever created, used for id
+    pub incr: u32,
+    /// Seed from which the evolving BaseSeqs derive their per-loop generation seeds
+    pub master_seed: u64,
+    /// Unit in which the OSC length args are given, converted to bars on reception
+    pub time_unit: TimeUnit,
+    /// Send note offs as note ons of velocity 0, allowing devices to use running status
+    pub note_off_as_velocity_zero: bool,
+    /// Release velocity of the notes off sent to whole channels, on stop, pause or flush
+    pub notes_off_velocity: u8,
+    /// Swing applied to all BaseSeqs at playback, as a fraction of a sixteenth in [0, 1)
+    pub master_shuffle: f32,
+    /// Reply to every OSC command with its result, to the sender
+    pub reply_on_result: bool,
+    /// Length in bars of the song form loop, reference for the changes landing at the top of the loop
+    pub meta_loop_bars: u32,
+    /// Division of the bar on which recorded events are snapped, None to keep them as played
+    pub capture_quantize: Option<u32>,
+    /// Resolution of the event positions, in ticks per bar. The generated and recorded events
+    /// are snapped to its ticks, the notes being kept at least a tick long.
+    pub ticks_per_bar: u32,
+    /// Swing of the grid on which recorded events are snapped, as [SeqParams::master_shuffle]
+    pub capture_swing: f32,
+    /// Bounds within which BaseSeqs are randomized, see [Sequencer::randomize_base_seq]
+    pub randomize_bounds: RandomizeBounds,
+    /// Min and max bpm, inclusive
+    pub bpm_range: (f32, f32),
+    /// Reshaping of the note on velocities at playback, the buffers are left untouched
+    pub velocity_curve: VelocityCurve,
+    /// Prefix of the command addresses, distinguishing the instances listening side by side
+    pub addr_prefix: String,
+    /// BaseSeq whose note length, velocity and root are controlled by the encoders 1 to 3
+    pub focused_base_seq: Option<u32>,
+    /// Acceleration of the bpm encoder
+    pub encoder_curve: EncoderCurve,
+    /// When set, the focused encoders are engaged only once caught after a focus change
+    pub soft_takeover: bool,
+    pub encoder_takeover: EncoderTakeover,
+    /// Max nb of events written per jack cycle, the excess being carried over to the next ones.
+    /// Keeps dense bursts from overrunning the jack midi buffer.
+    pub max_events_per_cycle: Option<u32>,
+    /// Max nb of note ons written per jack cycle over all the BaseSeqs, the lowest velocity ones
+    /// being dropped along with their note offs. Protects the polyphony of the synths.
+    pub max_polyphony: Option<u32>,
+    /// Serialosc port of the monome grid showing the focused BaseSeq, see [crate::grid::GridLeds]
+    pub grid_feedback: Option<SocketAddr>,
+    /// Direction in which the loops of the BaseSeqs are read
+    pub direction: Direction,
+}
+
+impl SeqParams {
+    /// Take the next auto assigned id, refused once the counter reaches the caller chosen ids
+    pub fn next_id(&mut self) -> anyhow::Result<u32> {
+        if self.incr >= CALLER_ID_START {
+            bail!("No auto assigned ids left, they end at {CALLER_ID_START}.");
+        }
+        self.incr += 1;
+        Ok(self.incr - 1)
+    }
+}
+
+pub const DEFAULT_ADDR_PREFIX: &str = "/gisele";
+
+pub const DEFAULT_BPM_RANGE: (f32, f32) = (20., 999.);
+
+/// Mapping of the encoder deltas to parameter changes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EncoderCurve {
+    pub shape: EncoderShape,
+    pub gain: f32,
+}
+
+/// Nb of ticks in a same direction catching a focused encoder, see [EncoderTakeover]
+pub const ENC_TAKEOVER_TICKS: i32 = 3;
+
+/// Soft takeover of the focused encoders 1 to 3. The encoders being relative, there is no
+/// value to catch: a stray tick when switching the focus would nudge the newly focused BaseSeq.
+/// Instead, an encoder is caught once turned by a few ticks in a same direction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EncoderTakeover {
+    /// Ticks turned in a same direction since the focus change, None once engaged
+    catching: [Option<i32>; 3],
+}
+
+impl EncoderTakeover {
+    /// Release all the encoders, to be caught again
+    pub fn release(&mut self) {
+        self.catching = [Some(0); 3];
+    }
+
+    /// Whether the delta of the encoder 1 to 3 is to be applied, counting it towards the catch
+    pub fn engage(&mut self, enc_nb: i32, delta: i32) -> bool {
+        let Some(catching) = self.catching.get_mut(enc_nb as usize - 1) else {
+            return true;
+        };
+        let Some(ticks) = catching else {
+            return true;
+        };
+        // A change of direction starts the catch over
+        *ticks = if ticks.signum() == -delta.signum() {
+            delta
+        } else {
+            *ticks + delta
+        };
+        if ENC_TAKEOVER_TICKS <= ticks.abs() {
+            *catching = None;
+        }
+        false
+    }
+}
+
+/// Playback direction of the loops. The time keeps running forward, the positions of the
+/// events being mirrored within the loops of the BaseSeqs read backward.
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+pub enum Direction {
+    Forward,
+    Backward,
+    /// Alternating forward and backward on every loop of each BaseSeq
+    PingPong,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+pub enum EncoderShape {
+    /// Change proportional to the delta, for a precise control
+    Linear,
+    /// Change proportional to the square of the delta, keeping its sign, faster spins going further
+    Quadratic,
+}
+
+impl Default for EncoderCurve {
+    fn default() -> Self {
+        EncoderCurve {
+            shape: EncoderShape::Quadratic,
+            gain: 0.01,
+        }
+    }
+}
+
+impl EncoderCurve {
+    pub fn apply(self, delta: i32) -> f32 {
+        let delta = delta as f32;
+        match self.shape {
+            EncoderShape::Linear => self.gain * delta,
+            EncoderShape::Quadratic => self.gain * delta.signum() * delta * delta,
+        }
+    }
+}
+
+/// Velocity response compensating the one of the receiving device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VelocityCurve {
+    Linear,
+    /// Gamma of 2, softening the low velocities
+    Exp,
+    /// Gamma of 1/2, raising the low velocities
+    Log,
+    Gamma(f32),
+}
+
+impl VelocityCurve {
+    pub fn gamma(self) -> f32 {
+        match self {
+            VelocityCurve::Linear => 1.,
+            VelocityCurve::Exp => 2.,
+            VelocityCurve::Log => 0.5,
+            VelocityCurve::Gamma(gamma) => gamma,
+        }
+    }
+
+    /// 127 * (velocity / 127)^gamma, clamped to 1..=127 so that note ons stay note ons
+    pub fn apply(self, velocity: u8) -> u8 {
+        let curved = 127. * (velocity as f32 / 127.).powf(self.gamma());
+        curved.round().clamp(1., 127.) as u8
+    }
+}
+
+/// Inclusive bounds of the BaseSeq params picked when randomizing
+#[derive(Clone, Copy, Debug)]
+pub struct RandomizeBounds {
+    pub nb_events: (u32, u32),
+    pub pulses: (u32, u32),
+    pub steps: (u32, u32),
+    /// In bars
+    pub note_len: (f32, f32),
+    pub velocity: (u8, u8),
+    /// Midi pitch, should be >= 12
+    pub root: (u8, u8),
+}
+
+impl Default for RandomizeBounds {
+    fn default() -> Self {
+        RandomizeBounds {
+            nb_events: (1, 32),
+            pulses: (1, 16),
+            steps: (1, 16),
+            note_len: (0.05, 0.5),
+            velocity: (40, 120),
+            root: (36, 84),
+        }
+    }
+}
+
+impl RandomizeBounds {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let ordered = self.nb_events.0 <= self.nb_events.1
+            && self.pulses.0 <= self.pulses.1
+            && self.steps.0 <= self.steps.1
+            && self.note_len.0 <= self.note_len.1
+            && self.velocity.0 <= self.velocity.1
+            && self.root.0 <= self.root.1;
+        if !ordered {
+            bail!("Randomize bounds should be given as min then max.");
+        }
+        if self.steps.0 == 0 || self.note_len.0 <= 0. || self.root.0 < 12 || self.velocity.1 > 127 {
+            bail!("Randomize bounds are out of range.");
+        }
+        Ok(())
+    }
+}
+
+/// Units for lengths and times. The sequencer works in bars internally, the bpm
+/// giving the nb of bars played per minute (see jackp::cycle_len_bars).
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum TimeUnit {
+    Bars,
+    /// Beats of the bpm, hence the bars of the sequencer
+    Beats,
+    /// bars = ms * bpm / 60_000
+    Ms,
+}
+
+impl TimeUnit {
+    pub fn to_bars(self, value: f32, bpm: f32) -> f32 {
+        match self {
+            TimeUnit::Bars | TimeUnit::Beats => value,
+            TimeUnit::Ms => value * bpm / 60_000.,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////
+// Base Sequences
+
+#[derive(Clone, Debug)]
+pub enum BaseSeqType {
+    Random(RandomBase),
+    Euclid(EuclidBase),
+    Steps(StepsBase),
+    Harmony(HarmonyBase),
+    /// Events given as is, e.g. recorded
+    Custom,
+}
+
+#[derive(Clone, Debug)]
+pub struct BaseSeqParams {
+    pub ty: BaseSeqType,
+    /// In bars, 16 is 4 measures
+    pub loop_length: f32,
+    pub root_note: Note,
+    /// In bars
+    pub note_len_avg: f32,
+    /// Standard deviation from average value note_len in normal random generation
+    /// In bars
+    pub note_len_div: f32,
+    /// In midi range (0-127)
+    pub velocity_avg: u8,
+    /// Standard deviation from average value velocity in normal random generation
+    pub velocity_div: f32,
+    /// Channel, should be 1-16
+    pub midi_ch: u8,
+    /// Midi pitches drawn from by the random generation instead of the root note scale
+    pub pitch_pool: Option<Vec<u8>>,
+    /// Store note offs as durations of their note ons rather than as separate events
+    pub explicit_durations: bool,
+    /// Connect the overlapping notes, for mono legato lines
+    pub legato: bool,
+    /// Velocity multipliers of the steps of a sixteenth grid, repeated over the loop
+    pub velocity_contour: Option<Vec<f32>>,
+    /// Intervals by which the notes are doubled, in order, see [double_notes]
+    pub doublings: Vec<i32>,
+    /// Note lengths as a fraction of the gap to the next note on, see [apply_gate_ratio]
+    pub gate_ratio: Option<f32>,
+    /// Scaling of the generated note lengths by the velocity relative to the average,
+    /// positive to lengthen the loud notes, negative to shorten them
+    pub vel_len_coupling: f32,
+    /// Velocity scaling of the notes by their distance to a grid, see [apply_grid_velocity]
+    pub grid_velocity: Option<GridVelocity>,
+    /// Release velocity of the note offs, applied at playback. None keeps the note on velocity.
+    pub note_off_velocity: Option<u8>,
+    /// Wrap the generated note offs past the loop end to its start. Otherwise they are kept past
+    /// the loop end and sent from their note on, so that the notes sustain into the next loop
+    /// whatever their length. Exclusive of the buffer passes wrapping them again, see
+    /// [BaseSeqParams::rewraps_note_offs].
+    pub wrap_note_off: bool,
+}
+
+impl BaseSeqParams {
+    /// Whether one of the buffer passes converting to durations is on, which wrap the note offs
+    /// to the loop start again
+    pub fn rewraps_note_offs(&self) -> bool {
+        self.legato
+            || self.gate_ratio.is_some()
+            || !self.doublings.is_empty()
+            || self.explicit_durations
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GridVelocity {
+    /// Nb of grid steps per bar
+    pub division: u32,
+    /// In [-1, 1]. Positive to soften the notes away from the grid, negative to accent them
+    pub strength: f32,
+}
+
+/// State of a base sequence that is generated and inserted into the EventBuffer.
+/// As all of its state is behind Arcs, a clone is a handle sharing the same state.
+#[derive(Clone)]
+pub struct BaseSeq {
+    pub params: Arc<RwLock<BaseSeqParams>>,
+    /// Current position in the event buffer.
+    /// Write: OSC + Jack process
+    pub event_head: Arc<RwLock<usize>>,
+    /// Identifies events in the EventBuffer
+    /// Event Buffer
+    /// Events are ordered by their times
+    /// Write: OSC process, Read: Jack process
+    pub event_buffer: Arc<RwLock<Vec<Event>>>,
+    /// FxProcessor ids to which the BaseSeq feeds events
+    pub fx_proc_ids: Arc<RwLock<Vec<u32>>>,
+    /// When set, events skip the fx chain
+    pub fx_bypassed: Arc<RwLock<bool>>,
+    /// When set, the BaseSeq is skipped by the Jack process
+    pub paused: Arc<RwLock<bool>>,
+    /// Start and end bar_pos of the regions whose note ons are skipped at playback.
+    /// A region with its start after its end wraps around the loop.
+    pub muted_regions: Arc<RwLock<Vec<(f32, f32)>>>,
+    /// Random generator used to generate the events
+    pub rng: Arc<RwLock<StdRng>>,
+    /// Seed the generator was reset to for the last generation, see [BaseSeq::gen_events]
+    pub seed: Arc<RwLock<u64>>,
+    /// Random generator for the playback time randomness, seeded from the master seed and the id
+    /// so that BaseSeqs are independent but reproducible
+    /// Write: Jack process
+    pub play_rng: Arc<RwLock<StdRng>>,
+    /// When set, the BaseSeq is reseeded from the master seed and regenerated on every loop
+    pub evolve: Arc<RwLock<bool>>,
+    /// Thinning out of the note ons over the loops
+    pub decay: Arc<RwLock<Decay>>,
+    /// Chance of the note ons to be retriggered as a roll, see [NoteRepeat]
+    pub note_repeat: Arc<RwLock<NoteRepeat>>,
+    /// Mixer-style velocity scaling of the note ons at playback, see [Gain]
+    pub gain: Arc<RwLock<Gain>>,
+    /// When set, the regenerated events wait in the pending buffer for the loop boundary,
+    /// the current loop playing out. The edits of the event buffer stay immediate.
+    pub regen_on_loop: Arc<RwLock<bool>>,
+    /// Regenerated events to be swapped in at the next loop boundary
+    /// Write: OSC process + Jack process
+    pub pending_buffer: Arc<RwLock<Option<Vec<Event>>>>,
+    /// Shift of the loop start in bars, the loop position being the time since start minus
+    /// the phase, see [Sequencer::seek]
+    pub phase: Arc<RwLock<f32>>,
+    /// Range of channels over which the simultaneous notes are spread at playback, one per note,
+    /// see [SeqInternal::allocate_mpe_channel]
+    pub mpe_range: Arc<RwLock<Option<(u8, u8)>>>,
+    /// Time since the sequencer start, in bars, before which the BaseSeq stays silent.
+    /// Its event head keeps advancing meanwhile, to enter synced to its loop.
+    pub start_offset: Arc<RwLock<f32>>,
+    /// Harmony BaseSeqs mirroring the events of this one, updated on every change of the EventBuffer
+    pub followers: Arc<RwLock<Vec<BaseSeq>>>,
+    /// Resolution on whose ticks the events are generated, see [SeqParams::ticks_per_bar]
+    pub ticks_per_bar: Arc<RwLock<u32>>,
+    /// Note ons of the EventBuffer mirrored for the backward reading, see [mirrored_notes].
+    /// Refreshed on every sync of the event head, which follows the changes of the EventBuffer.
+    pub mirrored_notes: Arc<RwLock<Vec<MirroredNote>>>,
+    /// Unique identifier to the base_seq
+    pub id: u32,
+}
+
+impl BaseSeq {
+    /// Create a new base sequence and fill its event buffer.
+    /// The jack process window end time gives a reference point to the present time for the synchronizing
+    /// of the BaseSeq event_head
+    #[cfg(test)]
+    pub(crate) fn new_fill(
+        params: BaseSeqParams,
+        id: u32,
+        master_seed: u64,
+        ticks_per_bar: u32,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<BaseSeq> {
+        let base_seq = BaseSeq::new(params, id, master_seed, ticks_per_bar);
+        base_seq.gen_fill(seq_int)?;
+        Ok(base_seq)
+    }
+
+    /// Create a new base sequence with an empty event buffer
+    pub(crate) fn new(
+        params: BaseSeqParams,
+        id: u32,
+        master_seed: u64,
+        ticks_per_bar: u32,
+    ) -> BaseSeq {
+        BaseSeq {
+            params: Arc::new(RwLock::new(params)),
+            event_head: Arc::new(RwLock::new(0)),
+            event_buffer: Arc::new(RwLock::new(vec![])),
+            fx_proc_ids: Arc::new(RwLock::new(vec![])),
+            fx_bypassed: Arc::new(RwLock::new(false)),
+            paused: Arc::new(RwLock::new(false)),
+            muted_regions: Arc::new(RwLock::new(vec![])),
+            rng: Arc::new(RwLock::new(StdRng::from_entropy())),
+            seed: Arc::new(RwLock::new(0)),
+            play_rng: Arc::new(RwLock::new(StdRng::seed_from_u64(playback_seed(
+                master_seed,
+                id,
+            )))),
+            evolve: Arc::new(RwLock::new(false)),
+            decay: Arc::new(RwLock::new(Decay::default())),
+            note_repeat: Arc::new(RwLock::new(NoteRepeat::default())),
+            gain: Arc::new(RwLock::new(Gain::default())),
+            regen_on_loop: Arc::new(RwLock::new(false)),
+            pending_buffer: Arc::new(RwLock::new(None)),
+            phase: Arc::new(RwLock::new(0.)),
+            mpe_range: Arc::new(RwLock::new(None)),
+            start_offset: Arc::new(RwLock::new(0.)),
+            followers: Arc::new(RwLock::new(vec![])),
+            ticks_per_bar: Arc::new(RwLock::new(ticks_per_bar)),
+            mirrored_notes: Arc::new(RwLock::new(vec![])),
+            id,
+        }
+    }
+
+    /// Copy of the BaseSeq sharing no state with it, but its followers which are left empty,
+    /// see [snapshot_base_seqs]
+    fn snapshot(&self) -> BaseSeq {
+        BaseSeq {
+            params: Arc::new(RwLock::new(self.params.read().clone())),
+            event_head: Arc::new(RwLock::new(0)),
+            event_buffer: Arc::new(RwLock::new(self.event_buffer.read().clone())),
+            fx_proc_ids: Arc::new(RwLock::new(self.fx_proc_ids.read().clone())),
+            fx_bypassed: Arc::new(RwLock::new(*self.fx_bypassed.read())),
+            paused: Arc::new(RwLock::new(*self.paused.read())),
+            muted_regions: Arc::new(RwLock::new(self.muted_regions.read().clone())),
+            rng: Arc::new(RwLock::new(self.rng.read().clone())),
+            seed: Arc::new(RwLock::new(*self.seed.read())),
+            play_rng: Arc::new(RwLock::new(self.play_rng.read().clone())),
+            evolve: Arc::new(RwLock::new(*self.evolve.read())),
+            decay: Arc::new(RwLock::new(*self.decay.read())),
+            note_repeat: Arc::new(RwLock::new(*self.note_repeat.read())),
+            gain: Arc::new(RwLock::new(*self.gain.read())),
+            regen_on_loop: Arc::new(RwLock::new(*self.regen_on_loop.read())),
+            pending_buffer: Arc::new(RwLock::new(self.pending_buffer.read().clone())),
+            phase: Arc::new(RwLock::new(*self.phase.read())),
+            mpe_range: Arc::new(RwLock::new(*self.mpe_range.read())),
+            start_offset: Arc::new(RwLock::new(*self.start_offset.read())),
+            followers: Arc::new(RwLock::new(vec![])),
+            ticks_per_bar: Arc::new(RwLock::new(*self.ticks_per_bar.read())),
+            mirrored_notes: Arc::new(RwLock::new(self.mirrored_notes.read().clone())),
+            id: self.id,
+        }
+    }
+
+    /// Position in the shared transport loop of an event at the bar_pos, given the phase.
+    /// The events past the loop end are left there, to be skipped.
+    pub(crate) fn phased_pos(&self, bar_pos: f32, loop_len: f32) -> f32 {
+        if bar_pos < loop_len {
+            (bar_pos + *self.phase.read()).rem_euclid(loop_len)
+        } else {
+            bar_pos
+        }
+    }
+
+    /// Fail when the note offs are kept past the loop end, which a buffer pass converting to
+    /// durations would wrap again, see [BaseSeqParams::wrap_note_off]
+    fn check_wrap_note_off(&self) -> anyhow::Result<()> {
+        if !self.params.read().wrap_note_off {
+            bail!("The note offs are kept past the loop end, see set_wrap_note_off.");
+        }
+        Ok(())
+    }
+
+    /// Whether the BaseSeq has nothing to do in a jack cycle: paused, or without events to play
+    /// and nothing to swap in, evolve nor decay at its loop boundary
+    pub(crate) fn is_idle(&self) -> bool {
+        *self.paused.read()
+            || (self.event_buffer.read().is_empty()
+                && self.pending_buffer.read().is_none()
+                && !*self.evolve.read()
+                && self.decay.read().factor == 1.)
+    }
+
+    /// Whether the bar_pos falls in one of the muted regions
+    pub(crate) fn is_muted_at(&self, bar_pos: f32) -> bool {
+        self.muted_regions.read().iter().any(|&(start, end)| {
+            if start <= end {
+                start <= bar_pos && bar_pos < end
+            } else {
+                start <= bar_pos || bar_pos < end
+            }
+        })
+    }
+
+    /// Fill the event buffer of a BaseSeq.
+    /// The jack process window end time gives a reference point to the present time for the synchronizing
+    /// of the BaseSeq event_head
+    fn gen_fill(&self, seq_int: &SeqInternal) -> anyhow::Result<()> {
+        let Some(events) = self.gen_events()? else {
+            return Ok(());
+        };
+        self.load_events(events, seq_int);
+        Ok(())
+    }
+
+    /// Replace the events of the BaseSeq, right away or at its next loop boundary,
+    /// see [BaseSeq::regen_on_loop]
+    fn load_events(&self, events: Vec<Event>, seq_int: &SeqInternal) {
+        if *self.regen_on_loop.read() {
+            *self.pending_buffer.write() = Some(events);
+        } else {
+            self.fill(events, seq_int);
+        }
+    }
+
+    fn fill(&self, events: Vec<Event>, seq_int: &SeqInternal) {
+        *self.event_buffer.write() = events;
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    /// Swap in the pending events, returns whether there were any
+    pub(crate) fn swap_pending(&self, seq_int: &SeqInternal) -> bool {
+        let Some(events) = self.pending_buffer.write().take() else {
+            return false;
+        };
+        self.fill(events, seq_int);
+        // On a window ending right on the loop start, the sync skips the first events
+        let loop_pos = (seq_int.j_window_time_end - *self.phase.read() as f64)
+            .rem_euclid(self.params.read().loop_length as f64);
+        if loop_pos == 0. {
+            *self.event_head.write() = 0;
+        }
+        true
+    }
+
+    /// Generate the events of the BaseSeq, sorted, without touching its event buffer.
+    /// None for the BaseSeqs that are not generated.
+    /// The generator is reset to a seed drawn from itself, which is kept so that the
+    /// generation can be reproduced, see [BaseSeq::reseed].
+    fn gen_events(&self) -> anyhow::Result<Option<Vec<Event>>> {
+        let seed = self.rng.write().gen();
+        self.gen_events_seeded(seed)
+    }
+
+    fn gen_events_seeded(&self, seed: u64) -> anyhow::Result<Option<Vec<Event>>> {
+        *self.seed.write() = seed;
+        *self.rng.write() = StdRng::seed_from_u64(seed);
+        let mut events = match self.params.read().ty {
+            Random(_) => gen_rand_midi_vec(self),
+            Euclid(_) => gen_euclid_midi_vec(self)?,
+            Steps(_) => gen_step_midi_vec(self),
+            // Followers are only filled from their source
+            Harmony(_) => return Ok(None),
+            Custom => return Ok(None),
+        };
+        events.sort_by_key(Event::sort_key); //TODO use FP32 instead
+        let params = self.params.read();
+        if let Some(contour) = &params.velocity_contour {
+            apply_velocity_contour(&mut events, contour);
+        }
+        // On the generated positions, the master shuffle only being applied at playback
+        if let Some(grid_velocity) = params.grid_velocity {
+            apply_grid_velocity(&mut events, grid_velocity);
+        }
+        if params.legato {
+            events = connect_notes(events, params.loop_length);
+        }
+        if let Some(ratio) = params.gate_ratio {
+            events = apply_gate_ratio(events, ratio, params.loop_length);
+        }
+        for interval in &params.doublings {
+            events = double_notes(events, *interval, params.loop_length);
+        }
+        if !params.doublings.is_empty() {
+            events = dedup_notes(events, params.loop_length);
+        }
+        events = snap_to_ticks(events, *self.ticks_per_bar.read(), params.loop_length);
+        if params.explicit_durations {
+            events = events_to_durations(events, params.loop_length);
+        }
+        Ok(Some(events))
+    }
+
+    /// Copy the events of the source into a Harmony BaseSeq, shifted by its interval
+    /// and moved to its channel. The loop of the source is taken over to stay in sync.
+    pub(self) fn mirror(&self, source: &BaseSeq, seq_int: &SeqInternal) {
+        let source_params = source.params.read();
+        let mut params = self.params.write();
+        let interval = match params.ty {
+            Harmony(HarmonyBase { interval, .. }) => interval,
+            _ => return,
+        };
+        params.loop_length = source_params.loop_length;
+        params.note_len_avg = source_params.note_len_avg;
+        params.explicit_durations = source_params.explicit_durations;
+        let (midi_ch, loop_length, explicit_durations) = (
+            params.midi_ch,
+            params.loop_length,
+            params.explicit_durations,
+        );
+        drop(params);
+        drop(source_params);
+
+        let events = source
+            .event_buffer
+            .read()
+            .iter()
+            .map(|event| {
+                let mut event = event.clone();
+                if let EventType::MidiNote(ref mut note) = event.e_type {
+                    note.pitch = (note.pitch as i32 + interval).clamp(0, 127) as u8;
+                    note.channel = midi_ch;
+                }
+                event
+            })
+            .collect();
+        // The pitches clamped to the midi range may collide
+        let events = dedup_notes(events, loop_length);
+        *self.event_buffer.write() = if explicit_durations {
+            events_to_durations(events, loop_length)
+        } else {
+            events
+        };
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn update_followers(&self, seq_int: &SeqInternal) {
+        for follower in self.followers.read().iter() {
+            follower.mirror(self, seq_int);
+        }
+    }
+
+    /// Reseed the generator from the master seed and the loop count, then regenerate.
+    /// The resulting sequence of variations is reproducible for a given master seed.
+    pub fn evolve(
+        &self,
+        master_seed: u64,
+        loop_count: u64,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let seed = evolve_seed(master_seed, self.id, loop_count);
+        *self.rng.write() = StdRng::seed_from_u64(seed);
+        // Already at the loop boundary
+        if let Some(events) = self.gen_events()? {
+            self.fill(events, seq_int);
+        }
+        Ok(())
+    }
+
+    /// Regenerate the events from the given seed, as read from [BaseSeq::seed]
+    pub(self) fn reseed(&self, seed: u64, seq_int: &SeqInternal) -> anyhow::Result<()> {
+        let Some(events) = self.gen_events_seeded(seed)? else {
+            bail!("Only the generated base sequences can be reseeded.");
+        };
+        self.load_events(events, seq_int);
+        Ok(())
+    }
+
+    pub fn reseed_playback(&self, master_seed: u64) {
+        *self.play_rng.write() = StdRng::seed_from_u64(playback_seed(master_seed, self.id));
+    }
+
+    /// Random draw that succeeds with the given probability
+    pub fn roll(&self, prob: f32) -> bool {
+        prob >= 1. || self.play_rng.write().gen::<f32>() < prob
+    }
+
+    pub(crate) fn sync_event_head(&self, seq_int: &SeqInternal) {
+        self.resync_event_head(seq_int);
+        *self.mirrored_notes.write() =
+            mirrored_notes(&self.event_buffer.read(), self.params.read().loop_length);
+
+        println!("Event head synced!")
+    }
+
+    /// Head only part of [BaseSeq::sync_event_head], for an event buffer left unchanged since
+    /// its last sync
+    pub(crate) fn resync_event_head(&self, seq_int: &SeqInternal) {
+        // Reset event_head to next idx right after the current jack window
+        // The preliminary binary search is an optional optimization.
+        let event_buffer = self.event_buffer.read();
+        let mut new_head = match event_buffer.binary_search_by_key(
+            &(SORT_TICKS_PER_BAR
+                * ((seq_int.j_window_time_end - *self.phase.read() as f64)
+                    .rem_euclid(self.params.read().loop_length as f64) as u32)),
+            |e| e.sort_key().0,
+        ) {
+            Ok(idx) | Err(idx) => idx,
+        };
+
+        if new_head == event_buffer.len() {
+            new_head = 0;
+        } else if let Some(idx) = event_buffer[new_head..]
+            .iter()
+            .position(|e| e.bar_pos > event_buffer[new_head].bar_pos)
+        {
+            // As the return of the binary search for multiple matches is arbitrary,
+            // we look for the exact event.
+            new_head += idx;
+        } else {
+            new_head = 0;
+        }
+
+        *self.event_head.write() = min(new_head, event_buffer.len().saturating_sub(1));
+    }
+
+    /// Convert the EventBuffer in place between separate note offs and note on durations
+    pub(self) fn set_explicit_durations(&self, explicit_durations: bool, seq_int: &SeqInternal) {
+        let mut params = self.params.write();
+        if params.explicit_durations == explicit_durations {
+            return;
+        }
+        params.explicit_durations = explicit_durations;
+        let mut event_buff = self.event_buffer.write();
+        let events = std::mem::take(&mut *event_buff);
+        *event_buff = if explicit_durations {
+            events_to_durations(events, params.loop_length)
+        } else {
+            durations_to_events(events, params.loop_length)
+        };
+        drop(event_buff);
+        drop(params);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn change_note_len(
+        &self,
+        target_note_len: f32,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        let mut event_buff = self.event_buffer.write();
+        for event in event_buff.iter_mut() {
+            if let Some(ref mut duration) = event.duration {
+                *duration = (*duration + target_note_len - params.note_len_avg).max(0.);
+            } else if let EventType::MidiNote(MidiNote { on_off, .. }) = event.e_type {
+                if !on_off {
+                    event.bar_pos = event.bar_pos + target_note_len - params.note_len_avg;
+                    if params.wrap_note_off {
+                        event.bar_pos %= params.loop_length;
+                    }
+                }
+            }
+        }
+        params.note_len_avg = target_note_len;
+
+        event_buff.sort_by_key(Event::sort_key);
+        drop(event_buff);
+        drop(params);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+        Ok(())
+    }
+
+    /// Note ons past the loop end are kept to be played again if the loop is lengthened,
+    /// but their note offs are wrapped to avoid stuck notes, unless they are sent from their
+    /// note on, see [BaseSeqParams::wrap_note_off].
+    pub(self) fn change_loop_len(&self, target_loop_len: f32, seq_int: &SeqInternal) {
+        let mut params = self.params.write();
+        params.loop_length = target_loop_len;
+        let wrap_note_off = params.wrap_note_off;
+        drop(params);
+        let mut event_buff = self.event_buffer.write();
+        if wrap_note_off {
+            for event in event_buff.iter_mut() {
+                if let EventType::MidiNote(MidiNote { on_off: false, .. }) = event.e_type {
+                    event.bar_pos %= target_loop_len;
+                }
+            }
+        }
+        event_buff.sort_by_key(Event::sort_key);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn set_nb_events(
+        &self,
+        target_nb_events: u32,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let BaseSeqParams {
+            ty: Random(RandomBase {
+                ref mut nb_events, ..
+            }),
+            ..
+        } = *params
+        {
+            *nb_events = target_nb_events;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        };
+        drop(params);
+        self.gen_fill(seq_int)?;
+        Ok(())
+    }
+
+    pub(self) fn set_spacing(
+        &self,
+        target_spacing: Spacing,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let BaseSeqParams {
+            ty: Random(RandomBase {
+                ref mut spacing, ..
+            }),
+            ..
+        } = *params
+        {
+            *spacing = target_spacing;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        };
+        drop(params);
+        self.gen_fill(seq_int)?;
+        Ok(())
+    }
+
+    pub(self) fn set_euclid(
+        &self,
+        target_pulses: Option<u32>,
+        target_steps: Option<u32>,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        let loop_length = params.loop_length;
+        if let Euclid(EuclidBase {
+            ref mut pulses,
+            ref mut steps,
+        }) = params.ty
+        {
+            let new_pulses = target_pulses.unwrap_or(*pulses);
+            let new_steps = target_steps.unwrap_or(*steps);
+            if new_steps == 0 {
+                bail!("Steps should be at least 1.");
+            }
+            if new_pulses > new_steps {
+                bail!("Pulses should be less than steps.");
+            }
+            if loop_length % new_steps as f32 != 0. {
+                bail!("The loop length should be a multiple of the steps.");
+            }
+            *pulses = new_pulses;
+            *steps = new_steps;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        }
+        drop(params);
+        self.gen_fill(seq_int)
+    }
+
+    /// Expand the Euclidean rhythm into an editable step pattern.
+    /// The events are left untouched as they already follow the pattern.
+    pub(self) fn euclid_to_steps(&self) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let Euclid(EuclidBase { pulses, steps }) = params.ty {
+            let pattern = gen_euclid(pulses, steps)?.iter().map(|s| *s == 1).collect();
+            params.ty = Steps(StepsBase { pattern });
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        }
+        Ok(())
+    }
+
+    pub(self) fn toggle_step(&self, step_idx: usize, seq_int: &SeqInternal) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let Steps(StepsBase { ref mut pattern }) = params.ty {
+            let step = pattern
+                .get_mut(step_idx)
+                .ok_or_else(|| anyhow!("Step {step_idx} is out of the pattern."))?;
+            *step = !*step;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        }
+        drop(params);
+        self.gen_fill(seq_int)
+    }
+
+    pub(self) fn set_octave_range(
+        &self,
+        target_octaves: u32,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let BaseSeqParams {
+            ty: Random(RandomBase {
+                ref mut octaves, ..
+            }),
+            ..
+        } = *params
+        {
+            *octaves = target_octaves;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        };
+        drop(params);
+        self.gen_fill(seq_int)?;
+        Ok(())
+    }
+
+    pub(self) fn set_octave_scales(
+        &self,
+        target_octave_scales: Vec<OctaveScale>,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let Random(RandomBase {
+            ref mut octave_scales,
+            ..
+        }) = params.ty
+        {
+            *octave_scales = target_octave_scales;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        }
+        drop(params);
+        self.gen_fill(seq_int)
+    }
+
+    pub(self) fn set_markov(
+        &self,
+        weights: Option<Vec<f32>>,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let Random(RandomBase { ref mut markov, .. }) = params.ty {
+            *markov = weights;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        }
+        drop(params);
+        self.gen_fill(seq_int)
+    }
+
+    pub(self) fn set_velocity_dist(
+        &self,
+        target_velocity_dist: VelocityDist,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        if let Random(RandomBase {
+            ref mut velocity_dist,
+            ..
+        }) = params.ty
+        {
+            *velocity_dist = target_velocity_dist;
+        } else {
+            bail!("The given base_seq_id is wrong.");
+        }
+        drop(params);
+        self.gen_fill(seq_int)
+    }
+
+    /// Move a note and set its velocity. The note index addresses note ons (i.e. note pairs)
+    /// in their order in the EventBuffer, not raw buffer slots: the matching note off is
+    /// moved along so that the note length is kept.
+    pub(self) fn edit_event(
+        &self,
+        note_idx: usize,
+        bar_pos: f32,
+        velocity: u8,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let params = self.params.read();
+        if !(0. ..params.loop_length).contains(&bar_pos) {
+            bail!("The bar position {bar_pos} is out of the loop.");
+        }
+        let mut event_buff = self.event_buffer.write();
+        let events = std::mem::take(&mut *event_buff);
+        // Pair the note offs with their note ons so that the index addresses whole notes
+        let mut events = if params.explicit_durations {
+            events
+        } else {
+            events_to_durations(events, params.loop_length)
+        };
+        let edited = events
+            .iter_mut()
+            .filter(|e| e.is_note_on())
+            .nth(note_idx)
+            .map(|event| {
+                event.bar_pos = bar_pos;
+                if let EventType::MidiNote(ref mut note) = event.e_type {
+                    note.velocity = velocity;
+                }
+            });
+        let nb_notes = events.iter().filter(|e| e.is_note_on()).count();
+        *event_buff = if params.explicit_durations {
+            events
+        } else {
+            durations_to_events(events, params.loop_length)
+        };
+        if edited.is_none() {
+            bail!("Note {note_idx} is out of the {nb_notes} notes of the sequence.");
+        }
+        event_buff.sort_by_key(Event::sort_key);
+        drop(event_buff);
+        drop(params);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+        Ok(())
+    }
+
+    /// Euclid steps are only picked among the ones dividing the loop, the rhythm could not be
+    /// generated otherwise.
+    pub(self) fn randomize(
+        &self,
+        bounds: &RandomizeBounds,
+        rng: &mut StdRng,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        let loop_length = params.loop_length;
+        match params.ty {
+            Random(ref mut random) => {
+                random.nb_events = rng.gen_range(bounds.nb_events.0..=bounds.nb_events.1);
+            }
+            Euclid(ref mut euclid) => {
+                let steps: Vec<u32> = (bounds.steps.0..=bounds.steps.1)
+                    .filter(|steps| loop_length % *steps as f32 == 0.)
+                    .collect();
+                if !steps.is_empty() {
+                    euclid.steps = steps[rng.gen_range(0..steps.len())];
+                }
+                euclid.pulses = rng
+                    .gen_range(bounds.pulses.0..=bounds.pulses.1)
+                    .min(euclid.steps);
+            }
+            Steps(_) => {}
+            Harmony(_) | Custom => bail!("The given base_seq_id is wrong."),
+        }
+        params.note_len_avg = rng.gen_range(bounds.note_len.0..=bounds.note_len.1);
+        params.velocity_avg = rng.gen_range(bounds.velocity.0..=bounds.velocity.1);
+        params.root_note = midi_pitch_to_note(rng.gen_range(bounds.root.0..=bounds.root.1))?;
+        drop(params);
+        self.gen_fill(seq_int)
+    }
+
+    /// Offset the velocity of the note ons, keeping them audible
+    pub(self) fn add_velocity(&self, delta: i32, seq_int: &SeqInternal) {
+        for event in self.event_buffer.write().iter_mut() {
+            if let EventType::MidiNote(ref mut note @ MidiNote { on_off: true, .. }) = event.e_type
+            {
+                note.velocity = (note.velocity as i32 + delta).clamp(1, 127) as u8;
+            }
+        }
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn quantize(&self, division: u32, seq_int: &SeqInternal) {
+        let loop_length = self.params.read().loop_length;
+        let mut event_buff = self.event_buffer.write();
+        let events = std::mem::take(&mut *event_buff);
+        *event_buff = quantize_positions(events, division, 0., loop_length);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    /// Double the notes at the interval. The interval is kept to double the regenerated notes too.
+    pub(self) fn double(&self, interval: i32, seq_int: &SeqInternal) {
+        let mut params = self.params.write();
+        params.doublings.push(interval);
+        let mut event_buff = self.event_buffer.write();
+        let events = double_notes(
+            std::mem::take(&mut *event_buff),
+            interval,
+            params.loop_length,
+        );
+        let events = dedup_notes(events, params.loop_length);
+        *event_buff = if params.explicit_durations {
+            events_to_durations(events, params.loop_length)
+        } else {
+            events
+        };
+        drop(params);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    /// Snap the events on the ticks of the resolution, see [snap_to_ticks]
+    pub(self) fn snap_to_ticks(&self, seq_int: &SeqInternal) {
+        let params = self.params.read();
+        let mut event_buff = self.event_buffer.write();
+        let events = snap_to_ticks(
+            std::mem::take(&mut *event_buff),
+            *self.ticks_per_bar.read(),
+            params.loop_length,
+        );
+        *event_buff = if params.explicit_durations {
+            events_to_durations(events, params.loop_length)
+        } else {
+            events
+        };
+        drop(params);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn dedup_events(&self, seq_int: &SeqInternal) {
+        let params = self.params.read();
+        let mut event_buff = self.event_buffer.write();
+        let events = dedup_notes(std::mem::take(&mut *event_buff), params.loop_length);
+        *event_buff = if params.explicit_durations {
+            events_to_durations(events, params.loop_length)
+        } else {
+            events
+        };
+        drop(params);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn set_gate_ratio(&self, ratio: f32, seq_int: &SeqInternal) {
+        let mut params = self.params.write();
+        params.gate_ratio = Some(ratio);
+        let mut event_buff = self.event_buffer.write();
+        let events = apply_gate_ratio(std::mem::take(&mut *event_buff), ratio, params.loop_length);
+        *event_buff = if params.explicit_durations {
+            events_to_durations(events, params.loop_length)
+        } else {
+            events
+        };
+        drop(params);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn strum(&self, spread: f32, seq_int: &SeqInternal) {
+        let params = self.params.read();
+        let mut event_buff = self.event_buffer.write();
+        let events = strum_chords(std::mem::take(&mut *event_buff), spread, params.loop_length);
+        *event_buff = if params.explicit_durations {
+            events_to_durations(events, params.loop_length)
+        } else {
+            events
+        };
+        drop(params);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn trim(&self, leading: bool, trailing: bool, seq_int: &SeqInternal) {
+        let mut params = self.params.write();
+        let mut event_buff = self.event_buffer.write();
+        let (events, loop_length) = trim_silence(
+            std::mem::take(&mut *event_buff),
+            params.loop_length,
+            leading,
+            trailing,
+        );
+        params.loop_length = loop_length;
+        *event_buff = if params.explicit_durations {
+            events_to_durations(events, loop_length)
+        } else {
+            events
+        };
+        drop(params);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn mask(&self, mask: &[u8], seq_int: &SeqInternal) {
+        let params = self.params.read();
+        let mut event_buff = self.event_buffer.write();
+        let events = apply_rhythm_mask(std::mem::take(&mut *event_buff), mask, params.loop_length);
+        *event_buff = if params.explicit_durations {
+            events_to_durations(events, params.loop_length)
+        } else {
+            events
+        };
+        drop(params);
+        drop(event_buff);
+        self.sync_event_head(seq_int);
+        self.update_followers(seq_int);
+    }
+
+    pub(self) fn transpose(
+        &self,
+        target_root_note: Note,
+        seq_int: &SeqInternal,
+    ) -> anyhow::Result<()> {
+        let mut params = self.params.write();
+        let root_note_midi = note_to_midi_pitch(&params.root_note);
+        let target_root_note_midi = note_to_midi_pitch(&target_root_note);
+        let pitch_diff = target_root_note_midi as i32 - root_note_midi as i32;
+        for event in self.event_buffer.write().iter_mut() {
+            if let EventType::MidiNote(MidiNote { ref mut pitch, .. }) = event.e_type {
+                *pitch = (*pitch as i32 + pitch_diff).clamp(0, 127) as u8;
+            }
+        }
+        params.root_note = target_root_note;
+        drop(params);
+        self.update_followers(seq_int);
+        Ok(())
+    }
+
+    /// Shift the notes by degrees of the major scale of the root note, see [diatonic_shift].
+    /// The root note is kept, as the key does not change.
+    pub(self) fn transpose_diatonic(&self, degrees: i32, seq_int: &SeqInternal) {
+        let root_pitch = note_to_midi_pitch(&self.params.read().root_note);
+        for event in self.event_buffer.write().iter_mut() {
+            if let EventType::MidiNote(MidiNote { ref mut pitch, .. }) = event.e_type {
+                *pitch = diatonic_shift(*pitch, root_pitch, degrees);
+            }
+        }
+        self.update_followers(seq_int);
+    }
+
+    /// Mirror the pitches around the pivot, note offs included so that they stay matched
+    pub(self) fn invert(&self, pivot: u8, seq_int: &SeqInternal) {
+        for event in self.event_buffer.write().iter_mut() {
+            if let EventType::MidiNote(MidiNote { ref mut pitch, .. }) = event.e_type {
+                *pitch = invert_pitch(*pitch, pivot);
+            }
+        }
+        self.update_followers(seq_int);
+    }
+
+    /// Render the event buffer as an ascii piano roll, a row per pitch and a column per grid
+    /// step, the grid getting coarser on long loops to keep the width bounded
+    pub fn to_ascii(&self) -> String {
+        let events = self.event_buffer.read().clone();
+        ascii_piano_roll(events, self.params.read().loop_length)
+    }
+
+    pub fn incr_event_head(&self) {
+        let curr_event_head = *self.event_head.read();
+        *self.event_head.write() = (curr_event_head + 1) % self.event_buffer.read().len();
+    }
+
+    //TODO to be used in when inserting evnets to increase nb_events without regen
+    // /// The input events need to be sorted by bar_pos
+    // pub fn insert_events(&self, events: Vec<Event>) {
+    //     let mut event_buffer_mut = self.event_buffer.write();
+    //     let mut buff_idx = 0;
+    //     for e in events {
+    //         while buff_idx < event_buffer_mut.len() {
+    //             if event_buffer_mut[buff_idx].bar_pos < e.bar_pos {
+    //                 buff_idx += 1;
+    //             } else {
+    //                 break;
+    //             }
+    //         }
+    //         event_buffer_mut.insert(buff_idx, e.clone());
+    //         buff_idx += 1;
+    //     }
+    // }
+}
+
+/// Play probability under which a decaying BaseSeq is silenced
+const DECAY_THRESHOLD: f32 = 0.01;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Decay {
+    /// Multiplier applied to the play probability at each loop boundary
+    pub factor: f32,
+    /// Current probability for a note on to be played
+    pub play_prob: f32,
+}
+
+impl Default for Decay {
+    fn default() -> Self {
+        Decay {
+            factor: 1.,
+            play_prob: 1.,
+        }
+    }
+}
+
+impl Decay {
+    pub fn next_loop(&mut self) {
+        self.play_prob *= self.factor;
+        if self.play_prob < DECAY_THRESHOLD {
+            self.play_prob = 0.;
+        }
+    }
+}
+
+/// Roll of a note at playback: with the given probability, a note on is split into count
+/// evenly spaced retriggers over the note length, the last one ending with the original note off
+#[derive(Clone, Copy, Debug)]
+pub struct NoteRepeat {
+    pub prob: f32,
+    pub count: u32,
+}
+
+impl Default for NoteRepeat {
+    fn default() -> Self {
+        NoteRepeat { prob: 0., count: 1 }
+    }
+}
+
+/// Velocity gain of a BaseSeq at playback, the scale being applied first, then the offset:
+/// `clamp(velocity * scale + offset, 1, 127)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gain {
+    pub scale: f32,
+    pub offset: i32,
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Gain {
+            scale: 1.,
+            offset: 0,
+        }
+    }
+}
+
+impl Gain {
+    pub fn apply(&self, velocity: u8) -> u8 {
+        (velocity as f32 * self.scale + self.offset as f32)
+            .round()
+            .clamp(1., 127.) as u8
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////
+// LFOs
+
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum LfoShape {
+    Sine,
+    Tri,
+    Saw,
+    Square,
+}
+
+impl LfoShape {
+    /// Value in [-1, 1] at the given phase in [0, 1), starting upwards from 0
+    fn value(self, phase: f32) -> f32 {
+        match self {
+            LfoShape::Sine => (phase * std::f32::consts::TAU).sin(),
+            LfoShape::Tri => 1. - 4. * ((phase + 0.25) % 1. - 0.5).abs(),
+            LfoShape::Saw => 2. * ((phase + 0.5) % 1.) - 1.,
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfoTarget {
+    Bpm,
+    /// Channel should be 1-16
+    Cc {
+        channel: u8,
+        controller: u8,
+    },
+}
+
+/// Low frequency oscillator, synced to the sequencer time.
+/// The LFOs of a same target are summed.
+#[derive(Clone, Copy, Debug)]
+pub struct Lfo {
+    pub shape: LfoShape,
+    /// In bars
+    pub period: f32,
+    /// Amplitude, in the unit of the target
+    pub depth: f32,
+    pub target: LfoTarget,
+}
+
+impl Lfo {
+    /// Value at the given time in bars since start
+    pub fn value(&self, time: f64) -> f32 {
+        let phase = (time / self.period as f64).rem_euclid(1.) as f32;
+        self.depth * self.shape.value(phase)
+    }
+}
+
+/// Seed of the playback generator of a BaseSeq, distinct from its evolve seeds
+fn playback_seed(master_seed: u64, base_seq_id: u32) -> u64 {
+    evolve_seed(!master_seed, base_seq_id, 0)
+}
+
+/// Derive the generation seed of an evolving BaseSeq for a given loop (splitmix64 finalizer).
+/// The base_seq_id is mixed in so that identical BaseSeqs do not evolve in lockstep.
+fn evolve_seed(master_seed: u64, base_seq_id: u32, loop_count: u64) -> u64 {
+    let mut z = master_seed
+        ^ (base_seq_id as u64).wrapping_mul(0xD1B5_4A32_D192_ED03)
+        ^ loop_count.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub(crate) const DEFAULT_TICKS_PER_BAR: u32 = 1_000;
+/// Keeps the tick positions of loops of a few thousand bars within u32
+const MAX_TICKS_PER_BAR: u32 = 100_000;
+/// Resolution of the fixed point positions by which the EventBuffers are sorted and searched,
+/// in ticks per bar. The finest of the resolutions, for their ticks to keep their order.
+const SORT_TICKS_PER_BAR: u32 = MAX_TICKS_PER_BAR;
+
+/// Nb of ticks per bar of the grid on which the loop lengths are taken for their combined cycle
+const CYCLE_GRID_DIV: u64 = 64;
+
+/// Least common multiple of the lengths in bars, in ticks of 1/[CYCLE_GRID_DIV] bar.
+/// Each length is rounded to the nearest tick, at least one, so lengths off the grid give the
+/// cycle of their rounded values: a loop of 1/3 bar is taken as 21 ticks.
+fn combined_cycle_ticks(lengths: &[f32]) -> anyhow::Result<u64> {
+    let gcd = |mut a: u64, mut b: u64| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    lengths.iter().try_fold(1u64, |lcm, len| {
+        let ticks = ((len * CYCLE_GRID_DIV as f32).round() as u64).max(1);
+        (lcm / gcd(lcm, ticks))
+            .checked_mul(ticks)
+            .ok_or_else(|| anyhow!("The combined cycle is too long."))
+    })
+}
+
+/// Loop lengths end up in the modulos of the time math, which a zero or a NaN would poison
+fn check_loop_len(loop_len: f32) -> anyhow::Result<()> {
+    if !(loop_len.is_finite() && 0. < loop_len) {
+        bail!("The loop length should be a positive finite number.");
+    }
+    Ok(())
+}
+
+/// Copy of the BaseSeqs, see [BaseSeq::snapshot], with the followers linked to the copies
+fn snapshot_base_seqs(base_seqs: &[BaseSeq]) -> Vec<BaseSeq> {
+    let copies = base_seqs.iter().map(BaseSeq::snapshot).collect::<Vec<_>>();
+    for (base_seq, copy) in base_seqs.iter().zip(&copies) {
+        *copy.followers.write() = base_seq
+            .followers
+            .read()
+            .iter()
+            .filter_map(|f| copies.iter().find(|c| c.id == f.id).cloned())
+            .collect();
+    }
+    copies
+}
+
+/// Random loop rotation offset of a BaseSeq for a loop, up to the amount fraction of the loop
+fn rotation_offset(
+    amount: f32,
+    seed: u64,
+    base_seq_id: u32,
+    loop_count: u64,
+    loop_len: f32,
+) -> f32 {
+    let draw = (evolve_seed(seed, base_seq_id, loop_count) >> 40) as f32 / (1 << 24) as f32;
+    amount * draw * loop_len
+}
+
+#[derive(Clone, Debug)]
+pub struct RandomBase {
+    pub nb_events: u32,
+    /// Distribution of the time between consecutive events
+    pub spacing: Spacing,
+    /// Nb of octaves, from the root note up, over which the pitches are drawn
+    pub octaves: u32,
+    /// Scales used on given octaves, the major one is used on the others
+    pub octave_scales: Vec<OctaveScale>,
+    /// Weights of the moves from a pitch to the next by their nb of scale degrees, the first one
+    /// being for a repeat, see [crate::midi::DEFAULT_MARKOV_WEIGHTS]. None draws the pitches independently.
+    pub markov: Option<Vec<f32>>,
+    pub velocity_dist: VelocityDist,
+}
+
+/// Scale used for the pitches drawn in a range of octaves, relative to the root note
+#[derive(Clone, Copy, Debug)]
+pub struct OctaveScale {
+    /// First and last octave, included
+    pub octaves: (u32, u32),
+    pub mode: Mode,
+}
+
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum Spacing {
+    /// Uniformly distributed over the whole loop
+    Uniform,
+    /// Uniformly distributed, snapped to a sixteenth note grid
+    Grid,
+    /// Exponentially distributed with nb_events per loop on average, as in a Poisson process
+    Poisson,
+}
+
+/// Distribution of the note on velocities of a Random BaseSeq
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VelocityDist {
+    /// Normal, of the velocity avg and div of the BaseSeq
+    Normal,
+    /// Uniform between min and max, inclusive
+    Uniform { min: u8, max: u8 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EuclidBase {
+    pub pulses: u32,
+    pub steps: u32,
+}
+
+/// Explicit rhythm, spread evenly over the loop
+#[derive(Clone, Debug)]
+pub struct StepsBase {
+    /// Whether each step holds a note
+    pub pattern: Vec<bool>,
+}
+
+/// Copy of the events of another BaseSeq, transposed
+#[derive(Clone, Debug)]
+pub struct HarmonyBase {
+    pub source_id: u32,
+    /// In semitones
+    pub interval: i32,
+}
+
+//////////////////////////////////////////////////////////////////////////
+// Effect Event processor
+
+/// Effect applied by an FxProcessor to the events going through it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FxKind {
+    /// Random pitch jitter of about a semitone
+    Jitter,
+    /// Fixed pitch shift in semitones, clamped to the midi pitches
+    Transpose { semitones: i32 },
+}
+
+pub struct FxProcessor {
+    rng: Arc<RwLock<StdRng>>,
+    distr: Normal<f64>,
+    // processor: Box<dyn Fn(Event) -> Event>,
+    pub kind: FxKind,
+    /// Disabled processors are skipped in the fx chain
+    pub enabled: Arc<RwLock<bool>>,
+    /// Unique identifier to the FxProcessors
+    pub id: u32,
+}
+
+impl FxProcessor {
+    fn new(id: u32, kind: FxKind) -> Self {
+        let rng = Arc::new(RwLock::new(rand::rngs::StdRng::from_entropy()));
+        let distr = Normal::new(0., 1.).unwrap();
+        // let processor = Box::new(|e| -> return e);
+        FxProcessor {
+            rng,
+            distr,
+            // processor,
+            kind,
+            enabled: Arc::new(RwLock::new(true)),
+            id,
+        }
+    }
+
+    pub(crate) fn process(&self, event: &mut Event) {
+        match event.e_type {
+            EventType::MidiNote(ref mut note) => match self.kind {
+                FxKind::Jitter => {
+                    let rng_guard = &mut *self.rng.write();
+                    note.pitch = (note.pitch as f64 + self.distr.sample(rng_guard)) as u8;
+                }
+                FxKind::Transpose { semitones } => {
+                    note.pitch = (note.pitch as i32 + semitones).clamp(0, 127) as u8;
+                }
+            },
+            EventType::PitchBend(_) => {}
+            EventType::_Fill => todo!(),
+        };
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////
+// Internal Sequencer state
+
+/// Assumed until the jack server gives its own
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// Additional SeqParams, only to be set and read by the jack Cycle
+pub struct SeqInternal {
+    /// Allows for cycle skipping when on pause/stop.
+    /// Indicates, when stopping, if we are on the final cycle before silence.
+    /// Only noteOff events are allowed on final cycle.
+    pub status: SeqInternalStatus,
+    /// Position of current jack cycle in time
+    /// In bars. To be reset on loop or start/stop
+    pub j_window_time_start: f64,
+    /// Position of current jack cycle in time
+    /// In bars. To be reset on loop or start/stop
+    pub j_window_time_end: f64,
+    /// Current bar position in loop rhythm grid.
+    /// Stored here for logging purposes
+    pub curr_bar: u32,
+    /// Nb of frames of the last jack cycle.
+    /// Stored here for logging purposes
+    pub j_buffer_size: u32,
+    /// Of the jack server, in Hz
+    pub sample_rate: u32,
+    /// Note offs derived from the durations of the note ons sent, with their time in bars and
+    /// the id of their BaseSeq
+    pub pending_note_offs: Vec<(f64, u32, Event)>,
+    /// One-shot events requested by the OSC process, with their time in bars and the id of
+    /// their BaseSeq
+    pub scheduled_events: Vec<(f64, u32, Event)>,
+    /// Stutters of BaseSeqs, requested by the OSC process
+    pub stutters: Vec<Stutter>,
+    /// Midi channels to send notes off to on the next cycle, requested by the OSC process
+    pub channel_flushes: Vec<u8>,
+    /// Velocity ducks between BaseSeqs, with their envelope
+    pub ducks: Vec<Duck>,
+    /// Channel and pitch of the note ons that could not be written, whose note offs are dropped
+    pub dropped_notes: Vec<(u8, u8)>,
+    /// Note offs that could not be written, to be retried on the next cycle
+    pub retry_note_offs: Vec<Event>,
+    /// Nb of events that can still be written in the current cycle, see
+    /// [SeqParams::max_events_per_cycle]
+    pub cycle_budget: Option<u32>,
+    /// Events over the budget of their cycle, in order, sent first on the next ones, with the
+    /// id of their BaseSeq if any
+    pub deferred_events: Vec<(Option<u32>, Event)>,
+    /// Max nb of note ons of the current cycle, see [SeqParams::max_polyphony]
+    pub polyphony_cap: Option<u32>,
+    /// Note ons of the current cycle under a polyphony cap, with their frame offsets and the id
+    /// of their BaseSeq if any, held back until the end of the cycle along with their note offs,
+    /// see [SeqInternal::send_staged_notes]
+    pub staged_notes: Vec<(u32, Option<u32>, Event)>,
+    /// Last values sent of the LFO modulated CCs, by channel and controller
+    pub lfo_cc_values: Vec<((u8, u8), u8)>,
+    /// Max loop rotation of BaseSeqs, by id, as a fraction of their loop in [0, 1)
+    pub loop_rotations: Vec<(u32, f32)>,
+    /// Channel overrides of BaseSeqs, requested by the OSC process
+    pub route_overrides: Vec<RouteOverride>,
+    /// Notes sounding on the channels allocated to them, oldest first, see
+    /// [SeqInternal::allocate_mpe_channel]
+    pub mpe_voices: Vec<MpeVoice>,
+    /// Next channel of the round robin allocation of the BaseSeqs, by id
+    pub mpe_cursors: Vec<(u32, u8)>,
+    /// Index of the last scene recalled
+    pub current_scene: Option<u32>,
+    /// Scene recalled on the next meta loop boundary, requested by the OSC process
+    pub pending_scene: Option<Scene>,
+    /// BaseSeqs replaced by the jack process, left for the OSC process to drop
+    pub retired_base_seqs: Option<Vec<BaseSeq>>,
+}
+
+/// Sidechain like ducking of the velocities of a BaseSeq by the notes of another
+pub struct Duck {
+    pub trigger_id: u32,
+    pub target_id: u32,
+    /// Fraction of the velocity removed right at the trigger, in [0, 1]
+    pub amount: f32,
+    /// In bars
+    pub release: f32,
+    /// Time in bars since start of the last trigger note on
+    pub last_trigger: Option<f64>,
+}
+
+/// Transient replay of a slice of a BaseSeq
+pub struct Stutter {
+    pub base_seq_id: u32,
+    /// In bars
+    pub slice_len: f32,
+    pub repeats: u32,
+    /// Time in bars since start and looped position at which the stutter started.
+    /// Set on the first jack cycle of the stutter.
+    pub start: Option<(f64, f64)>,
+}
+
+/// Playback time re-routing of a BaseSeq to another channel, see [Sequencer::route_override]
+pub struct RouteOverride {
+    pub base_seq_id: u32,
+    pub channel: u8,
+    /// Time in bars since start at which the BaseSeq is back on its channel
+    pub end: f64,
+}
+
+/// Note of a BaseSeq sounding on the channel allocated to it
+#[derive(Debug, PartialEq)]
+pub struct MpeVoice {
+    pub base_seq_id: u32,
+    pub pitch: u8,
+    pub channel: u8,
+}
+
+pub enum StutterCycle {
+    /// Positions of the current window relative to the slice start, [from, to) may wrap
+    /// around the slice end
+    Replay {
+        slice_start: f64,
+        slice_len: f64,
+        from: f64,
+        to: f64,
+    },
+    /// The stutter ends on this cycle, the BaseSeq resumes normally
+    Ended,
+}
+
+impl StutterCycle {
+    /// Whether an event is replayed in the current window
+    pub fn replays(&self, bar_pos: f32, loop_len: f32) -> bool {
+        match *self {
+            StutterCycle::Replay {
+                slice_start,
+                slice_len,
+                from,
+                to,
+            } => {
+                let rel_pos = (bar_pos as f64 - slice_start).rem_euclid(loop_len as f64);
+                rel_pos < slice_len
+                    && ((from <= rel_pos && rel_pos < to) || rel_pos < to - slice_len)
+            }
+            StutterCycle::Ended => false,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+pub enum SeqInternalStatus {
+    Silence,
+    Playing,
+}
+
+impl SeqInternal {
+    pub fn new() -> Self {
+        SeqInternal {
+            status: SeqInternalStatus::Silence,
+            j_window_time_start: 0.,
+            j_window_time_end: 0.,
+            curr_bar: 0,
+            j_buffer_size: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            pending_note_offs: vec![],
+            scheduled_events: vec![],
+            stutters: vec![],
+            channel_flushes: vec![],
+            ducks: vec![],
+            dropped_notes: vec![],
+            retry_note_offs: vec![],
+            cycle_budget: None,
+            deferred_events: vec![],
+            polyphony_cap: None,
+            staged_notes: vec![],
+            lfo_cc_values: vec![],
+            loop_rotations: vec![],
+            route_overrides: vec![],
+            mpe_voices: vec![],
+            mpe_cursors: vec![],
+            current_scene: None,
+            pending_scene: None,
+            retired_base_seqs: None,
+        }
+    }
+
+    /// Nb of complete loops of the given length played since start
+    pub fn loop_count(&self, loop_len: f32) -> u64 {
+        (self.j_window_time_end / loop_len as f64) as u64
+    }
+
+    /// Whether a loop of the given length is read backward in the current jack window,
+    /// the ping pong direction being set at the window start
+    pub fn is_backward(&self, direction: Direction, loop_len: f32) -> bool {
+        match direction {
+            Direction::Forward => false,
+            Direction::Backward => true,
+            Direction::PingPong => (self.j_window_time_start / loop_len as f64) as u64 % 2 == 1,
+        }
+    }
+
+    /// Whether the current jack window crossed the loop boundary
+    pub fn crossed_loop_boundary(&self, loop_len: f32) -> bool {
+        (self.j_window_time_start / loop_len as f64) as u64 != self.loop_count(loop_len)
+    }
+
+    /// Frame offset in the current jack cycle of an event at the given time in bars since start
+    pub fn frame_offset(&self, time: f64) -> u32 {
+        frame_offset(
+            time,
+            self.j_window_time_start,
+            self.j_window_time_end,
+            self.j_buffer_size,
+        )
+    }
+
+    /// Time in bars since start of an event of the current window
+    pub fn event_time(&self, bar_pos: f32, loop_len: f32) -> f64 {
+        let win_start_looped = self.j_window_time_start % (loop_len as f64);
+        self.j_window_time_start + (bar_pos as f64 - win_start_looped).rem_euclid(loop_len as f64)
+    }
+
+    /// Schedule the note off of a note on of a BaseSeq carrying its duration, sent at the given
+    /// time in bars, with the release velocity if any or the note on one
+    pub fn schedule_note_off(
+        &mut self,
+        base_seq_id: u32,
+        event: &Event,
+        on_time: f64,
+        off_velocity: Option<u8>,
+    ) {
+        if let (EventType::MidiNote(note), Some(duration)) = (&event.e_type, event.duration) {
+            let note_off = Event {
+                e_type: EventType::MidiNote(MidiNote {
+                    on_off: false,
+                    velocity: off_velocity.unwrap_or(note.velocity),
+                    ..*note
+                }),
+                bar_pos: event.bar_pos + duration,
+                duration: None,
+            };
+            self.pending_note_offs
+                .push((on_time + duration as f64, base_seq_id, note_off));
+        }
+    }
+
+    /// Schedule the retriggers of a rolled note on of a BaseSeq played at the given time in bars,
+    /// splitting its length evenly. Each retrigger is preceded by the note off of the previous
+    /// one, with the release velocity if any, and the last one is ended by the original note off.
+    pub fn schedule_note_repeats(
+        &mut self,
+        base_seq_id: u32,
+        event: &Event,
+        on_time: f64,
+        note_len: f32,
+        count: u32,
+        off_velocity: Option<u8>,
+    ) {
+        let EventType::MidiNote(note) = event.e_type else {
+            return;
+        };
+        let sub_len = note_len / count as f32;
+        let note_event = |on_off, k: u32| {
+            let offset = k as f32 * sub_len;
+            let velocity = if on_off {
+                note.velocity
+            } else {
+                off_velocity.unwrap_or(note.velocity)
+            };
+            let e_type = EventType::MidiNote(MidiNote {
+                on_off,
+                velocity,
+                ..note
+            });
+            let event = Event {
+                e_type,
+                bar_pos: event.bar_pos + offset,
+                duration: None,
+            };
+            (on_time + offset as f64, base_seq_id, event)
+        };
+        for k in 1..count {
+            self.scheduled_events.push(note_event(false, k));
+            self.scheduled_events.push(note_event(true, k));
+        }
+    }
+
+    /// Send an event of the BaseSeq of the id if any, keeping track of the write failures so that
+    /// no note is left stuck: the note offs of dropped note ons are dropped too, and dropped note
+    /// offs are retried. Events over the cycle budget are deferred to the next cycle.
+    pub fn send_tracked(
+        &mut self,
+        sink: &mut impl MidiSink,
+        monitor: &MidiMonitor,
+        event: &Event,
+        base_seq_id: Option<u32>,
+        note_off_as_velocity_zero: bool,
+    ) {
+        let EventType::MidiNote(note) = event.e_type else {
+            // Only the notes are tracked
+            if !self.defer_over_budget(base_seq_id, event) {
+                let _ = send_event(sink, monitor, event, note_off_as_velocity_zero);
+            }
+            return;
+        };
+        // The note offs of staged note ons follow them, so that they do not get stuck.
+        // Their budget is spent once sent, see [SeqInternal::send_staged_notes].
+        let staged_on = |(_, _, e): &(u32, Option<u32>, Event)| match e.e_type {
+            EventType::MidiNote(n) => {
+                n.on_off && (n.channel, n.pitch) == (note.channel, note.pitch)
+            }
+            _ => false,
+        };
+        if self.polyphony_cap.is_some() && (note.on_off || self.staged_notes.iter().any(staged_on))
+        {
+            self.staged_notes
+                .push((sink.frame(), base_seq_id, event.clone()));
+            return;
+        }
+        if self.defer_over_budget(base_seq_id, event) {
+            return;
+        }
+        if !note.on_off {
+            // Frees the allocated channel, whichever path the note off came from, dropped or not
+            if let Some(id) = base_seq_id {
+                self.release_mpe_voice(id, note.channel, note.pitch);
+            }
+            if let Some(idx) = self
+                .dropped_notes
+                .iter()
+                .position(|n| *n == (note.channel, note.pitch))
+            {
+                self.dropped_notes.swap_remove(idx);
+                return;
+            }
+        }
+        if send_event(sink, monitor, event, note_off_as_velocity_zero).is_err() {
+            monitor.count_dropped();
+            if note.on_off {
+                self.dropped_notes.push((note.channel, note.pitch));
+            } else {
+                self.retry_note_offs.push(event.clone());
+            }
+        }
+    }
+
+    fn release_mpe_voice(&mut self, base_seq_id: u32, channel: u8, pitch: u8) {
+        if let Some(idx) = self
+            .mpe_voices
+            .iter()
+            .position(|v| (v.base_seq_id, v.channel, v.pitch) == (base_seq_id, channel, pitch))
+        {
+            self.mpe_voices.remove(idx);
+        }
+    }
+
+    /// Spend a unit of the cycle budget on the event, deferring it to the next cycle once the
+    /// budget is spent. Returns whether it was deferred.
+    fn defer_over_budget(&mut self, base_seq_id: Option<u32>, event: &Event) -> bool {
+        match self.cycle_budget {
+            Some(0) => {
+                self.deferred_events.push((base_seq_id, event.clone()));
+                true
+            }
+            Some(ref mut budget) => {
+                *budget -= 1;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Send the note ons staged in the cycle under the polyphony cap, the highest velocity ones
+    /// first and the earliest of equal velocities, along with their note offs. The others are
+    /// dropped. Being sent at the end of the cycle, they may be delayed within it, and the ones
+    /// over the cycle budget are deferred to the next one.
+    pub fn send_staged_notes(
+        &mut self,
+        sink: &mut impl MidiSink,
+        monitor: &MidiMonitor,
+        note_off_as_velocity_zero: bool,
+    ) {
+        let Some(cap) = self.polyphony_cap else {
+            return;
+        };
+        let staged = std::mem::take(&mut self.staged_notes);
+        let mut note_ons: Vec<(usize, u8)> = staged
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, (_, _, e))| match e.e_type {
+                EventType::MidiNote(n) if n.on_off => Some((idx, n.velocity)),
+                _ => None,
+            })
+            .collect();
+        note_ons.sort_by_key(|(_, velocity)| u8::MAX - velocity);
+        let kept: Vec<usize> = note_ons
+            .iter()
+            .take(cap as usize)
+            .map(|(idx, _)| *idx)
+            .collect();
+        let mut dropped: Vec<(u8, u8)> = vec![];
+        for (idx, (frame, base_seq_id, event)) in staged.into_iter().enumerate() {
+            let EventType::MidiNote(note) = event.e_type else {
+                continue;
+            };
+            if note.on_off && !kept.contains(&idx) {
+                dropped.push((note.channel, note.pitch));
+                continue;
+            }
+            if let Some(i) = dropped
+                .iter()
+                .position(|n| !note.on_off && *n == (note.channel, note.pitch))
+            {
+                dropped.swap_remove(i);
+                if let Some(id) = base_seq_id {
+                    self.release_mpe_voice(id, note.channel, note.pitch);
+                }
+                continue;
+            }
+            // Deferred note ons are staged again on the next cycle
+            if self.defer_over_budget(base_seq_id, &event) {
+                continue;
+            }
+            sink.set_frame(frame);
+            if send_event(sink, monitor, &event, note_off_as_velocity_zero).is_err() {
+                monitor.count_dropped();
+                if note.on_off {
+                    self.dropped_notes.push((note.channel, note.pitch));
+                } else {
+                    self.retry_note_offs.push(event);
+                }
+            }
+        }
+        // Their note offs to come are dropped on arrival
+        self.dropped_notes.extend(dropped);
+    }
+
+    pub fn set_loop_rotation(&mut self, base_seq_id: u32, amount: f32) {
+        self.loop_rotations.retain(|(id, _)| *id != base_seq_id);
+        if amount > 0. {
+            self.loop_rotations.push((base_seq_id, amount));
+        }
+    }
+
+    /// Position of an event of a BaseSeq with a loop rotation jitter, see [rotate_pos].
+    /// Each loop is rotated by its own random offset, derived from the seed and the loop count.
+    /// The event is placed in the loop of the window start, or in the next one if it already
+    /// went by, so that changing offsets at the loop boundary never skips nor replays events.
+    pub fn rotated_pos(&self, bar_pos: f32, base_seq_id: u32, seed: u64, loop_len: f32) -> f32 {
+        let Some((_, amount)) = self
+            .loop_rotations
+            .iter()
+            .find(|(id, _)| *id == base_seq_id)
+        else {
+            return bar_pos;
+        };
+        let loop_offset =
+            |loop_count| rotation_offset(*amount, seed, base_seq_id, loop_count, loop_len);
+        let loop_count = (self.j_window_time_start / loop_len as f64) as u64;
+        let pos = rotate_pos(bar_pos, loop_offset(loop_count), loop_len);
+        let win_start_looped = (self.j_window_time_start % loop_len as f64) as f32;
+        if pos >= win_start_looped {
+            pos
+        } else {
+            rotate_pos(bar_pos, loop_offset(loop_count + 1), loop_len)
+        }
+    }
+
+    /// Position of an event of a BaseSeq in the given loop, with its loop rotation jitter
+    pub fn rotated_pos_in_loop(
+        &self,
+        bar_pos: f32,
+        base_seq_id: u32,
+        seed: u64,
+        loop_len: f32,
+        loop_count: u64,
+    ) -> f32 {
+        match self
+            .loop_rotations
+            .iter()
+            .find(|(id, _)| *id == base_seq_id)
+        {
+            Some((_, amount)) => rotate_pos(
+                bar_pos,
+                rotation_offset(*amount, seed, base_seq_id, loop_count, loop_len),
+                loop_len,
+            ),
+            None => bar_pos,
+        }
+    }
+
+    pub fn add_duck(&mut self, trigger_id: u32, target_id: u32, amount: f32, release: f32) {
+        self.ducks
+            .retain(|d| d.trigger_id != trigger_id || d.target_id != target_id);
+        self.ducks.push(Duck {
+            trigger_id,
+            target_id,
+            amount,
+            release,
+            last_trigger: None,
+        });
+    }
+
+    /// Restart the envelopes of the ducks triggered by the BaseSeq
+    pub fn trigger_ducks(&mut self, base_seq_id: u32, time: f64) {
+        for duck in self
+            .ducks
+            .iter_mut()
+            .filter(|d| d.trigger_id == base_seq_id)
+        {
+            duck.last_trigger = Some(time);
+        }
+    }
+
+    /// Velocity gain of the BaseSeq at the given time, the deepest of its ducks
+    pub fn duck_gain(&self, base_seq_id: u32, time: f64) -> f32 {
+        self.ducks
+            .iter()
+            .filter(|d| d.target_id == base_seq_id)
+            .filter_map(|d| {
+                let elapsed = (time - d.last_trigger?) as f32;
+                (0. ..d.release)
+                    .contains(&elapsed)
+                    .then(|| 1. - d.amount * (1. - elapsed / d.release))
+            })
+            .fold(1., f32::min)
+    }
+
+    /// Request a stutter of a BaseSeq, replaying the slice of given length from the current
+    /// position the given nb of times
+    pub fn add_stutter(&mut self, base_seq_id: u32, slice_len: f32, repeats: u32) {
+        self.stutters.retain(|s| s.base_seq_id != base_seq_id);
+        self.stutters.push(Stutter {
+            base_seq_id,
+            slice_len,
+            repeats,
+            start: None,
+        });
+    }
+
+    /// State of the stutter of a BaseSeq for the current window, None if it is not stuttering
+    pub fn stutter_cycle(&mut self, base_seq_id: u32, loop_len: f32) -> Option<StutterCycle> {
+        let win_start = self.j_window_time_start;
+        let win_len = self.j_window_time_end - self.j_window_time_start;
+        let idx = self
+            .stutters
+            .iter()
+            .position(|s| s.base_seq_id == base_seq_id)?;
+        let stutter = &mut self.stutters[idx];
+        let (start_time, slice_start) = *stutter
+            .start
+            .get_or_insert((win_start, win_start % loop_len as f64));
+        let slice_len = stutter.slice_len as f64;
+        if win_start >= start_time + slice_len * stutter.repeats as f64 {
+            self.stutters.remove(idx);
+            return Some(StutterCycle::Ended);
+        }
+        let from = (win_start - start_time) % slice_len;
+        Some(StutterCycle::Replay {
+            slice_start,
+            slice_len,
+            from,
+            to: from + win_len,
+        })
+    }
+
+    /// Allocate a channel of the range to a note on of the BaseSeq, round robin over the channels
+    /// free of its notes. When they are all taken, the channel of its oldest note is stolen,
+    /// which is returned to be sent a note off. None if there is no channel to steal either.
+    pub fn allocate_mpe_channel(
+        &mut self,
+        base_seq_id: u32,
+        (low, high): (u8, u8),
+        pitch: u8,
+    ) -> Option<(u8, Option<MpeVoice>)> {
+        let nb_channels = high - low + 1;
+        let cursor = self
+            .mpe_cursors
+            .iter()
+            .find(|(id, _)| *id == base_seq_id)
+            .map_or(low, |(_, ch)| (*ch).clamp(low, high));
+        let free_channel = (0..nb_channels)
+            .map(|k| low + (cursor - low + k) % nb_channels)
+            .find(|ch| {
+                !self
+                    .mpe_voices
+                    .iter()
+                    .any(|v| v.base_seq_id == base_seq_id && v.channel == *ch)
+            });
+        let (channel, stolen) = match free_channel {
+            Some(channel) => (channel, None),
+            None => {
+                let idx = self.mpe_voices.iter().position(|v| {
+                    v.base_seq_id == base_seq_id && (low..=high).contains(&v.channel)
+                })?;
+                let stolen = self.mpe_voices.remove(idx);
+                (stolen.channel, Some(stolen))
+            }
+        };
+        self.mpe_cursors.retain(|(id, _)| *id != base_seq_id);
+        self.mpe_cursors
+            .push((base_seq_id, low + (channel - low + 1) % nb_channels));
+        self.mpe_voices.push(MpeVoice {
+            base_seq_id,
+            pitch,
+            channel,
+        });
+        Some((channel, stolen))
+    }
+
+    /// Channel allocated to the oldest sounding note of the BaseSeq with the pitch, if any
+    pub fn mpe_channel(&self, base_seq_id: u32, pitch: u8) -> Option<u8> {
+        self.mpe_voices
+            .iter()
+            .find(|v| v.base_seq_id == base_seq_id && v.pitch == pitch)
+            .map(|v| v.channel)
+    }
+
+    /// Channel to which the events of the BaseSeq are re-routed at the given time, if any
+    pub fn routed_channel(&self, base_seq_id: u32, time: f64) -> Option<u8> {
+        self.route_overrides
+            .iter()
+            .find(|r| r.base_seq_id == base_seq_id && time < r.end)
+            .map(|r| r.channel)
+    }
+
+    /// Drop the route overrides that ended before the current window, flushing their channels
+    pub fn expire_route_overrides(&mut self) {
+        let win_start = self.j_window_time_start;
+        let (expired, route_overrides) = std::mem::take(&mut self.route_overrides)
+            .into_iter()
+            .partition::<Vec<_>, _>(|r| r.end <= win_start);
+        self.route_overrides = route_overrides;
+        for expired in expired {
+            if !self.channel_flushes.contains(&expired.channel) {
+                self.channel_flushes.push(expired.channel);
             }
         }
-        params.root_note = target_root_note;
-        Ok(())
     }
 
-    pub fn incr_event_head(&self) {
-        let curr_event_head = *self.event_head.read();
-        *self.event_head.write() = (curr_event_head + 1) % self.event_buffer.read().len();
+    pub fn event_in_cycle(&self, event_time: f64, loop_len: f32) -> bool {
+        let win_start_looped = self.j_window_time_start % (loop_len as f64);
+        let win_end_looped = self.j_window_time_end % (loop_len as f64);
+        if win_start_looped < win_end_looped {
+            win_start_looped <= event_time && event_time < win_end_looped
+        } else {
+            // EventBuffer wrapping case
+            println!("Wrapping EventBuffer..");
+            win_start_looped <= event_time || event_time < win_end_looped
+        }
     }
+}
 
-    //TODO to be used in when inserting evnets to increase nb_events without regen
-    // /// The input events need to be sorted by bar_pos
-    // pub fn insert_events(&self, events: Vec<Event>) {
-    //     let mut event_buffer_mut = self.event_buffer.write();
-    //     let mut buff_idx = 0;
-    //     for e in events {
-    //         while buff_idx < event_buffer_mut.len() {
-    //             if event_buffer_mut[buff_idx].bar_pos < e.bar_pos {
-    //                 buff_idx += 1;
-    //             } else {
-    //                 break;
-    //             }
-    //         }
-    //         event_buffer_mut.insert(buff_idx, e.clone());
-    //         buff_idx += 1;
-    //     }
-    // }
+#[test]
+fn test_edit_event() {
+    let params = BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, true, true, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    };
+    let base_seq =
+        BaseSeq::new_fill(params, 0, 0, DEFAULT_TICKS_PER_BAR, &SeqInternal::new()).unwrap();
+    let seq_int = SeqInternal::new();
+    base_seq.edit_event(0, 2.6, 42, &seq_int).unwrap();
+
+    let events = base_seq.event_buffer.read();
+    assert_eq!(events.len(), 8);
+    assert!(events.windows(2).all(|w| w[0].bar_pos <= w[1].bar_pos));
+    let moved: Vec<&Event> = events
+        .iter()
+        .filter(|e| matches!(e.e_type, EventType::MidiNote(MidiNote { velocity: 42, .. })))
+        .collect();
+    assert_eq!(moved.len(), 2);
+    assert!(moved[0].is_note_on() && moved[0].bar_pos == 2.6);
+    assert!(!moved[1].is_note_on() && (moved[1].bar_pos - 2.7).abs() < 1e-5);
+    drop(events);
+
+    assert!(base_seq.edit_event(4, 0.1, 100, &seq_int).is_err());
+    assert!(base_seq.edit_event(0, 4.5, 100, &seq_int).is_err());
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct RandomBase {
-    pub nb_events: u32,
+#[test]
+fn test_harmony_follows_source() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, true, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.add_harmony(0, 4, 2).unwrap();
+
+    let harmony_notes = |seq: &Sequencer| -> Vec<MidiNote> {
+        let follower = seq.get_base_seq(1).unwrap();
+        let events = follower.event_buffer.read();
+        events
+            .iter()
+            .filter_map(|e| match e.e_type {
+                EventType::MidiNote(note) => Some(note),
+                EventType::PitchBend(_) | EventType::_Fill => None,
+            })
+            .collect()
+    };
+    let notes = harmony_notes(&seq);
+    assert_eq!(notes.len(), 4);
+    assert!(notes.iter().all(|n| n.pitch == 64 && n.channel == 2));
+
+    // Regenerating the source updates the follower
+    seq.toggle_step(0, 1).unwrap();
+    assert_eq!(harmony_notes(&seq).len(), 6);
+
+    seq.remove_base_seq(1).unwrap();
+    assert!(seq.get_base_seq(0).unwrap().followers.read().is_empty());
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct EuclidBase {
-    pub pulses: u32,
-    pub steps: u32,
+#[test]
+fn test_record_to_custom_base_seq() {
+    let seq = Sequencer::new(120.);
+    let note = |on_off| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 3,
+            pitch: 64,
+            velocity: 90,
+        }),
+        bar_pos: 0.,
+        duration: None,
+    };
+    assert!(seq.record_stop(2.).is_err());
+
+    seq.internal.write().j_window_time_end = 5.;
+    seq.record_start();
+    seq.record(&note(true), 5.5);
+    // Drained by the OSC process while recording
+    seq.drain_record_queue();
+    seq.record(&note(false), 6.25);
+    // The recording is kept on an invalid loop length
+    assert!(seq.record_stop(0.).is_err());
+    assert!(seq.record_stop(-2.).is_err());
+    seq.record_stop(2.).unwrap();
+    seq.record(&note(true), 8.);
+    assert!(unsafe { seq.record_queue.pop() }.is_none());
+
+    let base_seq = seq.get_base_seq(0).unwrap();
+    assert_eq!(base_seq.params.read().midi_ch, 3);
+    let events = base_seq.event_buffer.read();
+    assert_eq!(events.len(), 2);
+    assert!(events[0].is_note_on() && events[0].bar_pos == 0.5);
+    assert!(!events[1].is_note_on() && events[1].bar_pos == 1.25);
 }
 
-//////////////////////////////////////////////////////////////////////////
-/// Effect Event processor
+#[test]
+fn test_add_base_seq_lock_hold() {
+    use std::time::{Duration, Instant};
 
-pub struct FxProcessor {
-    rng: Arc<RwLock<StdRng>>,
-    distr: Normal<f64>,
-    // processor: Box<dyn Fn(Event) -> Event>,
-    /// Unique identifier to the FxProcessors
-    pub id: u32,
+    let seq = Arc::new(Sequencer::new(120.));
+    // A long recording, whose events are snapped before taking the locks
+    let nb_notes = 100_000;
+    let events: Vec<Event> = (0..2 * nb_notes)
+        .map(|k| Event {
+            e_type: EventType::MidiNote(MidiNote {
+                on_off: k % 2 == 0,
+                channel: 1,
+                pitch: 60,
+                velocity: 100,
+            }),
+            bar_pos: (k / 2) as f32 * 4. / nb_notes as f32 + (k % 2) as f32 * 1e-5,
+            duration: None,
+        })
+        .collect();
+    let params = BaseSeqParams {
+        ty: Custom,
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.,
+        note_len_div: 0.,
+        velocity_avg: 0,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    };
+    let adder = {
+        let seq = seq.clone();
+        std::thread::spawn(move || seq.add_custom_base_seq(params, events))
+    };
+    // The locks taken by the jack process at each cycle are only held for the insertion, a
+    // small part of the time taken by the adder
+    let adding = Instant::now();
+    let mut max_wait = Duration::ZERO;
+    while !adder.is_finished() {
+        let start = Instant::now();
+        drop(seq.params.read());
+        drop(seq.base_seqs.read());
+        max_wait = max_wait.max(start.elapsed());
+    }
+    let added = adding.elapsed();
+    adder.join().unwrap().unwrap();
+    assert_eq!(
+        seq.get_base_seq(0).unwrap().event_buffer.read().len(),
+        2 * nb_notes
+    );
+    assert!(max_wait < added / 4);
 }
 
-impl FxProcessor {
-    fn new(id: u32) -> Self {
-        let rng = Arc::new(RwLock::new(rand::rngs::StdRng::from_entropy()));
-        let distr = Normal::new(0., 1.).unwrap();
-        // let processor = Box::new(|e| -> return e);
-        FxProcessor {
-            rng,
-            distr,
-            // processor,
+#[test]
+fn test_duck_gain() {
+    let mut seq_int = SeqInternal::new();
+    seq_int.add_duck(0, 1, 0.5, 2.);
+    assert_eq!(seq_int.duck_gain(1, 3.), 1.);
+    seq_int.trigger_ducks(0, 4.);
+    assert_eq!(seq_int.duck_gain(1, 4.), 0.5);
+    assert_eq!(seq_int.duck_gain(1, 5.), 0.75);
+    assert_eq!(seq_int.duck_gain(1, 6.), 1.);
+    // Only the target is ducked
+    assert_eq!(seq_int.duck_gain(0, 4.), 1.);
+}
+
+#[test]
+fn test_gain() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, false, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    assert!(seq.set_gain(0, -1., 0).is_err());
+    seq.set_gain(0, 0.5, 10).unwrap();
+    let gain = *seq.get_base_seq(0).unwrap().gain.read();
+    // Scaled, then offset
+    assert_eq!(gain.apply(100), 60);
+    assert_eq!(Gain::default().apply(100), 100);
+    // Clamped at both ends
+    assert_eq!(
+        Gain {
+            scale: 2.,
+            offset: 0
+        }
+        .apply(100),
+        127
+    );
+    assert_eq!(
+        Gain {
+            scale: 1.,
+            offset: -50
+        }
+        .apply(20),
+        1
+    );
+    assert_eq!(
+        Gain {
+            scale: 0.,
+            offset: 0
+        }
+        .apply(100),
+        1
+    );
+}
+
+#[test]
+fn test_auto_fill() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, false, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.save_scene(0);
+    assert!(seq.set_auto_fill(0, -1.).is_err());
+    assert!(seq.set_auto_fill(1, 1.).is_err());
+    seq.set_auto_fill(0, 1.).unwrap();
+    // Only played before a scene change
+    assert!(!seq.in_auto_fill(0, 3.5, 4));
+    seq.recall_scene(0, true).unwrap();
+    assert!(seq.in_auto_fill(0, 3.5, 4));
+    assert!(seq.in_auto_fill(0, 7., 4));
+    assert!(!seq.in_auto_fill(0, 2.5, 4));
+    seq.set_auto_fill(0, 0.).unwrap();
+    assert!(!seq.in_auto_fill(0, 3.5, 4));
+}
+
+#[test]
+fn test_ensure_base_seq() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, false, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    assert!(seq
+        .ensure_base_seq(
+            0,
+            BaseSeqParams {
+                ty: Steps(StepsBase {
+                    pattern: vec![true; 4]
+                }),
+                loop_length: 4.,
+                root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+                note_len_avg: 0.1,
+                note_len_div: 0.,
+                velocity_avg: 100,
+                velocity_div: 0.,
+                midi_ch: 1,
+                pitch_pool: None,
+                explicit_durations: false,
+                legato: false,
+                velocity_contour: None,
+                doublings: vec![],
+                gate_ratio: None,
+                vel_len_coupling: 0.,
+                grid_velocity: None,
+                note_off_velocity: None,
+                wrap_note_off: true,
+            }
+        )
+        .is_err());
+    let id = CALLER_ID_START + 3;
+    assert!(seq
+        .ensure_base_seq(
+            id,
+            BaseSeqParams {
+                ty: Steps(StepsBase {
+                    pattern: vec![true, false, false, false]
+                }),
+                loop_length: 4.,
+                root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+                note_len_avg: 0.1,
+                note_len_div: 0.,
+                velocity_avg: 100,
+                velocity_div: 0.,
+                midi_ch: 1,
+                pitch_pool: None,
+                explicit_durations: false,
+                legato: false,
+                velocity_contour: None,
+                doublings: vec![],
+                gate_ratio: None,
+                vel_len_coupling: 0.,
+                grid_velocity: None,
+                note_off_velocity: None,
+                wrap_note_off: true,
+            }
+        )
+        .unwrap());
+    // Run again, the params are updated in place
+    assert!(!seq
+        .ensure_base_seq(
+            id,
+            BaseSeqParams {
+                ty: Steps(StepsBase {
+                    pattern: vec![true, true, false, false]
+                }),
+                loop_length: 4.,
+                root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+                note_len_avg: 0.1,
+                note_len_div: 0.,
+                velocity_avg: 100,
+                velocity_div: 0.,
+                midi_ch: 1,
+                pitch_pool: None,
+                explicit_durations: false,
+                legato: false,
+                velocity_contour: None,
+                doublings: vec![],
+                gate_ratio: None,
+                vel_len_coupling: 0.,
+                grid_velocity: None,
+                note_off_velocity: None,
+                wrap_note_off: true,
+            }
+        )
+        .unwrap());
+    assert_eq!(seq.base_seqs.read().len(), 2);
+    let nb_note_ons = |id| {
+        let base_seq = seq.get_base_seq(id).unwrap();
+        let nb = base_seq
+            .event_buffer
+            .read()
+            .iter()
+            .filter(|e| e.is_note_on())
+            .count();
+        nb
+    };
+    assert_eq!(nb_note_ons(id), 2);
+    // But not its type
+    let mut custom_params = BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true; 4],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    };
+    custom_params.ty = Custom;
+    assert!(seq.ensure_base_seq(id, custom_params).is_err());
+    assert!(matches!(
+        seq.get_base_seq(id).unwrap().params.read().ty,
+        Steps(_)
+    ));
+    assert_eq!(nb_note_ons(id), 2);
+    // The auto assigned ids go on from where they were
+    seq.save_scene(0);
+    seq.recall_scene(0, false).unwrap();
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, false, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    assert!(seq.get_base_seq(1).is_ok());
+    // Up to the caller chosen ones, fx processors included
+    seq.params.write().incr = CALLER_ID_START - 1;
+    seq.add_fx_processor(1, FxKind::Transpose { semitones: 12 })
+        .unwrap();
+    assert!(seq
+        .add_base_seq(BaseSeqParams {
+            ty: Steps(StepsBase {
+                pattern: vec![true, false, false, false]
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        })
+        .is_err());
+    assert!(seq
+        .add_fx_processor(1, FxKind::Transpose { semitones: 12 })
+        .is_err());
+    assert_eq!(seq.base_seqs.read().len(), 3);
+}
+
+#[test]
+fn test_fx_transpose() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, false, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.add_fx_processor(0, FxKind::Transpose { semitones: 12 })
+        .unwrap();
+    seq.add_fx_processor(0, FxKind::Transpose { semitones: 60 })
+        .unwrap();
+    let note = |pitch| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off: true,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos: 0.,
+        duration: None,
+    };
+    let processed_pitch = |pitch| {
+        let mut event = note(pitch);
+        seq.process_event(&seq.get_base_seq(0).unwrap(), &mut event);
+        match event.e_type {
+            EventType::MidiNote(note) => note.pitch,
+            _ => unreachable!(),
+        }
+    };
+    // Applied in order, clamped to the midi pitches
+    assert_eq!(processed_pitch(40), 112);
+    assert_eq!(processed_pitch(60), 127);
+    seq.set_fx_enabled(2, false).unwrap();
+    assert_eq!(processed_pitch(60), 72);
+    // Left untouched in the buffer
+    let base_seq = seq.get_base_seq(0).unwrap();
+    assert!(base_seq.event_buffer.read().iter().all(|e| match e.e_type {
+        EventType::MidiNote(note) => note.pitch == 60,
+        _ => true,
+    }));
+}
+
+#[test]
+fn test_add_velocity() {
+    let params = BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, true, false, true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    };
+    let seq_int = SeqInternal::new();
+    let base_seq = BaseSeq::new_fill(params, 0, 0, DEFAULT_TICKS_PER_BAR, &seq_int).unwrap();
+    let on_velocities = |base_seq: &BaseSeq| -> Vec<u8> {
+        base_seq
+            .event_buffer
+            .read()
+            .iter()
+            .filter_map(|e| match e.e_type {
+                EventType::MidiNote(MidiNote {
+                    on_off: true,
+                    velocity,
+                    ..
+                }) => Some(velocity),
+                _ => None,
+            })
+            .collect()
+    };
+
+    base_seq.add_velocity(200, &seq_int);
+    assert_eq!(on_velocities(&base_seq), vec![127; 3]);
+    base_seq.add_velocity(-200, &seq_int);
+    assert_eq!(on_velocities(&base_seq), vec![1; 3]);
+}
+
+#[test]
+fn test_playback_rng_per_base_seq() {
+    let seq_int = SeqInternal::new();
+    let rolls = |id| {
+        let base_seq = BaseSeq::new_fill(
+            BaseSeqParams {
+                ty: Steps(StepsBase {
+                    pattern: vec![true],
+                }),
+                loop_length: 4.,
+                root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+                note_len_avg: 0.1,
+                note_len_div: 0.,
+                velocity_avg: 100,
+                velocity_div: 0.,
+                midi_ch: 1,
+                pitch_pool: None,
+                explicit_durations: false,
+                legato: false,
+                velocity_contour: None,
+                doublings: vec![],
+                gate_ratio: None,
+                vel_len_coupling: 0.,
+                grid_velocity: None,
+                note_off_velocity: None,
+                wrap_note_off: true,
+            },
             id,
+            42,
+            DEFAULT_TICKS_PER_BAR,
+            &seq_int,
+        )
+        .unwrap();
+        (0..32).map(|_| base_seq.roll(0.5)).collect::<Vec<bool>>()
+    };
+    assert_eq!(rolls(0), rolls(0));
+    assert_ne!(rolls(0), rolls(1));
+
+    // Seeded the same when added to a sequencer, which assigns the id after the generation
+    let seq = Sequencer::new(120.);
+    seq.set_master_seed(42);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let added = seq.get_base_seq(1).unwrap().clone();
+    let added_rolls: Vec<bool> = (0..32).map(|_| added.roll(0.5)).collect();
+    assert_eq!(added_rolls, rolls(1));
+}
+
+/// Sink with a fixed nb of free slots, as a full jack output buffer
+#[cfg(test)]
+struct TinySink {
+    free_slots: usize,
+    written: Vec<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl MidiSink for TinySink {
+    fn write(&mut self, raw: &[u8]) -> anyhow::Result<()> {
+        if self.free_slots == 0 {
+            bail!("Buffer full");
         }
+        self.free_slots -= 1;
+        self.written.push(raw.to_vec());
+        Ok(())
     }
+}
 
-    pub(crate) fn process(&self, event: &mut Event) {
-        match event.e_type {
-            EventType::MidiNote(ref mut note) => {
-                let rng_guard = &mut *self.rng.write();
-                note.pitch = (note.pitch as f64 + self.distr.sample(rng_guard)) as u8;
-            }
-            EventType::_Fill => todo!(),
-        };
+#[test]
+fn test_send_tracked_full_buffer() {
+    let note = |on_off, pitch| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos: 0.,
+        duration: None,
+    };
+    let monitor = MidiMonitor::default();
+    let mut seq_int = SeqInternal::new();
+    let mut sink = TinySink {
+        free_slots: 1,
+        written: vec![],
+    };
+
+    // The note on of 61 is dropped, so is its note off
+    seq_int.send_tracked(&mut sink, &monitor, &note(true, 60), None, false);
+    seq_int.send_tracked(&mut sink, &monitor, &note(true, 61), None, false);
+    sink.free_slots = 1;
+    seq_int.send_tracked(&mut sink, &monitor, &note(false, 61), None, false);
+    assert!(seq_int.dropped_notes.is_empty());
+
+    // The note off of 60 is kept for a retry
+    sink.free_slots = 0;
+    seq_int.send_tracked(&mut sink, &monitor, &note(false, 60), None, false);
+    assert_eq!(seq_int.retry_note_offs.len(), 1);
+    assert_eq!(sink.written, vec![vec![0x90, 60, 100]]);
+}
+
+#[test]
+fn test_lfo() {
+    let shapes = [
+        LfoShape::Sine,
+        LfoShape::Tri,
+        LfoShape::Saw,
+        LfoShape::Square,
+    ];
+    for shape in shapes {
+        assert!(shape.value(0.25) > 0., "{shape:?}");
+        assert!((0..100).all(|i| shape.value(i as f32 / 100.).abs() <= 1.));
     }
+    let lfo = |target| Lfo {
+        shape: LfoShape::Tri,
+        period: 4.,
+        depth: 10.,
+        target,
+    };
+    let seq = Sequencer::new(120.);
+    seq.add_lfo(lfo(LfoTarget::Bpm)).unwrap();
+    seq.add_lfo(lfo(LfoTarget::Bpm)).unwrap();
+    seq.add_lfo(lfo(LfoTarget::Cc {
+        channel: 1,
+        controller: 7,
+    }))
+    .unwrap();
+    assert_eq!(seq.lfo_bpm(120., 0.), 120.);
+    assert_eq!(seq.lfo_bpm(120., 1.), 140.);
+    assert_eq!(seq.lfo_bpm(120., 3.), 100.);
+
+    let mut sink: Vec<Vec<u8>> = vec![];
+    let mut seq_int = SeqInternal::new();
+    seq.send_lfo_ccs(&mut seq_int, &mut sink, 1.);
+    seq.send_lfo_ccs(&mut seq_int, &mut sink, 1.);
+    assert_eq!(sink, vec![vec![0xB0, 7, 74]]);
 }
 
-//////////////////////////////////////////////////////////////////////////
-/// Internal Sequencer state
+#[test]
+fn test_randomize_base_seq() {
+    let randomized_params = || {
+        let seq = Sequencer::new(120.);
+        seq.set_master_seed(7);
+        seq.add_base_seq(BaseSeqParams {
+            ty: Euclid(EuclidBase {
+                pulses: 2,
+                steps: 4,
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        })
+        .unwrap();
+        seq.randomize_base_seq(0).unwrap();
+        let params = seq.get_base_seq(0).unwrap().params.read().clone();
+        params
+    };
+    let params = randomized_params();
+    let Euclid(EuclidBase { pulses, steps }) = params.ty else {
+        panic!("The base seq type changed");
+    };
+    // Only 1, 2 and 4 steps divide the loop
+    assert!([1, 2, 4].contains(&steps) && pulses <= steps);
+    assert!((40..=120).contains(&params.velocity_avg));
+    assert!((36..=84).contains(&note_to_midi_pitch(&params.root_note)));
 
-/// Additional SeqParams, only to be set and read by the jack Cycle
-pub struct SeqInternal {
-    /// Allows for cycle skipping when on pause/stop.
-    /// Indicates, when stopping, if we are on the final cycle before silence.
-    /// Only noteOff events are allowed on final cycle.
-    pub status: SeqInternalStatus,
-    /// Position of current jack cycle in time
-    /// In bars. To be reset on loop or start/stop
-    pub j_window_time_start: f64,
-    /// Position of current jack cycle in time
-    /// In bars. To be reset on loop or start/stop
-    pub j_window_time_end: f64,
-    /// Current bar position in loop rhythm grid.
-    /// Stored here for logging purposes
-    pub curr_bar: u32,
+    let same_params = randomized_params();
+    assert_eq!(params.velocity_avg, same_params.velocity_avg);
+    assert_eq!(params.note_len_avg, same_params.note_len_avg);
 }
 
-#[derive(PartialEq, Eq)]
-pub enum SeqInternalStatus {
-    Silence,
-    Playing,
+#[test]
+fn test_set_euclid() {
+    let seq_int = SeqInternal::new();
+    let euclid_seq = BaseSeq::new_fill(
+        BaseSeqParams {
+            ty: Euclid(EuclidBase {
+                pulses: 2,
+                steps: 4,
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        },
+        0,
+        0,
+        DEFAULT_TICKS_PER_BAR,
+        &seq_int,
+    )
+    .unwrap();
+    let nb_note_ons = || {
+        euclid_seq
+            .event_buffer
+            .read()
+            .iter()
+            .filter(|e| e.is_note_on())
+            .count()
+    };
+
+    euclid_seq.set_euclid(Some(4), None, &seq_int).unwrap();
+    assert_eq!(nb_note_ons(), 4);
+    euclid_seq.set_euclid(Some(0), None, &seq_int).unwrap();
+    assert_eq!(nb_note_ons(), 0);
+    assert!(euclid_seq.set_euclid(Some(5), None, &seq_int).is_err());
+    assert!(euclid_seq.set_euclid(None, Some(0), &seq_int).is_err());
+    assert!(euclid_seq.set_euclid(None, Some(3), &seq_int).is_err());
+    euclid_seq.set_euclid(Some(1), Some(1), &seq_int).unwrap();
+    assert_eq!(nb_note_ons(), 1);
+    // Lowering the steps below the pulses is refused
+    euclid_seq.set_euclid(Some(2), Some(2), &seq_int).unwrap();
+    assert!(euclid_seq.set_euclid(None, Some(1), &seq_int).is_err());
+
+    let steps_seq = BaseSeq::new_fill(
+        BaseSeqParams {
+            ty: Steps(StepsBase {
+                pattern: vec![true],
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        },
+        1,
+        0,
+        DEFAULT_TICKS_PER_BAR,
+        &seq_int,
+    )
+    .unwrap();
+    assert!(steps_seq.set_euclid(Some(1), None, &seq_int).is_err());
 }
 
-impl SeqInternal {
-    pub fn new() -> Self {
-        SeqInternal {
-            status: SeqInternalStatus::Silence,
-            j_window_time_start: 0.,
-            j_window_time_end: 0.,
-            curr_bar: 0,
-        }
+#[test]
+fn test_set_bpm_clamp() {
+    let seq = Sequencer::new(120.);
+    assert_eq!(seq.set_bpm(128.5).unwrap(), 128.5);
+    assert_eq!(
+        seq.set_bpm(DEFAULT_BPM_RANGE.0).unwrap(),
+        DEFAULT_BPM_RANGE.0
+    );
+    assert_eq!(
+        seq.set_bpm(DEFAULT_BPM_RANGE.0 - 0.5).unwrap(),
+        DEFAULT_BPM_RANGE.0
+    );
+    assert_eq!(
+        seq.set_bpm(DEFAULT_BPM_RANGE.1).unwrap(),
+        DEFAULT_BPM_RANGE.1
+    );
+    assert_eq!(
+        seq.set_bpm(DEFAULT_BPM_RANGE.1 + 0.5).unwrap(),
+        DEFAULT_BPM_RANGE.1
+    );
+    assert_eq!(seq.set_bpm(-10.).unwrap(), DEFAULT_BPM_RANGE.0);
+
+    seq.set_bpm_range(60., 90.).unwrap();
+    assert_eq!(seq.params.read().bpm, 60.);
+    assert!(seq.set_bpm_range(90., 60.).is_err());
+    assert!(seq.set_bpm_range(0., 60.).is_err());
+}
+
+#[test]
+fn test_rotated_pos() {
+    let mut seq_int = SeqInternal::new();
+    assert_eq!(seq_int.rotated_pos(1., 0, 42, 4.), 1.);
+    seq_int.set_loop_rotation(0, 0.5);
+
+    // Positions are rotated into the loop of the window start, if not already gone by
+    seq_int.j_window_time_start = 4.;
+    seq_int.j_window_time_end = 4.01;
+    let pos = seq_int.rotated_pos(3., 0, 42, 4.);
+    assert!((3. ..4.).contains(&pos));
+    // Otherwise into the next loop, with its own offset
+    seq_int.j_window_time_start = 7.99;
+    seq_int.j_window_time_end = 8.;
+    let next_loop_pos = seq_int.rotated_pos(0., 0, 42, 4.);
+    assert!((0. ..2.).contains(&next_loop_pos));
+}
+
+#[test]
+fn test_encoder_curve() {
+    let linear = EncoderCurve {
+        shape: EncoderShape::Linear,
+        gain: 0.5,
+    };
+    let quadratic = EncoderCurve::default();
+    for (delta, linear_change, quadratic_change) in [
+        (1, 0.5, 0.01),
+        (-1, -0.5, -0.01),
+        (10, 5., 1.),
+        (-20, -10., -4.),
+    ] {
+        assert_eq!(linear.apply(delta), linear_change);
+        assert!((quadratic.apply(delta) - quadratic_change).abs() < 1e-5);
     }
+    assert!(Sequencer::new(120.)
+        .set_encoder_curve(EncoderCurve { gain: 0., ..linear })
+        .is_err());
+}
 
-    pub fn event_in_cycle(&self, event_time: f64, loop_len: f32) -> bool {
-        let win_start_looped = self.j_window_time_start % (loop_len as f64);
-        let win_end_looped = self.j_window_time_end % (loop_len as f64);
-        if win_start_looped < win_end_looped {
-            win_start_looped <= event_time && event_time < win_end_looped
-        } else {
-            // EventBuffer wrapping case
-            println!("Wrapping EventBuffer..");
-            win_start_looped <= event_time || event_time < win_end_looped
-        }
+#[test]
+fn test_velocity_curve() {
+    assert_eq!(VelocityCurve::Linear.apply(64), 64);
+    assert_eq!(VelocityCurve::Linear.apply(0), 1);
+    assert_eq!(VelocityCurve::Exp.apply(127), 127);
+    assert_eq!(VelocityCurve::Exp.apply(64), 32);
+    assert_eq!(VelocityCurve::Log.apply(32), 64);
+    assert_eq!(VelocityCurve::Gamma(3.).apply(1), 1);
+
+    let seq = Sequencer::new(120.);
+    assert!(seq.set_velocity_curve(VelocityCurve::Gamma(0.)).is_err());
+    seq.set_velocity_curve(VelocityCurve::Gamma(1.5)).unwrap();
+    assert_eq!(seq.params.read().velocity_curve, VelocityCurve::Gamma(1.5));
+}
+
+#[test]
+fn test_mute_region() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let base_seq_id = 0;
+    let base_seq = seq.get_base_seq(base_seq_id).unwrap().clone();
+    seq.mute_region(base_seq_id, 1., 2., true).unwrap();
+    assert!(!base_seq.is_muted_at(0.5));
+    assert!(base_seq.is_muted_at(1.));
+    assert!(!base_seq.is_muted_at(2.));
+
+    // Wrapping around the loop
+    seq.mute_region(base_seq_id, 3.5, 0.5, true).unwrap();
+    assert!(base_seq.is_muted_at(3.75));
+    assert!(base_seq.is_muted_at(0.25));
+    assert!(!base_seq.is_muted_at(3.));
+
+    seq.mute_region(base_seq_id, 1., 2., false).unwrap();
+    assert!(!base_seq.is_muted_at(1.5));
+    assert!(seq.mute_region(base_seq_id, 1., 1., true).is_err());
+}
+
+#[test]
+fn test_render_window() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, true, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    // A bar is half a second at 120 bpm
+    let frames_per_bar = DEFAULT_SAMPLE_RATE / 2;
+    let frames = |rendered: Vec<(u32, [u8; 3])>| -> Vec<(u32, bool)> {
+        rendered
+            .iter()
+            .map(|(frame, bytes)| (frame / (frames_per_bar / 10), bytes[0] == 0x90))
+            .collect()
+    };
+    assert_eq!(
+        frames(seq.render_window(0., 4.).unwrap()),
+        vec![(0, true), (1, false), (20, true), (21, false)]
+    );
+    // Across the loop end
+    assert_eq!(
+        frames(seq.render_window(3.5, 6.).unwrap()),
+        vec![(5, true), (6, false)]
+    );
+
+    seq.mute_region(0, 0., 1., true).unwrap();
+    assert_eq!(
+        frames(seq.render_window(0., 4.).unwrap()),
+        vec![(1, false), (20, true), (21, false)]
+    );
+    assert!(seq.render_window(2., 1.).is_err());
+    // Nothing was played
+    assert!(seq.internal.read().pending_note_offs.is_empty());
+}
+
+#[test]
+fn test_metrics() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, true, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let mut sink = TinySink {
+        free_slots: 1,
+        written: vec![],
+    };
+    let note_on = |pitch| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off: true,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos: 0.,
+        duration: None,
+    };
+    let mut seq_int = seq.internal.write();
+    seq_int.send_tracked(&mut sink, &seq.monitor, &note_on(60), None, false);
+    seq_int.send_tracked(&mut sink, &seq.monitor, &note_on(61), None, false);
+    drop(seq_int);
+
+    let metrics = seq.metrics();
+    assert_eq!(metrics.bpm, 120.);
+    assert_eq!(metrics.status, SeqStatus::Stop);
+    assert_eq!(metrics.nb_base_seqs, 1);
+    assert_eq!(metrics.nb_events, 4);
+    assert_eq!(metrics.events_sent, 1);
+    assert_eq!(metrics.events_dropped, 1);
+}
+
+#[test]
+fn test_double_kept_on_regen() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, true, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let nb_note_ons = || {
+        let base_seq = seq.get_base_seq(0).unwrap();
+        let nb = base_seq
+            .event_buffer
+            .read()
+            .iter()
+            .filter(|e| e.is_note_on())
+            .count();
+        nb
+    };
+    seq.double(0, 12).unwrap();
+    assert_eq!(nb_note_ons(), 4);
+    seq.regen_base_seq(0).unwrap();
+    assert_eq!(nb_note_ons(), 4);
+}
+
+#[test]
+fn test_dedup() {
+    let seq = Sequencer::new(120.);
+    let note = |on_off, pitch, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let mut events = vec![
+        note(true, 60, 0.),
+        note(true, 60, 0.),
+        note(true, 64, 0.),
+        note(false, 60, 0.5),
+        note(false, 60, 0.5),
+        note(false, 64, 0.5),
+        note(true, 60, 1.),
+        note(false, 60, 1.5),
+    ];
+    events.sort_by_key(Event::sort_key);
+    seq.add_custom_base_seq(
+        BaseSeqParams {
+            ty: Custom,
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        },
+        events,
+    )
+    .unwrap();
+    let notes = |id| -> Vec<(u8, bool, f32)> {
+        seq.get_base_seq(id)
+            .unwrap()
+            .event_buffer
+            .read()
+            .iter()
+            .filter_map(|e| match e.e_type {
+                EventType::MidiNote(n) => Some((n.pitch, n.on_off, e.bar_pos)),
+                EventType::PitchBend(_) | EventType::_Fill => None,
+            })
+            .collect()
+    };
+    seq.dedup(0).unwrap();
+    assert_eq!(
+        notes(0),
+        vec![
+            (60, true, 0.),
+            (64, true, 0.),
+            (60, false, 0.5),
+            (64, false, 0.5),
+            (60, true, 1.),
+            (60, false, 1.5),
+        ]
+    );
+
+    // A doubling colliding with the notes is merged into them
+    seq.double(0, 4).unwrap();
+    assert_eq!(
+        notes(0).iter().filter(|(p, on, _)| *p == 64 && *on).count(),
+        2
+    );
+    assert_eq!(notes(0).len(), 10);
+}
+
+#[test]
+fn test_remove_base_seq_state() {
+    let seq = Sequencer::new(120.);
+    for _ in 0..2 {
+        seq.add_base_seq(BaseSeqParams {
+            ty: Steps(StepsBase {
+                pattern: vec![true, false, true, false],
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        })
+        .unwrap();
+    }
+    seq.add_duck(0, 1, 0.5, 1.).unwrap();
+    seq.add_duck(1, 0, 0.5, 1.).unwrap();
+    seq.route_override(0, 2, 1.).unwrap();
+    seq.stutter(0, 0.25, 2).unwrap();
+    seq.set_loop_rotation_jitter(0, 0.5).unwrap();
+    seq.set_loop_rotation_jitter(1, 0.5).unwrap();
+    seq.internal.write().allocate_mpe_channel(0, (2, 3), 60);
+
+    // Only the state of the other BaseSeq is left
+    seq.remove_base_seq(0).unwrap();
+    let seq_int = seq.internal.read();
+    assert!(seq_int.ducks.is_empty());
+    assert!(seq_int.route_overrides.is_empty());
+    assert!(seq_int.stutters.is_empty());
+    assert_eq!(seq_int.loop_rotations, vec![(1, 0.5)]);
+    assert!(seq_int.mpe_voices.is_empty());
+    assert!(seq_int.mpe_cursors.is_empty());
+}
+
+#[test]
+fn test_scenes() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, true, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.save_scene(3);
+    seq.remove_base_seq(0).unwrap();
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.save_scene(5);
+    let ids = || {
+        seq.base_seqs
+            .read()
+            .iter()
+            .map(|b| b.id)
+            .collect::<Vec<_>>()
+    };
+
+    seq.recall_scene(3, false).unwrap();
+    assert_eq!(ids(), vec![0]);
+    assert_eq!(seq.internal.read().channel_flushes, vec![1]);
+    // The recalled base seqs are copies, the scene is left as saved
+    seq.get_base_seq(0).unwrap().event_buffer.write().clear();
+    seq.recall_scene(3, false).unwrap();
+    assert_eq!(seq.get_base_seq(0).unwrap().event_buffer.read().len(), 4);
+    assert!(seq.recall_scene(4, false).is_err());
+
+    seq.next_scene(false).unwrap();
+    assert_eq!(ids(), vec![1]);
+    seq.next_scene(true).unwrap();
+    assert_eq!(ids(), vec![1]);
+    assert_eq!(seq.internal.read().pending_scene.as_ref().unwrap().index, 3);
+    assert_eq!(seq.params.read().incr, 2);
+}
+
+#[test]
+fn test_base_seq_seed() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Random(RandomBase {
+            nb_events: 8,
+            spacing: Spacing::Uniform,
+            octaves: 2,
+            octave_scales: vec![],
+            markov: None,
+            velocity_dist: VelocityDist::Normal,
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let events = || format!("{:?}", seq.get_base_seq(0).unwrap().event_buffer.read());
+    let seed = seq.base_seq_seed(0).unwrap();
+    let first = events();
+    seq.regen_base_seq(0).unwrap();
+    assert_ne!(seq.base_seq_seed(0).unwrap(), seed);
+    seq.set_base_seq_seed(0, seed).unwrap();
+    assert_eq!(seq.base_seq_seed(0).unwrap(), seed);
+    assert_eq!(events(), first);
+}
+
+#[test]
+fn test_octave_range() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Random(RandomBase {
+            nb_events: 8,
+            spacing: Spacing::Uniform,
+            octaves: 1,
+            octave_scales: vec![],
+            markov: None,
+            velocity_dist: VelocityDist::Normal,
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.set_octave_range(0, MAX_OCTAVES).unwrap();
+    assert!(seq.set_octave_range(0, 0).is_err());
+    assert!(seq.set_octave_range(0, MAX_OCTAVES + 1).is_err());
+}
+
+#[test]
+fn test_velocity_dist() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Random(RandomBase {
+            nb_events: 200,
+            spacing: Spacing::Uniform,
+            octaves: 1,
+            octave_scales: vec![],
+            markov: None,
+            velocity_dist: VelocityDist::Normal,
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 30.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let velocities = || -> Vec<u8> {
+        seq.get_base_seq(0)
+            .unwrap()
+            .event_buffer
+            .read()
+            .iter()
+            .filter_map(|e| match e.e_type {
+                EventType::MidiNote(MidiNote {
+                    on_off: true,
+                    velocity,
+                    ..
+                }) => Some(velocity),
+                _ => None,
+            })
+            .collect()
+    };
+    assert!(velocities().iter().any(|v| !(40..=60).contains(v)));
+
+    seq.set_velocity_dist(0, VelocityDist::Uniform { min: 40, max: 60 })
+        .unwrap();
+    let uniform = velocities();
+    assert_eq!(uniform.len(), 200);
+    assert!(uniform.iter().all(|v| (40..=60).contains(v)));
+    assert!(uniform.contains(&40) && uniform.contains(&60));
+
+    assert!(seq
+        .set_velocity_dist(0, VelocityDist::Uniform { min: 60, max: 40 })
+        .is_err());
+    assert!(seq
+        .set_velocity_dist(0, VelocityDist::Uniform { min: 0, max: 40 })
+        .is_err());
+}
+
+#[test]
+fn test_clear_fx() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 2,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.add_fx_processor(1, FxKind::Jitter).unwrap();
+    seq.clear_fx();
+    assert_eq!(seq.base_seqs.read().len(), 2);
+    assert!(seq.fx_procs.read().is_empty());
+    assert!(seq.get_base_seq(1).unwrap().fx_proc_ids.read().is_empty());
+    assert_eq!(seq.internal.read().channel_flushes, vec![2]);
+}
+
+#[test]
+fn test_combined_cycle() {
+    assert_eq!(
+        combined_cycle_ticks(&[4., 6.]).unwrap(),
+        12 * CYCLE_GRID_DIV
+    );
+    assert_eq!(
+        combined_cycle_ticks(&[1.5, 2.]).unwrap(),
+        6 * CYCLE_GRID_DIV
+    );
+    assert_eq!(combined_cycle_ticks(&[1. / 3.]).unwrap(), 21);
+
+    let seq = Sequencer::new(120.);
+    assert_eq!(seq.combined_cycle().unwrap(), None);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, true],
+        }),
+        loop_length: 1.5,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true],
+        }),
+        loop_length: 0.5,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    assert_eq!(seq.combined_cycle().unwrap(), Some(1.5));
+    assert_eq!(seq.sync_meta_loop().unwrap(), 3);
+    assert_eq!(seq.params.read().meta_loop_bars, 3);
+}
+
+#[test]
+fn test_channel_usage() {
+    let seq = Sequencer::new(120.);
+    assert!(seq.channel_usage().is_empty());
+    for midi_ch in [3, 1, 3] {
+        seq.add_base_seq(BaseSeqParams {
+            ty: Steps(StepsBase {
+                pattern: vec![true],
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        })
+        .unwrap();
+    }
+    assert_eq!(seq.channel_usage(), vec![(1, 1), (3, 2)]);
+}
+
+#[test]
+fn test_allocate_mpe_channel() {
+    let mut seq_int = SeqInternal::new();
+    let voice = |pitch, channel| MpeVoice {
+        base_seq_id: 0,
+        pitch,
+        channel,
+    };
+    assert_eq!(seq_int.allocate_mpe_channel(0, (2, 4), 60), Some((2, None)));
+    assert_eq!(seq_int.allocate_mpe_channel(0, (2, 4), 62), Some((3, None)));
+    assert_eq!(seq_int.allocate_mpe_channel(0, (2, 4), 64), Some((4, None)));
+    // The oldest note gets its channel stolen
+    assert_eq!(
+        seq_int.allocate_mpe_channel(0, (2, 4), 65),
+        Some((2, Some(voice(60, 2))))
+    );
+    // Released channels are reused round robin
+    seq_int.mpe_voices.retain(|v| v.pitch != 64);
+    assert_eq!(seq_int.mpe_channel(0, 62), Some(3));
+    assert_eq!(seq_int.allocate_mpe_channel(0, (2, 4), 67), Some((4, None)));
+    assert_eq!(
+        seq_int.mpe_voices,
+        vec![voice(62, 3), voice(65, 2), voice(67, 4)]
+    );
+}
+
+#[test]
+fn test_cycle_budget() {
+    let seq = Sequencer::new(120.);
+    let mut sink = TinySink {
+        free_slots: 8,
+        written: vec![],
+    };
+    let note_on = |pitch| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off: true,
+            channel: 1,
+            pitch,
+            velocity: 100,
+        }),
+        bar_pos: 0.,
+        duration: None,
+    };
+    let mut seq_int = seq.internal.write();
+    seq_int.cycle_budget = Some(2);
+    for pitch in 60..64 {
+        seq_int.send_tracked(&mut sink, &seq.monitor, &note_on(pitch), None, false);
+    }
+    assert_eq!(sink.written.len(), 2);
+    assert_eq!(seq_int.cycle_budget, Some(0));
+    // The excess is carried over in order, not dropped
+    let pitches = seq_int
+        .deferred_events
+        .iter()
+        .map(|(_, e)| match e.e_type {
+            EventType::MidiNote(n) => n.pitch,
+            _ => unreachable!(),
+        })
+        .collect::<Vec<u8>>();
+    assert_eq!(pitches, vec![62, 63]);
+    assert!(seq_int.dropped_notes.is_empty());
+}
+
+#[test]
+fn test_max_polyphony() {
+    let seq = Sequencer::new(120.);
+    let note = |on_off, pitch, velocity| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch,
+            velocity,
+        }),
+        bar_pos: 0.,
+        duration: None,
+    };
+    let mut sink: Vec<Vec<u8>> = vec![];
+    let mut seq_int = seq.internal.write();
+    seq_int.polyphony_cap = Some(2);
+    for (pitch, velocity) in [(60, 50), (62, 100), (64, 20), (65, 100)] {
+        seq_int.send_tracked(
+            &mut sink,
+            &seq.monitor,
+            &note(true, pitch, velocity),
+            None,
+            false,
+        );
+    }
+    // The note off of a staged note on follows it, the others are sent right away
+    seq_int.send_tracked(&mut sink, &seq.monitor, &note(false, 64, 0), None, false);
+    seq_int.send_tracked(&mut sink, &seq.monitor, &note(false, 67, 0), None, false);
+    assert_eq!(sink, vec![vec![0x80, 67, 0]]);
+    seq_int.send_staged_notes(&mut sink, &seq.monitor, false);
+    // Over the cap, the lowest velocities are dropped
+    assert_eq!(sink[1..], [vec![0x90, 62, 100], vec![0x90, 65, 100]]);
+    assert!(seq_int.staged_notes.is_empty());
+    // The note off of the other dropped note is dropped on arrival
+    seq_int.polyphony_cap = None;
+    seq_int.send_tracked(&mut sink, &seq.monitor, &note(false, 60, 0), None, false);
+    assert_eq!(sink.len(), 3);
+    assert!(seq_int.dropped_notes.is_empty());
+
+    // The staged notes spend the budget once sent, the dropped ones freeing their mpe channel
+    seq_int.polyphony_cap = Some(2);
+    seq_int.cycle_budget = Some(1);
+    let voice = |base_seq_id| MpeVoice {
+        base_seq_id,
+        pitch: 60,
+        channel: 1,
+    };
+    seq_int.mpe_voices = vec![voice(0), voice(1)];
+    for (pitch, velocity) in [(60, 50), (62, 100), (64, 100)] {
+        seq_int.send_tracked(
+            &mut sink,
+            &seq.monitor,
+            &note(true, pitch, velocity),
+            Some(0),
+            false,
+        );
+    }
+    seq_int.send_staged_notes(&mut sink, &seq.monitor, false);
+    assert_eq!(sink[3..], [vec![0x90, 62, 100]]);
+    assert_eq!(seq_int.deferred_events.len(), 1);
+    seq_int.cycle_budget = None;
+    seq_int.send_tracked(&mut sink, &seq.monitor, &note(false, 60, 0), Some(0), false);
+    // The voice of another BaseSeq on the same channel and pitch is kept
+    assert_eq!(seq_int.mpe_voices, vec![voice(1)]);
+}
+
+#[test]
+fn test_phase_lock() {
+    let seq = Sequencer::new(120.);
+    for _ in 0..2 {
+        seq.add_base_seq(BaseSeqParams {
+            ty: Steps(StepsBase {
+                pattern: vec![true, false, true, false],
+            }),
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        })
+        .unwrap();
     }
+    let position = |id| {
+        let time = seq.internal.read().j_window_time_end;
+        let phase = *seq.get_base_seq(id).unwrap().phase.read() as f64;
+        (time - phase).rem_euclid(4.)
+    };
+    seq.internal.write().j_window_time_end = 5.;
+    seq.phase_lock(0, 1, 1.5).unwrap();
+    assert_eq!((position(0), position(1)), (1., 2.5));
+    // Either side of the lock can be seeked
+    seq.seek(0, 3.).unwrap();
+    assert_eq!((position(0), position(1)), (3., 0.5));
+    seq.seek(1, 2.).unwrap();
+    assert_eq!((position(0), position(1)), (0.5, 2.));
+    // Kept through a reset
+    seq.internal.write().j_window_time_end = 0.;
+    seq.reset_base_seqs(&seq.internal.read());
+    assert_eq!((position(0), position(1)), (0., 1.5));
+
+    assert!(seq.phase_unlock(1, 0));
+    seq.seek(0, 1.).unwrap();
+    assert_eq!((position(0), position(1)), (1., 1.5));
+    assert!(seq.phase_lock(0, 0, 1.).is_err());
+}
+
+#[test]
+fn test_trim() {
+    let seq = Sequencer::new(120.);
+    let note = |on_off, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch: 60,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let events = vec![
+        note(true, 2.),
+        note(false, 2.5),
+        note(true, 3.),
+        note(false, 3.25),
+    ];
+    for _ in 0..2 {
+        seq.add_custom_base_seq(
+            BaseSeqParams {
+                ty: Custom,
+                loop_length: 4.,
+                root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+                note_len_avg: 0.1,
+                note_len_div: 0.,
+                velocity_avg: 100,
+                velocity_div: 0.,
+                midi_ch: 1,
+                pitch_pool: None,
+                explicit_durations: false,
+                legato: false,
+                velocity_contour: None,
+                doublings: vec![],
+                gate_ratio: None,
+                vel_len_coupling: 0.,
+                grid_velocity: None,
+                note_off_velocity: None,
+                wrap_note_off: true,
+            },
+            events.clone(),
+        )
+        .unwrap();
+    }
+
+    let positions = |id| {
+        let base_seq = seq.get_base_seq(id).unwrap();
+        let loop_length = base_seq.params.read().loop_length;
+        let positions = base_seq
+            .event_buffer
+            .read()
+            .iter()
+            .map(|e| e.bar_pos)
+            .collect::<Vec<f32>>();
+        (positions, loop_length)
+    };
+    seq.trim(0, true, false).unwrap();
+    assert_eq!(positions(0), (vec![0., 0.5, 1., 1.25], 4.));
+    // The last note off wraps to the loop start, once the loop ends with it
+    seq.trim(1, true, true).unwrap();
+    assert_eq!(positions(1), (vec![0., 0., 0.5, 1.], 1.25));
+    seq.trim(1, true, true).unwrap();
+    assert_eq!(positions(1), (vec![0., 0., 0.5, 1.], 1.25));
+}
+
+#[test]
+fn test_resolution() {
+    let note = |on_off, bar_pos| Event {
+        e_type: EventType::MidiNote(MidiNote {
+            on_off,
+            channel: 1,
+            pitch: 60,
+            velocity: 100,
+        }),
+        bar_pos,
+        duration: None,
+    };
+    let seq = Sequencer::new(120.);
+    seq.add_custom_base_seq(
+        BaseSeqParams {
+            ty: Custom,
+            loop_length: 4.,
+            root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+            note_len_avg: 0.1,
+            note_len_div: 0.,
+            velocity_avg: 100,
+            velocity_div: 0.,
+            midi_ch: 1,
+            pitch_pool: None,
+            explicit_durations: false,
+            legato: false,
+            velocity_contour: None,
+            doublings: vec![],
+            gate_ratio: None,
+            vel_len_coupling: 0.,
+            grid_velocity: None,
+            note_off_velocity: None,
+            wrap_note_off: true,
+        },
+        vec![
+            note(true, 0.3),
+            note(true, 0.5),
+            note(false, 0.504),
+            note(false, 0.61),
+        ],
+    )
+    .unwrap();
+    seq.add_base_seq(BaseSeqParams {
+        ty: Steps(StepsBase {
+            pattern: vec![true, false, true, false],
+        }),
+        loop_length: 4.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.004,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    let events = |id| seq.get_base_seq(id).unwrap().event_buffer.read().clone();
+    let generated = events(1);
+
+    // Both notes stay a tick long on the coarse resolution, their note on first
+    seq.set_resolution(96).unwrap();
+    for id in 0..2 {
+        let events = events(id);
+        assert!(events.iter().all(|e| (e.bar_pos * 96.).fract() < 1e-3));
+        assert!(events[0].is_note_on());
+        let durations = events_to_durations(events, 4.);
+        assert!(durations.iter().all(|e| e.duration >= Some(1. / 96.)));
+    }
+    assert_eq!(events(1)[1].bar_pos, 1. / 96.);
+    // The generated events are regenerated from their seed rather than snapped again
+    seq.set_resolution(1_000).unwrap();
+    assert_eq!(format!("{:?}", events(1)), format!("{generated:?}"));
+    assert_eq!(Sequencer::new(120.).params.read().ticks_per_bar, 1_000);
+
+    assert!(seq.set_resolution(0).is_err());
+}
+
+#[test]
+fn test_euclid_complement() {
+    let seq = Sequencer::new(120.);
+    seq.add_base_seq(BaseSeqParams {
+        ty: Euclid(EuclidBase {
+            pulses: 3,
+            steps: 8,
+        }),
+        loop_length: 8.,
+        root_note: crate::midi::midi_pitch_to_note(60).unwrap(),
+        note_len_avg: 0.1,
+        note_len_div: 0.,
+        velocity_avg: 100,
+        velocity_div: 0.,
+        midi_ch: 1,
+        pitch_pool: None,
+        explicit_durations: false,
+        legato: false,
+        velocity_contour: None,
+        doublings: vec![],
+        gate_ratio: None,
+        vel_len_coupling: 0.,
+        grid_velocity: None,
+        note_off_velocity: None,
+        wrap_note_off: true,
+    })
+    .unwrap();
+    seq.add_euclid_complement(0, 2).unwrap();
+    assert!(seq.add_euclid_complement(1, 2).is_err());
+
+    let mut on_steps = seq
+        .base_seqs
+        .read()
+        .iter()
+        .flat_map(|b| {
+            b.event_buffer
+                .read()
+                .iter()
+                .filter(|e| e.is_note_on())
+                .map(|e| e.bar_pos as u32)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    on_steps.sort();
+    assert_eq!(on_steps, (0..8).collect::<Vec<_>>());
 }